@@ -0,0 +1,273 @@
+use crate::api_key_repository::ApiKeyRepository;
+use crate::application_error::{AppError, AppResult};
+use crate::user_profile_access_policy::Permission;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, HttpMessage};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use ring::constant_time::verify_slices_are_equal;
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+use std::rc::Rc;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Header carrying ***ysk_<key_id>_<secret>***
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+const API_KEY_PREFIX: &str = "ysk_";
+
+/// A verified API key, ***ApiKeyAuthentication*** stores it on the request in place of JWT claims
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKeyPrincipal {
+    pub key_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<Permission>,
+}
+
+impl ApiKeyPrincipal {
+    /// Never a user_id, so an API key cannot pass as the owner of a profile
+    pub fn subject(&self) -> String {
+        format!("api_key:{}", self.key_id)
+    }
+}
+
+/// A new key, ***api_key*** is only ever shown in the answer that issues it
+pub struct GeneratedApiKey {
+    pub key_id: Uuid,
+    pub api_key: String,
+    pub key_hash: String,
+}
+
+/// 32 random bytes from the OS, the key_id in front lets verification read a single row
+pub fn generate_api_key() -> AppResult<GeneratedApiKey> {
+    let mut secret = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut secret)
+        .map_err(|_| AppError::Internal { detail: "the system random generator failed".to_string() })?;
+    let secret = base64::encode_config(secret, base64::URL_SAFE_NO_PAD);
+    let key_id = Uuid::new_v4();
+    Ok(GeneratedApiKey {
+        key_id,
+        api_key: format!("{}{}_{}", API_KEY_PREFIX, key_id.to_simple(), secret),
+        key_hash: hash_secret(&secret),
+    })
+}
+
+/// A fast hash is enough, the secret is 256 random bits and not a password someone could guess
+fn hash_secret(secret: &str) -> String {
+    digest(&SHA256, secret.as_bytes()).as_ref().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_api_key(api_key: &str) -> Option<(Uuid, &str)> {
+    let (key_id, secret) = api_key.strip_prefix(API_KEY_PREFIX)?.split_once('_')?;
+    Some((Uuid::parse_str(key_id).ok()?, secret))
+}
+
+/// Scopes a new key may carry. Managing roles, keys and passwords stays with people, a leaked key cannot mint others
+/// or take over accounts
+pub fn parse_scopes(scopes: &[String]) -> AppResult<Vec<Permission>> {
+    let mut permissions = Vec::with_capacity(scopes.len());
+    for scope in scopes {
+        match Permission::parse(scope) {
+            Some(Permission::ManageRoles) | Some(Permission::ManageApiKeys) | Some(Permission::ManageCredentials) => {
+                return Err(AppError::bad_request(&format!("API keys cannot be granted {}", scope)))
+            }
+            Some(permission) if !permissions.contains(&permission) => permissions.push(permission),
+            Some(_) => {}
+            None => return Err(AppError::bad_request(&format!("{} is not a known permission", scope))),
+        }
+    }
+    Ok(permissions)
+}
+
+/// Looks the key up by its key_id and compares the secret hash in constant time
+pub struct ApiKeyVerifier {
+    api_keys: Arc<dyn ApiKeyRepository>,
+}
+
+impl ApiKeyVerifier {
+    pub fn new(api_keys: Arc<dyn ApiKeyRepository>) -> ApiKeyVerifier {
+        ApiKeyVerifier { api_keys }
+    }
+
+    /// 401 for malformed, unknown, revoked or expired keys, YCQL failures keep their own status
+    pub async fn verify(&self, api_key: &str) -> AppResult<ApiKeyPrincipal> {
+        let (key_id, secret) = parse_api_key(api_key).ok_or_else(|| AppError::denied("malformed X-Api-Key"))?;
+        let row = self
+            .api_keys
+            .read_api_key(key_id)
+            .await?
+            .ok_or_else(|| AppError::denied(&format!("API key {} is unknown, revoked or expired", key_id)))?;
+        verify_slices_are_equal(hash_secret(secret).as_bytes(), row.key_hash.as_bytes())
+            .map_err(|_| AppError::denied(&format!("wrong secret for API key {}", key_id)))?;
+        let scopes = row
+            .scopes
+            .iter()
+            .filter_map(|scope| {
+                let permission = Permission::parse(scope);
+                if permission.is_none() {
+                    log::warn!("API key {} holds unknown scope {}, ignored", key_id, scope);
+                }
+                permission
+            })
+            .collect();
+        Ok(ApiKeyPrincipal { key_id, name: row.name, scopes })
+    }
+}
+
+/// Middleware for the ***/api_v1*** scope, wrapped outside JwtAuthentication.
+/// A request with an ***X-Api-Key*** header is authenticated by the key alone, one without it goes on to the bearer token check
+#[derive(Clone)]
+pub struct ApiKeyAuthentication {
+    verifier: Arc<ApiKeyVerifier>,
+}
+
+impl ApiKeyAuthentication {
+    pub fn new(verifier: Arc<ApiKeyVerifier>) -> ApiKeyAuthentication {
+        ApiKeyAuthentication { verifier }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ApiKeyAuthentication
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = ApiKeyAuthenticationMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ApiKeyAuthenticationMiddleware {
+            service: Rc::new(service),
+            verifier: self.verifier.clone(),
+        }))
+    }
+}
+
+pub struct ApiKeyAuthenticationMiddleware<S> {
+    service: Rc<S>,
+    verifier: Arc<ApiKeyVerifier>,
+}
+
+impl<S, B> Service<ServiceRequest> for ApiKeyAuthenticationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<ServiceResponse<B>, Error>>;
+
+    actix_service::forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let api_key = match request.headers().get(API_KEY_HEADER) {
+            None => return Box::pin(self.service.call(request)),
+            Some(api_key) => api_key.to_str().map(str::to_string),
+        };
+        let service = self.service.clone();
+        let verifier = self.verifier.clone();
+        Box::pin(async move {
+            let principal = match api_key {
+                Ok(api_key) => verifier.verify(&api_key).await,
+                Err(_) => Err(AppError::denied("X-Api-Key is not visible ASCII")),
+            };
+            match principal {
+                Ok(principal) => {
+                    request.extensions_mut().insert(principal);
+                    service.call(request).await
+                }
+                Err(error) => Ok(request.error_response(error)),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_key_repository::InMemoryApiKeyRepository;
+    use crate::jwt_bearer_authentication::{JwtAuthentication, JwtVerifier};
+    use crate::user_profile_access_policy::{AccessControl, AuthorizedCaller};
+    use crate::ycql_db_schema_and_queries::ApiKeyRowStruct;
+    use crate::ycql_service_config::{JwtConfig, JwtKeyConfig};
+    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+
+    async fn list_profiles(caller: AuthorizedCaller) -> Result<HttpResponse, AppError> {
+        caller.require(Permission::ListProfiles, None).await?;
+        Ok(HttpResponse::Ok().body(caller.subject()))
+    }
+
+    async fn issue(api_keys: &InMemoryApiKeyRepository, scopes: &[&str]) -> GeneratedApiKey {
+        let generated = generate_api_key().unwrap();
+        api_keys
+            .store_api_key(ApiKeyRowStruct {
+                key_id: generated.key_id,
+                key_hash: generated.key_hash.clone(),
+                name: "nightly export".to_string(),
+                scopes: scopes.iter().map(ToString::to_string).collect(),
+                created_by: "ops@demo.com".to_string(),
+                ttl_secs: None,
+            })
+            .await
+            .unwrap();
+        generated
+    }
+
+    #[actix_rt::test]
+    async fn api_keys_stand_in_for_bearer_tokens_with_their_scopes() {
+        let api_keys = Arc::new(InMemoryApiKeyRepository::default());
+        let lister = issue(&api_keys, &["profile:list"]).await;
+        let reader = issue(&api_keys, &["profile:read"]).await;
+        let jwt_config = JwtConfig {
+            key: JwtKeyConfig::Hs256 { secret: "an HS256 test secret of 32+ bytes".to_string() },
+            ..JwtConfig::default()
+        };
+        let app = test::init_service(
+            App::new().app_data(AccessControl::for_tests()).service(
+                web::scope("/api_v1")
+                    .wrap(JwtAuthentication::new(Arc::new(JwtVerifier::from_config(&jwt_config).unwrap())))
+                    .wrap(ApiKeyAuthentication::new(Arc::new(ApiKeyVerifier::new(api_keys.clone()))))
+                    .route("/list_user_profiles", web::post().to(list_profiles)),
+            ),
+        )
+        .await;
+        let call = |api_key: Option<String>| {
+            let mut request = test::TestRequest::post().uri("/api_v1/list_user_profiles");
+            if let Some(api_key) = api_key {
+                request = request.insert_header((API_KEY_HEADER, api_key));
+            }
+            test::call_service(&app, request.to_request())
+        };
+
+        let response = call(Some(lister.api_key.clone())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(test::read_body(response).await, format!("api_key:{}", lister.key_id));
+        assert_eq!(call(Some(reader.api_key.clone())).await.status(), StatusCode::FORBIDDEN);
+
+        let forged = format!("{}x", lister.api_key);
+        assert_eq!(call(Some(forged)).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(call(Some("ysk_not-a-key".to_string())).await.status(), StatusCode::UNAUTHORIZED);
+        // no key falls through to the bearer token check
+        assert_eq!(call(None).await.status(), StatusCode::UNAUTHORIZED);
+
+        api_keys.delete_api_key(lister.key_id).await.unwrap();
+        assert_eq!(call(Some(lister.api_key)).await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn scopes_must_be_known_and_cannot_manage_access() {
+        assert_eq!(
+            parse_scopes(&["profile:read".to_string(), "profile:read".to_string(), "audit:read".to_string()]).unwrap(),
+            vec![Permission::ReadProfile, Permission::ReadAudit]
+        );
+        assert!(parse_scopes(&["roles:manage".to_string()]).is_err());
+        assert!(parse_scopes(&["api_keys:manage".to_string()]).is_err());
+        assert!(parse_scopes(&["credentials:manage".to_string()]).is_err());
+        assert!(parse_scopes(&["profile:everything".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,288 @@
+use crate::api_key_authentication::{generate_api_key, parse_scopes, GeneratedApiKey};
+use crate::api_key_repository::ApiKeyRepository;
+use crate::application_error::AppError;
+use crate::crud_controller::written_response;
+use crate::default_strings::CustomStatusMessage;
+use crate::generic_json_success_response::GenericJsonSuccessResponse;
+use crate::user_profile_access_policy::{AuthorizedCaller, Permission};
+use crate::validated_json_extractor::ValidatedJson;
+use crate::ycql_db_schema_and_queries::{ApiKeyRowStruct, MAX_TTL_SECS};
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Longest overlap a rotation may keep the old key working for
+const MAX_ROTATION_GRACE_SECS: i32 = 86_400;
+
+/// API key administration, mounted inside the protected ***/api_v1*** scope, every route needs api_keys:manage
+pub fn api_key_routes<R: ApiKeyRepository>(cfg: &mut web::ServiceConfig) {
+    cfg.route("/issue_api_key", web::post().to(issue_api_key::<R>))
+        .route("/rotate_api_key", web::post().to(rotate_api_key::<R>))
+        .route("/revoke_api_key", web::post().to(revoke_api_key::<R>))
+        .route("/list_api_keys", web::post().to(list_api_keys::<R>));
+}
+
+fn success_response<T: Serialize>(message: &str, data: T) -> web::HttpResponse {
+    web::HttpResponse::Ok()
+    .content_type("application/json")
+    .body(serde_json::to_string(
+        &GenericJsonSuccessResponse::<T>::generic_success_response(CustomStatusMessage::USER_REQUEST_SUCCESS.to_string(), message.to_string(), 200, Some(data))
+    ).unwrap())
+}
+
+///Json Request Struct for |issue_api_key| FUNCTION
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct IssueApiKeyJsonREQUEST {
+    /// What the key is for, eg ***nightly export***
+    #[validate(length(min = 1, max = 100, message = "must be between 1 and 100 characters"))]
+    name: String,
+    /// Permissions like ***profile:read***, roles:manage and api_keys:manage are refused
+    #[validate(length(min = 1, max = 8, message = "must hold between 1 and 8 scopes"))]
+    scopes: Vec<String>,
+    /// Seconds until the key expires, leave out for a key that lives until it is revoked
+    #[validate(range(min = 1, max = "MAX_TTL_SECS", message = "must be between 1 and 630720000 seconds"))]
+    ttl_secs: Option<i32>,
+}
+
+/// The only answer that ever carries ***api_key***, store it right away
+#[derive(Debug, Serialize)]
+pub struct IssuedApiKeyJsonRESPONSE {
+    api_key: String,
+    #[serde(flatten)]
+    key: ApiKeyRowStruct,
+}
+
+async fn store_generated_key<R: ApiKeyRepository>(
+    api_key_repository: &R,
+    generated: GeneratedApiKey,
+    name: String,
+    scopes: Vec<String>,
+    created_by: String,
+    ttl_secs: Option<i32>
+)-> Result<IssuedApiKeyJsonRESPONSE, AppError>{
+
+    let key = ApiKeyRowStruct { key_id: generated.key_id, key_hash: generated.key_hash, name, scopes, created_by, ttl_secs };
+    api_key_repository.store_api_key(key.clone()).await?;
+    Ok(IssuedApiKeyJsonRESPONSE { api_key: generated.api_key, key })
+}
+
+pub async fn issue_api_key<R: ApiKeyRepository>(
+    caller: AuthorizedCaller,
+    api_key_repository: web::Data<R>,
+    get_key: ValidatedJson<IssueApiKeyJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    caller.require(Permission::ManageApiKeys, None).await?;
+    let scopes = parse_scopes(&get_key.scopes)?.iter().map(ToString::to_string).collect();
+
+    let issued = store_generated_key(
+        api_key_repository.get_ref(),
+        generate_api_key()?,
+        get_key.name.to_string(),
+        scopes,
+        caller.subject(),
+        get_key.ttl_secs,
+    ).await?;
+    log::info!("API key issued: key_id={} name={} by={}", issued.key.key_id, issued.key.name, caller.subject());
+
+    Ok(success_response("API key issued, it is not shown again", issued))
+}
+
+///Json Request Struct for |rotate_api_key| FUNCTION
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct RotateApiKeyJsonREQUEST {
+    key_id: Uuid,
+    /// Seconds the old key keeps working so callers can switch over, leave out to revoke it at once
+    #[validate(range(min = 0, max = "MAX_ROTATION_GRACE_SECS", message = "must be between 0 and 86400 seconds"))]
+    grace_secs: Option<i32>,
+}
+
+/// A new secret with the old key's name, scopes and remaining TTL, under a new key_id
+pub async fn rotate_api_key<R: ApiKeyRepository>(
+    caller: AuthorizedCaller,
+    api_key_repository: web::Data<R>,
+    get_rotation: ValidatedJson<RotateApiKeyJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    caller.require(Permission::ManageApiKeys, None).await?;
+    let old_key = api_key_repository
+        .read_api_key(get_rotation.key_id)
+        .await?
+        // a key in its last second reads ***TTL 0***, which copied into ***USING TTL*** would never expire
+        .filter(|old_key| old_key.ttl_secs != Some(0))
+        .ok_or_else(|| AppError::not_found("No API key with this key_id, it may be revoked or expired"))?;
+
+    let issued = store_generated_key(
+        api_key_repository.get_ref(),
+        generate_api_key()?,
+        old_key.name.clone(),
+        old_key.scopes.clone(),
+        caller.subject(),
+        old_key.ttl_secs,
+    ).await?;
+
+    match get_rotation.grace_secs.filter(|grace_secs| *grace_secs > 0) {
+        // rewriting the row with a shorter TTL lets YCQL expire it, never extend a key that expires sooner
+        Some(grace_secs) => {
+            let ttl_secs = old_key.ttl_secs.map_or(grace_secs, |ttl_secs| ttl_secs.min(grace_secs));
+            api_key_repository.store_api_key(ApiKeyRowStruct { ttl_secs: Some(ttl_secs.max(1)), ..old_key }).await?
+        }
+        None => api_key_repository.delete_api_key(old_key.key_id).await?,
+    }
+    log::info!("API key rotated: key_id={} replaced_by={} by={}", get_rotation.key_id, issued.key.key_id, caller.subject());
+
+    Ok(success_response("API key rotated, it is not shown again", issued))
+}
+
+///Json Request Struct for |revoke_api_key| FUNCTION
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct RevokeApiKeyJsonREQUEST {
+    key_id: Uuid,
+}
+
+/// Deletes the row, the next request with the key answers 401
+pub async fn revoke_api_key<R: ApiKeyRepository>(
+    caller: AuthorizedCaller,
+    api_key_repository: web::Data<R>,
+    get_key: ValidatedJson<RevokeApiKeyJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    caller.require(Permission::ManageApiKeys, None).await?;
+    if api_key_repository.read_api_key(get_key.key_id).await?.is_none() {
+        return Err(AppError::not_found("No API key with this key_id, it may be revoked or expired"));
+    }
+    api_key_repository.delete_api_key(get_key.key_id).await?;
+    log::info!("API key revoked: key_id={} by={}", get_key.key_id, caller.subject());
+
+    Ok(written_response("Successfuly revoked API key", None))
+}
+
+/// Every live key without its hash
+pub async fn list_api_keys<R: ApiKeyRepository>(
+    caller: AuthorizedCaller,
+    api_key_repository: web::Data<R>
+)-> Result<web::HttpResponse, AppError>{
+
+    caller.require(Permission::ManageApiKeys, None).await?;
+    let api_keys = api_key_repository.list_api_keys().await?;
+
+    Ok(success_response("success", api_keys))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler_test_app::{app_as, ops_admin};
+    use crate::api_key_authentication::ApiKeyVerifier;
+    use crate::api_key_repository::InMemoryApiKeyRepository;
+    use crate::jwt_bearer_authentication::JwtClaims;
+    use crate::user_profile_access_policy::SUPPORT_ROLE;
+    use actix_web::{http::StatusCode, test};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    macro_rules! in_memory_api_key_app {
+        ($api_keys:expr, $claims:expr) => {
+            test::init_service(
+                app_as($claims)
+                    .app_data(web::Data::from($api_keys.clone()))
+                    .service(web::scope("/api_v1").configure(api_key_routes::<InMemoryApiKeyRepository>)),
+            )
+            .await
+        };
+    }
+
+    #[actix_rt::test]
+    async fn keys_are_issued_rotated_and_revoked_by_admins_only() {
+        let api_keys = Arc::new(InMemoryApiKeyRepository::default());
+        let verifier = ApiKeyVerifier::new(api_keys.clone());
+        let admin_app = in_memory_api_key_app!(api_keys, ops_admin());
+        let support_app = in_memory_api_key_app!(api_keys, JwtClaims::for_tests("support@demo.com", &[SUPPORT_ROLE]));
+
+        let issue = json!({"name": "nightly export", "scopes": ["profile:list", "profile:read"], "ttl_secs": 3600});
+        let request = test::TestRequest::post().uri("/api_v1/issue_api_key").set_json(&issue).to_request();
+        assert_eq!(test::call_service(&support_app, request).await.status(), StatusCode::FORBIDDEN);
+        let request = test::TestRequest::post()
+            .uri("/api_v1/issue_api_key")
+            .set_json(&json!({"name": "escalate", "scopes": ["roles:manage"]}))
+            .to_request();
+        assert_eq!(test::call_service(&admin_app, request).await.status(), StatusCode::BAD_REQUEST);
+
+        let request = test::TestRequest::post().uri("/api_v1/issue_api_key").set_json(&issue).to_request();
+        let body: Value = test::read_response_json(&admin_app, request).await;
+        let first_key = body["data"]["api_key"].as_str().unwrap().to_string();
+        let first_key_id = body["data"]["key_id"].clone();
+        assert_eq!(body["data"]["created_by"], json!("ops@demo.com"));
+        assert!(body["data"].get("key_hash").is_none());
+        assert_eq!(verifier.verify(&first_key).await.unwrap().scopes, vec![Permission::ListProfiles, Permission::ReadProfile]);
+
+        // the old key keeps working during the grace period
+        let request = test::TestRequest::post()
+            .uri("/api_v1/rotate_api_key")
+            .set_json(&json!({"key_id": first_key_id, "grace_secs": 60}))
+            .to_request();
+        let body: Value = test::read_response_json(&admin_app, request).await;
+        let second_key = body["data"]["api_key"].as_str().unwrap().to_string();
+        let second_key_id = body["data"]["key_id"].clone();
+        assert_ne!(second_key_id, first_key_id);
+        assert!(body["data"]["ttl_secs"].as_i64().unwrap() > 3500);
+        assert!(verifier.verify(&first_key).await.is_ok());
+        let old_ttl = api_keys.read_api_key(serde_json::from_value(first_key_id.clone()).unwrap()).await.unwrap().unwrap().ttl_secs;
+        assert!(old_ttl.unwrap() <= 60);
+
+        let request = test::TestRequest::post()
+            .uri("/api_v1/rotate_api_key")
+            .set_json(&json!({"key_id": second_key_id}))
+            .to_request();
+        let body: Value = test::read_response_json(&admin_app, request).await;
+        let third_key = body["data"]["api_key"].as_str().unwrap().to_string();
+        assert!(verifier.verify(&second_key).await.is_err());
+
+        let request = test::TestRequest::post().uri("/api_v1/list_api_keys").to_request();
+        let body: Value = test::read_response_json(&admin_app, request).await;
+        assert_eq!(body["data"].as_array().unwrap().len(), 2);
+
+        let request = test::TestRequest::post()
+            .uri("/api_v1/revoke_api_key")
+            .set_json(&json!({"key_id": body["data"][0]["key_id"]}))
+            .to_request();
+        assert_eq!(test::call_service(&admin_app, request).await.status(), StatusCode::OK);
+        let request = test::TestRequest::post()
+            .uri("/api_v1/revoke_api_key")
+            .set_json(&json!({"key_id": body["data"][0]["key_id"]}))
+            .to_request();
+        assert_eq!(test::call_service(&admin_app, request).await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            verifier.verify(&first_key).await.is_ok() as u8 + verifier.verify(&third_key).await.is_ok() as u8,
+            1
+        );
+    }
+
+    #[actix_rt::test]
+    async fn a_key_in_its_last_second_is_not_rotated() {
+        let api_keys = Arc::new(InMemoryApiKeyRepository::default());
+        let app = in_memory_api_key_app!(api_keys, ops_admin());
+        let key_id = Uuid::new_v4();
+        api_keys
+            .store_api_key(ApiKeyRowStruct {
+                key_id,
+                key_hash: "0".repeat(64),
+                name: "expiring".to_string(),
+                scopes: vec!["profile:read".to_string()],
+                created_by: "ops@demo.com".to_string(),
+                ttl_secs: Some(1),
+            })
+            .await
+            .unwrap();
+        actix_rt::time::sleep(std::time::Duration::from_millis(5)).await;
+        assert_eq!(api_keys.read_api_key(key_id).await.unwrap().unwrap().ttl_secs, Some(0));
+
+        let request = test::TestRequest::post()
+            .uri("/api_v1/rotate_api_key")
+            .set_json(&json!({"key_id": key_id}))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(api_keys.list_api_keys().await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::application_error::AppResult;
+use crate::ycql_db_schema_and_queries::{delete_api_key_query, insert_api_key_query, select_api_key_query, select_api_keys_query, ApiKeyRowStruct};
+use crate::yugabyte_db_ycql_config::CurrentYcqlDbSession;
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Storage of the hashed ***api_keys***, implemented for YCQL and for in-memory tests
+#[async_trait]
+pub trait ApiKeyRepository: Send + Sync + 'static {
+    /// Writes the row, replacing a key with the same key_id, ***ttl_secs*** sets when it expires
+    async fn store_api_key(&self, api_key_row: ApiKeyRowStruct) -> AppResult<()>;
+    /// None once the key was revoked or expired
+    async fn read_api_key(&self, key_id: Uuid) -> AppResult<Option<ApiKeyRowStruct>>;
+    async fn list_api_keys(&self) -> AppResult<Vec<ApiKeyRowStruct>>;
+    async fn delete_api_key(&self, key_id: Uuid) -> AppResult<()>;
+}
+
+/// ApiKeyRepository backed by the YCQL query functions
+#[derive(Clone)]
+pub struct YcqlApiKeyRepository {
+    ycql_session: CurrentYcqlDbSession,
+}
+
+impl YcqlApiKeyRepository {
+    pub fn new(ycql_session: CurrentYcqlDbSession) -> YcqlApiKeyRepository {
+        YcqlApiKeyRepository { ycql_session }
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for YcqlApiKeyRepository {
+    async fn store_api_key(&self, api_key_row: ApiKeyRowStruct) -> AppResult<()> {
+        Ok(insert_api_key_query(&self.ycql_session, api_key_row).await?)
+    }
+
+    async fn read_api_key(&self, key_id: Uuid) -> AppResult<Option<ApiKeyRowStruct>> {
+        Ok(select_api_key_query(&self.ycql_session, key_id).await?)
+    }
+
+    async fn list_api_keys(&self) -> AppResult<Vec<ApiKeyRowStruct>> {
+        Ok(select_api_keys_query(&self.ycql_session).await?)
+    }
+
+    async fn delete_api_key(&self, key_id: Uuid) -> AppResult<()> {
+        Ok(delete_api_key_query(&self.ycql_session, key_id).await?)
+    }
+}
+
+/// ApiKeyRepository kept in process memory, rows carry the deadline their ***ttl_secs*** sets
+#[derive(Default)]
+pub struct InMemoryApiKeyRepository {
+    api_keys: Mutex<BTreeMap<Uuid, (ApiKeyRowStruct, Option<Instant>)>>,
+}
+
+impl InMemoryApiKeyRepository {
+    /// Expired keys are dropped first, like YCQL never returning them
+    fn api_keys(&self) -> std::sync::MutexGuard<'_, BTreeMap<Uuid, (ApiKeyRowStruct, Option<Instant>)>> {
+        let mut api_keys = self.api_keys.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+        api_keys.retain(|_, (_, expires_at)| expires_at.is_none_or(|expires_at| expires_at > now));
+        api_keys
+    }
+
+    /// The row as a read returns it, ***ttl_secs*** counting down like ***TTL(key_hash)***
+    fn read((row, expires_at): &(ApiKeyRowStruct, Option<Instant>)) -> ApiKeyRowStruct {
+        let mut row = row.clone();
+        row.ttl_secs = expires_at.map(|expires_at| expires_at.saturating_duration_since(Instant::now()).as_secs() as i32);
+        row
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for InMemoryApiKeyRepository {
+    async fn store_api_key(&self, api_key_row: ApiKeyRowStruct) -> AppResult<()> {
+        let expires_at = api_key_row
+            .ttl_secs
+            .filter(|ttl_secs| *ttl_secs > 0)
+            .map(|ttl_secs| Instant::now() + Duration::from_secs(ttl_secs as u64));
+        self.api_keys().insert(api_key_row.key_id, (api_key_row, expires_at));
+        Ok(())
+    }
+
+    async fn read_api_key(&self, key_id: Uuid) -> AppResult<Option<ApiKeyRowStruct>> {
+        Ok(self.api_keys().get(&key_id).map(Self::read))
+    }
+
+    async fn list_api_keys(&self) -> AppResult<Vec<ApiKeyRowStruct>> {
+        Ok(self.api_keys().values().map(Self::read).collect())
+    }
+
+    async fn delete_api_key(&self, key_id: Uuid) -> AppResult<()> {
+        self.api_keys().remove(&key_id);
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use crate::default_strings::{CustomStatusMessage, UserErrorMessages};
+use crate::generic_json_error_response::GenericJsonErrorResponse;
+use crate::validated_json_extractor::validation_error_response;
+use crate::ycql_db_schema_and_queries::is_unique_email_violation;
+use actix_web::{http::{header, StatusCode}, web, ResponseError};
+use cdrs_tokio::error::Error as YcqlError;
+use cdrs_tokio::frame::frame_error::AdditionalErrorInfo;
+use derive_more::Display;
+use serde_json::json;
+use std::io::ErrorKind;
+use validator::ValidationErrors;
+
+pub type AppResult<T> = Result<T, AppError>;
+
+/// Every way a request can fail.
+/// ***Display*** carries the internal detail for the logs, clients only ever see the masked ***UserErrorMessages***.
+#[derive(Debug, Display)]
+pub enum AppError {
+    /// 400, ***reason*** is about the client input and safe to return
+    #[display(fmt = "bad request: {}", reason)]
+    BadRequest { reason: String },
+    /// 400 with every failing ***#[validate]*** rule
+    #[display(fmt = "validation failed: {}", _0)]
+    Validation(ValidationErrors),
+    /// 401, no bearer token or one that failed verification, ***reason*** is logged as a warning and never sent to the client
+    #[display(fmt = "access denied: {}", reason)]
+    Denied { reason: String },
+    /// 403, a verified caller without the permission the operation needs, ***AuthorizedCaller::require*** logs it
+    #[display(fmt = "forbidden: {}", reason)]
+    Forbidden { reason: String },
+    /// 404
+    #[display(fmt = "not found: {}", reason)]
+    NotFound { reason: String },
+    /// 409
+    #[display(fmt = "conflict: {}", reason)]
+    Conflict { reason: String },
+    /// 409, a conditional write was rejected, ***current*** is the stored row returned to the client
+    #[display(fmt = "conditional write not applied: {}", reason)]
+    NotApplied { reason: String, current: Option<serde_json::Value> },
+    /// 412, ***If-Match*** named a version the row no longer has, ***current*** is the stored row
+    #[display(fmt = "precondition failed: {}", reason)]
+    PreconditionFailed { reason: String, current: Option<serde_json::Value> },
+    /// 503, the cluster cannot take the request right now
+    #[display(fmt = "YCQL cluster unavailable: {}", detail)]
+    Unavailable { detail: String },
+    /// 504, the cluster did not answer in time
+    #[display(fmt = "YCQL request timed out: {}", detail)]
+    Timeout { detail: String },
+    /// 500, anything else
+    #[display(fmt = "internal error: {}", detail)]
+    Internal { detail: String },
+}
+
+impl AppError {
+    pub fn bad_request(reason: &str) -> AppError {
+        AppError::BadRequest { reason: reason.to_string() }
+    }
+
+    pub fn denied(reason: &str) -> AppError {
+        AppError::Denied { reason: reason.to_string() }
+    }
+
+    pub fn forbidden(reason: &str) -> AppError {
+        AppError::Forbidden { reason: reason.to_string() }
+    }
+
+    pub fn not_found(reason: &str) -> AppError {
+        AppError::NotFound { reason: reason.to_string() }
+    }
+
+    /// The ***user_profile_by_email*** unique index is the only uniqueness rule, so every conflict is about the email
+    pub fn email_already_registered() -> AppError {
+        AppError::Conflict { reason: "A user profile with this email address already exists".to_string() }
+    }
+
+    /// A rejected conditional write, the current row is included so the client can retry from it.
+    /// For YCQL it is read after the write, see ***ConditionalWriteOutcome::NotApplied***
+    pub fn not_applied<T: serde::Serialize>(reason: &str, current: Option<&T>) -> AppError {
+        AppError::NotApplied {
+            reason: reason.to_string(),
+            current: current.and_then(|row| serde_json::to_value(row).ok()),
+        }
+    }
+
+    /// A write carrying a stale ***If-Match*** version
+    pub fn precondition_failed<T: serde::Serialize>(reason: &str, current: Option<&T>) -> AppError {
+        AppError::PreconditionFailed {
+            reason: reason.to_string(),
+            current: current.and_then(|row| serde_json::to_value(row).ok()),
+        }
+    }
+
+    /// Writes the detail ***client_message*** masks: server errors as errors, 401 reasons as warnings.
+    /// Call it wherever an error is answered without going through ***error_response***
+    pub(crate) fn log_detail(&self) {
+        if self.status_code().is_server_error() {
+            log::error!("{}", self);
+        } else if let AppError::Denied { .. } = self {
+            // the middlewares and login routes answer straight from error_response, this is the only trace of why
+            log::warn!("{}", self);
+        }
+    }
+
+    pub(crate) fn client_message(&self) -> String {
+        match self {
+            AppError::BadRequest { reason }
+            | AppError::NotFound { reason }
+            | AppError::Conflict { reason }
+            | AppError::NotApplied { reason, .. }
+            | AppError::PreconditionFailed { reason, .. } => {
+                UserErrorMessages::InputError { reason: reason.to_string() }.to_string()
+            }
+            AppError::Validation(_) => UserErrorMessages::BadClientData.to_string(),
+            AppError::Denied { .. } => UserErrorMessages::JwtAccessTokenError.to_string(),
+            AppError::Forbidden { .. } => UserErrorMessages::AccessForbidden.to_string(),
+            AppError::Unavailable { .. } => UserErrorMessages::ServiceUnavailable.to_string(),
+            AppError::Timeout { .. } => UserErrorMessages::Timeout.to_string(),
+            AppError::Internal { .. } => UserErrorMessages::InternalServerError.to_string(),
+        }
+    }
+}
+
+impl From<YcqlError> for AppError {
+    fn from(error: YcqlError) -> AppError {
+        if is_unique_email_violation(&error) {
+            return AppError::email_already_registered();
+        }
+        let detail = error.to_string();
+        match error {
+            YcqlError::Server(server_error) => match server_error.additional_info {
+                AdditionalErrorInfo::Unavailable(_)
+                | AdditionalErrorInfo::Overloaded(_)
+                | AdditionalErrorInfo::IsBootstrapping(_) => AppError::Unavailable { detail },
+                AdditionalErrorInfo::ReadTimeout(_) | AdditionalErrorInfo::WriteTimeout(_) => AppError::Timeout { detail },
+                _ => AppError::Internal { detail },
+            },
+            YcqlError::Io(io_error) if io_error.kind() == ErrorKind::TimedOut => AppError::Timeout { detail },
+            YcqlError::Io(_) => AppError::Unavailable { detail },
+            // raised by the driver when no node in the pool can be reached
+            YcqlError::General(ref message) if message.contains("Unable to get transport") => AppError::Unavailable { detail },
+            _ => AppError::Internal { detail },
+        }
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(errors: ValidationErrors) -> AppError {
+        AppError::Validation(errors)
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest { .. } | AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::Denied { .. } => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden { .. } => StatusCode::FORBIDDEN,
+            AppError::NotFound { .. } => StatusCode::NOT_FOUND,
+            AppError::Conflict { .. } | AppError::NotApplied { .. } => StatusCode::CONFLICT,
+            AppError::PreconditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
+            AppError::Unavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Timeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            AppError::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> web::HttpResponse {
+        self.log_detail();
+        if let AppError::Validation(errors) = self {
+            return validation_error_response(errors.clone()).error_response();
+        }
+        if let AppError::Denied { .. } = self {
+            return web::HttpResponse::build(self.status_code())
+                .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                .json(json!({
+                    "custom_status": CustomStatusMessage::USER_REQUEST_DENIED,
+                    "message": self.client_message(),
+                }));
+        }
+        if let AppError::Forbidden { .. } = self {
+            return web::HttpResponse::build(self.status_code()).json(json!({
+                "custom_status": CustomStatusMessage::USER_REQUEST_DENIED,
+                "message": self.client_message(),
+            }));
+        }
+        if let AppError::NotApplied { current, .. } | AppError::PreconditionFailed { current, .. } = self {
+            return web::HttpResponse::build(self.status_code()).json(json!({
+                "custom_status": CustomStatusMessage::USER_REQUEST_FAILED,
+                "message": self.client_message(),
+                "data": current,
+            }));
+        }
+        GenericJsonErrorResponse {
+            custom_status: CustomStatusMessage::USER_REQUEST_FAILED.to_string(),
+            message: self.client_message(),
+            status: self.status_code().as_u16(),
+        }
+        .error_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn driver_errors_map_to_gateway_statuses() {
+        let refused = AppError::from(YcqlError::Io(io::Error::new(ErrorKind::ConnectionRefused, "refused")));
+        assert_eq!(refused.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let timed_out = AppError::from(YcqlError::Io(io::Error::new(ErrorKind::TimedOut, "slow node")));
+        assert_eq!(timed_out.status_code(), StatusCode::GATEWAY_TIMEOUT);
+
+        let no_node = AppError::from(YcqlError::General("Unable to get transport".to_string()));
+        assert_eq!(no_node.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let parse = AppError::from(YcqlError::General("Column or UDT property 'age' is empty".to_string()));
+        assert_eq!(parse.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn server_errors_are_masked_for_clients() {
+        let error = AppError::Internal { detail: "keyspace demo does not exist".to_string() };
+        assert!(!error.client_message().contains("keyspace"));
+        assert!(error.to_string().contains("keyspace"));
+    }
+}
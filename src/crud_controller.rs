@@ -1,252 +1,1517 @@
+use crate::application_error::AppError;
 use crate::default_strings::{CustomStatusMessage, UserErrorMessages};
-use crate::generic_json_error_response::GenericJsonErrorResponse;
-use crate::generic_json_success_response::GenericJsonSuccessResponse;
-use crate::yugabyte_db_ycql_config::CurrentYcqlDbSession;
-use crate::ycql_db_schema_and_queries::{UserProfileRowStruct, create_new_user_profile_query, select_user_profile_query, update_full_name_and_age_query, delete_user_profile_query};
-use actix_web::{web, get, post, App, HttpServer, http::StatusCode, ResponseError, Error, HttpResponse, Responder};
+use crate::generic_json_success_response::{GenericJsonSuccessResponse, PaginationBlock};
+use crate::user_profile_repository::UserProfileRepository;
+use crate::ycql_db_schema_and_queries::{
+    Address, CollectionChange, ConditionalWriteOutcome, ExpectedFullNameAndAge, MAX_PREFERENCES_BYTES, MAX_TTL_SECS, PreferencePath, Preferences, RowCondition,
+    UserProfilePatch, UserProfileRowStruct,
+};
+use crate::user_profile_etag::{etag_for, if_match_condition, insert_etag};
+use crate::ycql_service_config::PaginationConfig;
+use crate::validated_json_extractor::ValidatedJson;
+use crate::time_uuid_generator::TimeUuidGenerator;
+use crate::user_profile_access_policy::{AuthorizedCaller, Permission};
+use actix_web::{web, get, post, App, HttpServer, http::{header, StatusCode}, ResponseError, Error, HttpRequest, HttpResponse, Responder};
 
 
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::io;
-use serde::{Deserialize, Serialize};
-use validator::{Validate, ValidationError};
+use serde::{Deserialize, Deserializer, Serialize};
+use validator::{Validate, ValidationError, ValidationErrors};
 use serde_json::{json, to_string_pretty};
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Mutex;
 use uuid::Uuid;
-use uuid::v1::{Timestamp, Context};
-use std::time::{SystemTime, UNIX_EPOCH};
 
-pub fn get_epoch_ms() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
+/// Most elements a collection change or a new profile may carry per collection
+const MAX_COLLECTION_ELEMENTS: usize = 50;
+
+fn collection_error(message: &'static str) -> ValidationError {
+    let mut error = ValidationError::new("collection");
+    error.message = Some(message.into());
+    error
+}
+
+fn validate_tags(tags: &[String]) -> Result<(), ValidationError> {
+    if tags.is_empty() || tags.len() > MAX_COLLECTION_ELEMENTS {
+        return Err(collection_error("must hold between 1 and 50 tags"));
+    }
+    if tags.iter().any(|tag| tag.trim().is_empty() || tag.chars().count() > 50) {
+        return Err(collection_error("every tag must be between 1 and 50 characters"));
+    }
+    Ok(())
 }
+
+fn validate_phone_numbers(phone_numbers: &[String]) -> Result<(), ValidationError> {
+    if phone_numbers.is_empty() || phone_numbers.len() > MAX_COLLECTION_ELEMENTS {
+        return Err(collection_error("must hold between 1 and 50 phone numbers"));
+    }
+    let is_phone_number = |number: &String| {
+        (3..=32).contains(&number.len())
+            && number.chars().any(|c| c.is_ascii_digit())
+            && number.chars().all(|c| c.is_ascii_digit() || " +-()".contains(c))
+    };
+    if !phone_numbers.iter().all(is_phone_number) {
+        return Err(collection_error("every phone number must be 3 to 32 digits, spaces or + - ( )"));
+    }
+    Ok(())
+}
+
+fn validate_attribute_keys(keys: &[String]) -> Result<(), ValidationError> {
+    if keys.is_empty() || keys.len() > MAX_COLLECTION_ELEMENTS {
+        return Err(collection_error("must hold between 1 and 50 attribute keys"));
+    }
+    if keys.iter().any(|key| key.trim().is_empty() || key.chars().count() > 64) {
+        return Err(collection_error("every attribute key must be between 1 and 64 characters"));
+    }
+    Ok(())
+}
+
+fn validate_attributes(attributes: &HashMap<String, String>) -> Result<(), ValidationError> {
+    validate_attribute_keys(&attributes.keys().cloned().collect::<Vec<_>>())?;
+    if attributes.values().any(|value| value.chars().count() > 500) {
+        return Err(collection_error("every attribute value must be at most 500 characters"));
+    }
+    Ok(())
+}
+
+fn preferences_error(message: &'static str) -> ValidationError {
+    let mut error = ValidationError::new("preferences");
+    error.message = Some(message.into());
+    error
+}
+
+/// The whole document must be a JSON object of at most ***MAX_PREFERENCES_BYTES***
+fn validate_preferences(preferences: &Preferences) -> Result<(), ValidationError> {
+    if !preferences.0.is_object() {
+        return Err(preferences_error("must be a JSON object"));
+    }
+    validate_preference_value(&preferences.0)
+}
+
+pub(crate) fn validate_preference_value(value: &serde_json::Value) -> Result<(), ValidationError> {
+    if value.to_string().len() > MAX_PREFERENCES_BYTES {
+        return Err(preferences_error("must be at most 16384 bytes of JSON"));
+    }
+    Ok(())
+}
+
+fn validate_preference_path(path: &str) -> Result<(), ValidationError> {
+    PreferencePath::parse(path).map(|_| ()).map_err(preferences_error)
+}
+
+/// Email addresses are stored and looked up trimmed and lower-cased, so ***Jane@Demo.com*** and ***jane@demo.com***
+/// are one profile and one login
+pub(crate) fn normalize_email_address(email_address: &str) -> String {
+    email_address.trim().to_lowercase()
+}
+
+/// ***normalize_email_address*** at deserialisation, use on every request field holding an email address
+pub(crate) fn deserialize_email_address<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(deserializer).map(|email_address| normalize_email_address(&email_address))
+}
+
 ///Json Request Struct for |create_user_profile| FUNCTION 
-#[derive(Debug, Validate, Deserialize, Serialize)]
+#[derive(Debug, Default, Validate, Deserialize, Serialize)]
 pub struct UserProfileJsonREQUEST {
+    #[validate(email(message = "must be a valid email address"))]
+    #[serde(deserialize_with = "deserialize_email_address")]
     email_address: String,
+    #[validate(length(min = 1, max = 100, message = "must be between 1 and 100 characters"))]
     full_name: String,
+    #[validate(range(min = 0, max = 120, message = "must be between 0 and 120"))]
     age: i8,
-    comment: Option<String>
-}
-#[post("/create_user")]
-pub async fn create_user_profile(
-    ycql_connection_data: web::Data<Mutex<CurrentYcqlDbSession>>,
-    get_user_profile: web::Json<UserProfileJsonREQUEST>
-)-> Result<web::HttpResponse, GenericJsonErrorResponse> {
-
-    let mut current_ycql_conn   = ycql_connection_data.lock().unwrap(); // get DB session
-
-    // Create version 1 UUID(TimeUUID)
-    let current_time = get_epoch_ms();
-    let context = Context::new(42);
-    let ts = Timestamp::from_rfc4122(current_time, 0);
-    let uuid_version_1 = Uuid::new_v1(ts, &[1, 2, 3, 4, 5, 6]).expect("failed to generate UUID Version_1");
-     //Create version 2 UUID
+    #[validate(length(max = 500, message = "must be at most 500 characters"))]
+    comment: Option<String>,
+    /// Seconds until the profile expires, eg trial accounts. Leave out for the table default
+    #[validate(range(min = 1, max = "MAX_TTL_SECS", message = "must be between 1 and 630720000 seconds"))]
+    ttl_secs: Option<i32>,
+    #[validate]
+    address: Option<Address>,
+    #[validate(custom = "validate_tags")]
+    tags: Option<Vec<String>>,
+    #[validate(custom = "validate_phone_numbers")]
+    phone_numbers: Option<Vec<String>>,
+    #[validate(custom = "validate_attributes")]
+    attributes: Option<HashMap<String, String>>,
+    #[validate(custom = "validate_preferences")]
+    preferences: Option<Preferences>
+}
+/// Register every user profile route against the repository stored in app data as ***web::Data<R>***
+pub fn user_profile_routes<R: UserProfileRepository>(cfg: &mut web::ServiceConfig) {
+    cfg.route("/create_user", web::post().to(create_user_profile::<R>))
+        .route("/get_user_profile", web::post().to(read_user_profile::<R>))
+        .route("/find_user_by_email", web::post().to(find_user_profile_by_email::<R>))
+        .route("/update_user_profile", web::post().to(update_user_profile::<R>))
+        .route("/patch_user_profile", web::post().to(patch_user_profile::<R>))
+        .route("/update_user_collection", web::post().to(update_user_collection::<R>))
+        .route("/read_user_preference", web::post().to(read_user_preference::<R>))
+        .route("/update_user_preference", web::post().to(update_user_preference::<R>))
+        .route("/delete_user", web::post().to(delete_user_profile::<R>))
+        .route("/list_user_profiles", web::post().to(list_user_profiles::<R>));
+}
+
+pub async fn create_user_profile<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    user_profile_repository: web::Data<R>,
+    time_uuid_generator: web::Data<TimeUuidGenerator>,
+    get_user_profile: ValidatedJson<UserProfileJsonREQUEST>
+)-> Result<web::HttpResponse, AppError> {
+
+    caller.require(Permission::CreateProfile, None).await?;
+    let row = insert_new_user_profile(user_profile_repository.get_ref(), &time_uuid_generator, &get_user_profile).await?;
+
+    let mut response = web::HttpResponse::Ok();
+    insert_etag(&mut response, row.version);
+    Ok(response
+    .content_type("application/json")
+    .body(serde_json::to_string(
+        &GenericJsonSuccessResponse::<String>::generic_success_response(CustomStatusMessage::USER_REQUEST_SUCCESS.to_string(), "User profile created successfuly".to_string(), 200, None)
+    ).unwrap()))
+
+}
+
+/// Shared by the RPC and REST create routes, returns the stored row with its generated ids
+pub(crate) async fn insert_new_user_profile<R: UserProfileRepository>(
+    user_profile_repository: &R,
+    time_uuid_generator: &TimeUuidGenerator,
+    get_user_profile: &UserProfileJsonREQUEST
+)-> Result<UserProfileRowStruct, AppError> {
+
+    // Friendly early answer, the unique index still decides when two creates race
+    if user_profile_repository.find_by_email(get_user_profile.email_address.to_string()).await?.is_some() {
+        return Err(AppError::email_already_registered());
+    }
+
+    let row = new_user_profile_row(time_uuid_generator, get_user_profile);
+    match user_profile_repository.create_user_profile(row.clone()).await? {
+        ConditionalWriteOutcome::Applied => Ok(row),
+        ConditionalWriteOutcome::NotApplied { current } => {
+            Err(AppError::not_applied("A user profile with this user id and time_uuid_order already exists", current.as_deref()))
+        }
+    }
+}
+
+/// Row for a validated create request, with a new user_id and the next time_uuid_order
+pub(crate) fn new_user_profile_row(time_uuid_generator: &TimeUuidGenerator, get_user_profile: &UserProfileJsonREQUEST) -> UserProfileRowStruct {
+
+    // Create version 1 UUID(TimeUUID), strictly increasing across the whole process
+    let uuid_version_1 = time_uuid_generator.generate();
+     //Create version 4 UUID
     let uuid_version_4 = Uuid::new_v4();
 
-    let row = UserProfileRowStruct::build_user_profile(
+    let mut row = UserProfileRowStruct::build_user_profile(
         uuid_version_4, // user_id,
         uuid_version_1, // time_uuid_order,
         get_user_profile.email_address.to_string(), //email_address,
         get_user_profile.full_name.to_string(), // full name
         get_user_profile.age,
-        None // insert null into YCQL DB for comment field, 
+        get_user_profile.comment.clone() // null in YCQL DB when the request has no comment
     );
- 
-    let new_insert = create_new_user_profile_query(&mut current_ycql_conn, row).await;
-
-
-    match new_insert {
-        Ok(inserted_ycql_result)  =>  {
-            return Ok(web::HttpResponse::Ok()
-            .content_type("application/json")
-            .body(serde_json::to_string(
-                &GenericJsonSuccessResponse::<String>::generic_success_response(CustomStatusMessage::USER_REQUEST_SUCCESS.to_string(), "User profile created successfuly".to_string(), 200, None)
-            ).unwrap()))
-    },
-        Err(e) =>{
-            // println!("{:?}", e); // You probably wanna log this error for better tracing and you don't wanna return this back to the user
-            return Err(GenericJsonErrorResponse {
-                custom_status: CustomStatusMessage::USER_REQUEST_FAILED.to_string(),
-                message: UserErrorMessages::InputError{ reason: "We could not create your profile please try again later".to_string()}.to_string(),
-                status: 401,
-            })
+    row.ttl_secs = get_user_profile.ttl_secs;
+    row.address = get_user_profile.address.clone();
+    if let Some(tags) = &get_user_profile.tags {
+        // stored as a SET, return it the way YCQL will
+        row.tags = tags.clone();
+        row.tags.sort();
+        row.tags.dedup();
+    }
+    row.phone_numbers = get_user_profile.phone_numbers.clone().unwrap_or_default();
+    row.attributes = get_user_profile.attributes.clone().unwrap_or_default();
+    row.preferences = get_user_profile.preferences.clone();
+    row
+}
+
+/// Turn the outcome of a write on an existing row into 404 (row missing), 412 (stale If-Match)
+/// or 409 (row changed, sent back to the client)
+pub(crate) fn ensure_write_applied(outcome: ConditionalWriteOutcome, condition: &RowCondition) -> Result<(), AppError> {
+    match outcome {
+        ConditionalWriteOutcome::Applied => Ok(()),
+        ConditionalWriteOutcome::NotApplied { current: None } => {
+            Err(AppError::not_found("No user profile with this user id and time_uuid_order"))
+        }
+        ConditionalWriteOutcome::NotApplied { current: Some(current) } => match condition {
+            RowCondition::VersionMatches(_) => {
+                Err(AppError::precondition_failed("The user profile version does not match If-Match", Some(&current)))
+            }
+            _ => Err(AppError::not_applied("The user profile changed since you read it", Some(&current))),
         },
-    };
+    }
+}
 
+/// Answer for a write that stored ***new_version***, the ETag lets the client chain the next If-Match
+pub(crate) fn written_response(message: &str, new_version: Option<Uuid>) -> web::HttpResponse {
+    let mut response = web::HttpResponse::Ok();
+    insert_etag(&mut response, new_version);
+    response
+    .content_type("application/json")
+    .body(serde_json::to_string(
+        &GenericJsonSuccessResponse::<String>::generic_success_response(CustomStatusMessage::USER_REQUEST_SUCCESS.to_string(), message.to_string(), 200, None)
+    ).unwrap())
 }
 
 ///Json Request Struct for |read_user_profile| FUNCTION 
 #[derive(Debug, Validate, Deserialize, Serialize)]
 pub struct ReadUserProfileJsonREQUEST {
     user_id: Uuid,
+    #[validate(email(message = "must be a valid email address"))]
+    #[serde(deserialize_with = "deserialize_email_address")]
     email_address: String
 }
-#[post("/get_user_profile")]
-pub async fn read_user_profile(
-    ycql_connection_data: web::Data<Mutex<CurrentYcqlDbSession>>,
-    get_id: web::Json<ReadUserProfileJsonREQUEST>
-)-> Result<web::HttpResponse<>, GenericJsonErrorResponse>{
+pub async fn read_user_profile<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    user_profile_repository: web::Data<R>,
+    get_id: ValidatedJson<ReadUserProfileJsonREQUEST>
+)-> Result<web::HttpResponse<>, AppError>{
 
-    let mut current_ycql_conn   = ycql_connection_data.lock().unwrap();
+    caller.require(Permission::ReadProfile, Some(get_id.user_id)).await?;
 
-    let id = &get_id.user_id;
-    let email = &get_id.email_address;
-    let my_uuid =
-    Uuid::parse_str(&id.to_string()).unwrap(); // string to type uuid for query
     // query
-    let new_select = select_user_profile_query(&mut current_ycql_conn, my_uuid, email.to_string()).await;
-   
-    match new_select {
-        Ok(selected_ycql_result)  =>  {
-        return Ok(web::HttpResponse::Ok()
-        .content_type("application/json")
-        .body(serde_json::to_string(
-            &GenericJsonSuccessResponse::<Vec<UserProfileRowStruct>>::generic_success_response(CustomStatusMessage::USER_REQUEST_SUCCESS.to_string(), "success".to_string(), 200, Some(selected_ycql_result))
-        ).unwrap()))
-    
-    },
-        Err(e) =>{
-            println!("{:?}", e); // You probably want to log this error for better tracing and you don't want to return this back to the user
-            return Err(GenericJsonErrorResponse {
-                custom_status: CustomStatusMessage::USER_REQUEST_FAILED.to_string(),
-                message: UserErrorMessages::InputError{ reason: "We could not get your profile right noe".to_string()}.to_string(),
-                status: 401,
-            })
-        },
-    
-    };
+    let selected_ycql_result = user_profile_repository.read_user_profile(get_id.user_id, get_id.email_address.to_string()).await?;
+    if selected_ycql_result.is_empty() {
+        return Err(AppError::not_found("No user profile matches this user id and email address"));
+    }
+
+    Ok(web::HttpResponse::Ok()
+    .content_type("application/json")
+    .body(serde_json::to_string(
+        &GenericJsonSuccessResponse::<Vec<UserProfileRowStruct>>::generic_success_response(CustomStatusMessage::USER_REQUEST_SUCCESS.to_string(), "success".to_string(), 200, Some(selected_ycql_result))
+    ).unwrap()))
+}
+
+///Json Request Struct for |find_user_profile_by_email| FUNCTION
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct FindUserProfileByEmailJsonREQUEST {
+    #[validate(email(message = "must be a valid email address"))]
+    #[serde(deserialize_with = "deserialize_email_address")]
+    email_address: String
+}
+pub async fn find_user_profile_by_email<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    user_profile_repository: web::Data<R>,
+    get_email: ValidatedJson<FindUserProfileByEmailJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    let not_found = || AppError::not_found("No user profile with this email address");
+    let selected_ycql_result = user_profile_repository
+        .find_by_email(get_email.email_address.to_string())
+        .await?
+        .ok_or_else(not_found)?;
+    // the owner is only known once the row is read. A caller that may not read it gets the same 404 as for
+    // an unknown email, a 403 would confirm the address is registered
+    caller.require(Permission::ReadProfile, Some(selected_ycql_result.user_id)).await.map_err(|error| match error {
+        AppError::Forbidden { .. } => not_found(),
+        other => other,
+    })?;
+
+    let mut response = web::HttpResponse::Ok();
+    insert_etag(&mut response, selected_ycql_result.version);
+    Ok(response
+    .content_type("application/json")
+    .body(serde_json::to_string(
+        &GenericJsonSuccessResponse::<UserProfileRowStruct>::generic_success_response(CustomStatusMessage::USER_REQUEST_SUCCESS.to_string(), "success".to_string(), 200, Some(selected_ycql_result))
+    ).unwrap()))
+}
+
+/// Parse a uuid sent as a string field, ***field*** names it in the 400 answer
+pub(crate) fn parse_uuid_field(value: &str, field: &str) -> Result<Uuid, AppError> {
+    Uuid::parse_str(value).map_err(|_| AppError::BadRequest { reason: format!("Invalid {} format", field) })
 }
+
 ///Json Request Struct for |update_user_profile| FUNCTION 
 #[derive(Debug, Validate, Deserialize, Serialize)]
 pub struct UpdateUserProfileJsonREQUEST {
     user_id: String,
     time_uuid_order: String,
+    #[validate(range(min = 0, max = 120, message = "must be between 0 and 120"))]
     age: i8,
-    full_name: String
+    #[validate(length(min = 1, max = 100, message = "must be between 1 and 100 characters"))]
+    full_name: String,
+    /// Values the client last read, the update is rejected with 409 if the row no longer holds them.
+    /// Cannot be combined with an ***If-Match*** header
+    expected: Option<ExpectedUserProfileJsonREQUEST>
 }
-#[post("/update_user_profile")]
-pub async fn update_user_profile(   
- ycql_connection_data: web::Data<Mutex<CurrentYcqlDbSession>>,
- get_update_fields: web::Json<UpdateUserProfileJsonREQUEST>
-)-> Result<web::HttpResponse, GenericJsonErrorResponse>{
-    
-    let mut current_ycql_conn   = ycql_connection_data.lock().unwrap();
-
-    let user_id = &get_update_fields.user_id;
-    let time_uuid_order = &get_update_fields.time_uuid_order;
-    let age = &get_update_fields.age;
-    let full_name = &get_update_fields.full_name;
-   
-    let new_user_id = match Uuid::parse_str(&user_id.to_string()) {
-        Ok(new_uuid)  => { new_uuid }
-        Err(e) => { 
-            return Err(GenericJsonErrorResponse {
-            custom_status: CustomStatusMessage::USER_REQUEST_FAILED.to_string(),
-            message: UserErrorMessages::InputError{ reason: "Invalid user id format".to_string()}.to_string(),
-            status: 401,
-        }) }
-    };
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExpectedUserProfileJsonREQUEST {
+    full_name: String,
+    age: i8
+}
+pub async fn update_user_profile<R: UserProfileRepository>(
+ caller: AuthorizedCaller,
+ request: HttpRequest,
+ user_profile_repository: web::Data<R>,
+ get_update_fields: ValidatedJson<UpdateUserProfileJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
 
-    let new_time_uuid_order = match Uuid::parse_str(&time_uuid_order.to_string()) {
-        Ok(new_uuid)  => { new_uuid }
-        Err(e) => { 
-            return Err(GenericJsonErrorResponse {
-            custom_status: CustomStatusMessage::USER_REQUEST_FAILED.to_string(),
-            message: UserErrorMessages::InputError{ reason: "Invalid time_uuid_order format".to_string()}.to_string(),
-            status: 401,
-        }) }
+    let new_user_id = parse_uuid_field(&get_update_fields.user_id, "user id")?;
+    let new_time_uuid_order = parse_uuid_field(&get_update_fields.time_uuid_order, "time_uuid_order")?;
+    caller.require(Permission::UpdateProfile, Some(new_user_id)).await?;
+    let condition = match (if_match_condition(&request)?, get_update_fields.expected.as_ref()) {
+        (Some(_), Some(_)) => return Err(AppError::bad_request("Send either an If-Match header or expected, not both")),
+        (Some(condition), None) => condition,
+        (None, Some(expected)) => RowCondition::Unchanged(ExpectedFullNameAndAge {
+            full_name: expected.full_name.to_string(),
+            age: expected.age,
+        }),
+        (None, None) => RowCondition::Exists,
     };
+    let new_version = Uuid::new_v4();
   
     // query
-    let new_update = update_full_name_and_age_query(&mut current_ycql_conn, new_user_id, new_time_uuid_order, *age, full_name.to_string()).await;
-    // let new_select = select_user_profile(&mut current_ycql_conn).await;
-    match new_update {
-        Ok(updated_ycql_result)  =>  {
-    
-        return Ok(web::HttpResponse::Ok()
-        .content_type("application/json")
-        .body(serde_json::to_string(
-            &GenericJsonSuccessResponse::<String>::generic_success_response(CustomStatusMessage::USER_REQUEST_SUCCESS.to_string(), "User profile updated successfully".to_string(), 200, None)
-        ).unwrap()))
-    
-    },
-        Err(e) =>{
-            println!("{:?}", e);// You probably want to log this error for better tracing and you don't want to return this back to the user
-            return Err(GenericJsonErrorResponse {
-                custom_status: CustomStatusMessage::USER_REQUEST_FAILED.to_string(),
-                message: UserErrorMessages::InputError{ reason: "Could not update profile".to_string()}.to_string(),
-                status: 401,
-            })
-        },
-    
-    };
+    let outcome = user_profile_repository.update_full_name_and_age(new_user_id, new_time_uuid_order, get_update_fields.age, get_update_fields.full_name.to_string(), condition.clone(), new_version).await?;
+    ensure_write_applied(outcome, &condition)?;
+
+    Ok(written_response("User profile updated successfully", Some(new_version)))
     
 }
 
+/// Tells a missing field (None) apart from an explicit null (Some(None)), use with ***#[serde(default)]***
+fn deserialize_present<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+///Json Request Struct for the patchable columns, leave a field out to keep it, send null to clear comment, address or preferences
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct UserProfilePatchJsonREQUEST {
+    #[serde(default, deserialize_with = "deserialize_present")]
+    #[validate(length(min = 1, max = 100, message = "must be between 1 and 100 characters"))]
+    full_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_present")]
+    #[validate(range(min = 0, max = 120, message = "must be between 0 and 120"))]
+    age: Option<Option<i8>>,
+    #[serde(default, deserialize_with = "deserialize_present")]
+    #[validate(length(max = 500, message = "must be at most 500 characters"))]
+    comment: Option<Option<String>>,
+    /// validated in to_patch, the derive does not reach inside Option<Option<_>>
+    #[serde(default, deserialize_with = "deserialize_present")]
+    address: Option<Option<Address>>,
+    /// replaces the whole document, use update_user_preference to change one path
+    #[serde(default, deserialize_with = "deserialize_present")]
+    preferences: Option<Option<Preferences>>
+}
+
+impl UserProfilePatchJsonREQUEST {
+    pub(crate) fn to_patch(&self) -> Result<UserProfilePatch, AppError> {
+        let patch = UserProfilePatch {
+            full_name: match &self.full_name {
+                Some(None) => return Err(AppError::bad_request("full_name cannot be null")),
+                full_name => full_name.clone().flatten(),
+            },
+            age: match self.age {
+                Some(None) => return Err(AppError::bad_request("age cannot be null")),
+                age => age.flatten(),
+            },
+            comment: self.comment.clone(),
+            address: self.address.clone(),
+            preferences: self.preferences.clone(),
+        };
+        if let Some(Some(address)) = &patch.address {
+            ValidationErrors::merge(Ok(()), "address", address.validate())?;
+        }
+        if let Some(Some(preferences)) = &patch.preferences {
+            if let Err(error) = validate_preferences(preferences) {
+                let mut errors = ValidationErrors::new();
+                errors.add("preferences", error);
+                return Err(errors.into());
+            }
+        }
+        if patch.is_empty() {
+            return Err(AppError::bad_request("Nothing to update, send at least one of full_name, age, comment, address or preferences"));
+        }
+        Ok(patch)
+    }
+}
+
+///Json Request Struct for |patch_user_profile| FUNCTION
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PatchUserProfileJsonREQUEST {
+    user_id: String,
+    time_uuid_order: String,
+    #[serde(flatten)]
+    fields: UserProfilePatchJsonREQUEST
+}
+
+/// The flattened fields are validated directly so field errors keep their JSON names
+impl Validate for PatchUserProfileJsonREQUEST {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        self.fields.validate()
+    }
+}
+
+pub async fn patch_user_profile<R: UserProfileRepository>(
+ caller: AuthorizedCaller,
+ request: HttpRequest,
+ user_profile_repository: web::Data<R>,
+ get_patch_fields: ValidatedJson<PatchUserProfileJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    let user_id = parse_uuid_field(&get_patch_fields.user_id, "user id")?;
+    let time_uuid_order = parse_uuid_field(&get_patch_fields.time_uuid_order, "time_uuid_order")?;
+    caller.require(Permission::UpdateProfile, Some(user_id)).await?;
+    let patch = get_patch_fields.fields.to_patch()?;
+
+    apply_user_profile_patch(user_profile_repository.get_ref(), &request, user_id, time_uuid_order, patch).await
+}
+
+/// Shared by the RPC and REST patch routes, honours ***If-Match***
+pub(crate) async fn apply_user_profile_patch<R: UserProfileRepository>(
+ user_profile_repository: &R,
+ request: &HttpRequest,
+ user_id: Uuid,
+ time_uuid_order: Uuid,
+ patch: UserProfilePatch
+)-> Result<web::HttpResponse, AppError>{
+
+    let condition = if_match_condition(request)?.unwrap_or(RowCondition::Exists);
+    let new_version = Uuid::new_v4();
+
+    // query
+    let outcome = user_profile_repository.patch_user_profile(user_id, time_uuid_order, patch, condition.clone(), new_version).await?;
+    ensure_write_applied(outcome, &condition)?;
+
+    Ok(written_response("User profile updated successfully", Some(new_version)))
+}
+
+/// One collection element change, eg ***{"add_tags": ["vip"]}*** or ***{"remove_attributes": ["plan"]}***
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CollectionChangeJsonREQUEST {
+    AddTags(Vec<String>),
+    RemoveTags(Vec<String>),
+    AppendPhoneNumbers(Vec<String>),
+    RemovePhoneNumbers(Vec<String>),
+    PutAttributes(HashMap<String, String>),
+    RemoveAttributes(Vec<String>),
+}
+
+impl CollectionChangeJsonREQUEST {
+    /// Checks the elements like the create rules, errors are reported on ***change***
+    pub(crate) fn to_change(&self) -> Result<CollectionChange, ValidationErrors> {
+        let checked = match self {
+            CollectionChangeJsonREQUEST::AddTags(tags) | CollectionChangeJsonREQUEST::RemoveTags(tags) => validate_tags(tags),
+            CollectionChangeJsonREQUEST::AppendPhoneNumbers(numbers) | CollectionChangeJsonREQUEST::RemovePhoneNumbers(numbers) => {
+                validate_phone_numbers(numbers)
+            }
+            CollectionChangeJsonREQUEST::PutAttributes(attributes) => validate_attributes(attributes),
+            CollectionChangeJsonREQUEST::RemoveAttributes(keys) => validate_attribute_keys(keys),
+        };
+        if let Err(error) = checked {
+            let mut errors = ValidationErrors::new();
+            errors.add("change", error);
+            return Err(errors);
+        }
+        Ok(match self {
+            CollectionChangeJsonREQUEST::AddTags(tags) => CollectionChange::AddTags(tags.clone()),
+            CollectionChangeJsonREQUEST::RemoveTags(tags) => CollectionChange::RemoveTags(tags.clone()),
+            CollectionChangeJsonREQUEST::AppendPhoneNumbers(numbers) => CollectionChange::AppendPhoneNumbers(numbers.clone()),
+            CollectionChangeJsonREQUEST::RemovePhoneNumbers(numbers) => CollectionChange::RemovePhoneNumbers(numbers.clone()),
+            CollectionChangeJsonREQUEST::PutAttributes(attributes) => CollectionChange::PutAttributes(attributes.clone()),
+            CollectionChangeJsonREQUEST::RemoveAttributes(keys) => CollectionChange::RemoveAttributes(keys.clone()),
+        })
+    }
+}
+
+///Json Request Struct for |update_user_collection| FUNCTION
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UpdateUserCollectionJsonREQUEST {
+    user_id: String,
+    time_uuid_order: String,
+    change: CollectionChangeJsonREQUEST
+}
+
+impl Validate for UpdateUserCollectionJsonREQUEST {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        self.change.to_change().map(|_| ())
+    }
+}
+
+pub async fn update_user_collection<R: UserProfileRepository>(
+ caller: AuthorizedCaller,
+ request: HttpRequest,
+ user_profile_repository: web::Data<R>,
+ get_change: ValidatedJson<UpdateUserCollectionJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    let user_id = parse_uuid_field(&get_change.user_id, "user id")?;
+    let time_uuid_order = parse_uuid_field(&get_change.time_uuid_order, "time_uuid_order")?;
+    caller.require(Permission::UpdateProfile, Some(user_id)).await?;
+    let change = get_change.change.to_change()?;
+
+    apply_collection_change(user_profile_repository.get_ref(), &request, user_id, time_uuid_order, change).await
+}
+
+/// Shared by the RPC and REST collection routes, honours ***If-Match***
+pub(crate) async fn apply_collection_change<R: UserProfileRepository>(
+ user_profile_repository: &R,
+ request: &HttpRequest,
+ user_id: Uuid,
+ time_uuid_order: Uuid,
+ change: CollectionChange
+)-> Result<web::HttpResponse, AppError>{
+
+    let condition = if_match_condition(request)?.unwrap_or(RowCondition::Exists);
+    let new_version = Uuid::new_v4();
+
+    // query
+    let outcome = user_profile_repository.update_collection(user_id, time_uuid_order, change, condition.clone(), new_version).await?;
+    ensure_write_applied(outcome, &condition)?;
+
+    Ok(written_response("User profile updated successfully", Some(new_version)))
+}
+
+///Json Request Struct for |read_user_preference| FUNCTION, ***path*** is dotted eg ***theme.color***
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct ReadUserPreferenceJsonREQUEST {
+    user_id: String,
+    time_uuid_order: String,
+    #[validate(custom = "validate_preference_path")]
+    path: String
+}
+
+/// One preference as ***->>*** returns it, ***value*** is null when the path is not stored
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UserPreferenceJsonRESPONSE {
+    pub path: String,
+    pub value: Option<String>,
+}
+
+pub async fn read_user_preference<R: UserProfileRepository>(
+ caller: AuthorizedCaller,
+ user_profile_repository: web::Data<R>,
+ get_path: ValidatedJson<ReadUserPreferenceJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    let user_id = parse_uuid_field(&get_path.user_id, "user id")?;
+    let time_uuid_order = parse_uuid_field(&get_path.time_uuid_order, "time_uuid_order")?;
+    caller.require(Permission::ReadProfile, Some(user_id)).await?;
+    let path = PreferencePath::parse(&get_path.path).map_err(AppError::bad_request)?;
+
+    read_preference_value(user_profile_repository.get_ref(), user_id, time_uuid_order, path).await
+}
+
+/// Shared by the RPC and REST preference reads
+pub(crate) async fn read_preference_value<R: UserProfileRepository>(
+ user_profile_repository: &R,
+ user_id: Uuid,
+ time_uuid_order: Uuid,
+ path: PreferencePath
+)-> Result<web::HttpResponse, AppError>{
+
+    // query
+    let value = user_profile_repository
+        .read_preference(user_id, time_uuid_order, path.clone())
+        .await?
+        .ok_or_else(|| AppError::not_found("No user profile with this user id and time_uuid_order"))?;
+
+    Ok(web::HttpResponse::Ok()
+    .content_type("application/json")
+    .body(serde_json::to_string(
+        &GenericJsonSuccessResponse::<UserPreferenceJsonRESPONSE>::generic_success_response(CustomStatusMessage::USER_REQUEST_SUCCESS.to_string(), "success".to_string(), 200, Some(UserPreferenceJsonRESPONSE { path: path.to_string(), value }))
+    ).unwrap()))
+}
+
+///Json Request Struct for |update_user_preference| FUNCTION, ***value*** is any JSON value stored at ***path***
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct UpdateUserPreferenceJsonREQUEST {
+    user_id: String,
+    time_uuid_order: String,
+    #[validate(custom = "validate_preference_path")]
+    path: String,
+    #[validate(custom = "validate_preference_value")]
+    value: serde_json::Value
+}
+
+pub async fn update_user_preference<R: UserProfileRepository>(
+ caller: AuthorizedCaller,
+ request: HttpRequest,
+ user_profile_repository: web::Data<R>,
+ get_preference: ValidatedJson<UpdateUserPreferenceJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    let user_id = parse_uuid_field(&get_preference.user_id, "user id")?;
+    let time_uuid_order = parse_uuid_field(&get_preference.time_uuid_order, "time_uuid_order")?;
+    caller.require(Permission::UpdateProfile, Some(user_id)).await?;
+    let path = PreferencePath::parse(&get_preference.path).map_err(AppError::bad_request)?;
+
+    apply_preference_update(user_profile_repository.get_ref(), &request, user_id, time_uuid_order, path, get_preference.value.clone()).await
+}
+
+/// Shared by the RPC and REST preference updates, honours ***If-Match***
+pub(crate) async fn apply_preference_update<R: UserProfileRepository>(
+ user_profile_repository: &R,
+ request: &HttpRequest,
+ user_id: Uuid,
+ time_uuid_order: Uuid,
+ path: PreferencePath,
+ value: serde_json::Value
+)-> Result<web::HttpResponse, AppError>{
+
+    let condition = if_match_condition(request)?.unwrap_or(RowCondition::Exists);
+    let new_version = Uuid::new_v4();
+
+    // query
+    let outcome = user_profile_repository.set_preference(user_id, time_uuid_order, path, value, condition.clone(), new_version).await?;
+    ensure_write_applied(outcome, &condition)?;
+
+    Ok(written_response("User profile updated successfully", Some(new_version)))
+}
+
 #[derive(Debug, Validate, Deserialize, Serialize)]
 pub struct DeleteUserProfileJsonREQUEST {
   pub time_uuid_order: String,
   pub user_id: String
 }
 
-#[post("/delete_user")]
-pub async fn delete_user_profile(   
- ycql_connection_data: web::Data<Mutex<CurrentYcqlDbSession>>,
- get_fields: web::Json<DeleteUserProfileJsonREQUEST>
-)-> Result<web::HttpResponse, GenericJsonErrorResponse>{
-    
-    let mut current_ycql_conn   = ycql_connection_data.lock().unwrap();
+pub async fn delete_user_profile<R: UserProfileRepository>(
+ caller: AuthorizedCaller,
+ request: HttpRequest,
+ user_profile_repository: web::Data<R>,
+ get_fields: ValidatedJson<DeleteUserProfileJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
 
-    let time_uuid_order = &get_fields.time_uuid_order;
-    let user_id = &get_fields.user_id;
-    
+    let uuid = parse_uuid_field(&get_fields.user_id, "user id")?;
+    let time_uuid = parse_uuid_field(&get_fields.time_uuid_order, "time_uuid_order")?;
+    caller.require(Permission::DeleteProfile, Some(uuid)).await?;
+
+    remove_user_profile(user_profile_repository.get_ref(), &request, uuid, time_uuid).await
+}
+
+/// Shared by the RPC and REST delete routes, honours ***If-Match***, a missing row is a 404
+pub(crate) async fn remove_user_profile<R: UserProfileRepository>(
+ user_profile_repository: &R,
+ request: &HttpRequest,
+ user_id: Uuid,
+ time_uuid_order: Uuid
+)-> Result<web::HttpResponse, AppError>{
+
+    let condition = if_match_condition(request)?.unwrap_or(RowCondition::Exists);
+
+    // query
+    let outcome = user_profile_repository.delete_user_profile(user_id, time_uuid_order, condition.clone()).await?;
+    ensure_write_applied(outcome, &condition)?;
+
+    Ok(written_response("Successfuly deleted profile", None))
+}
+///Json Request Struct for |list_user_profiles| FUNCTION, send an empty object for the first page
+#[derive(Debug, Default, Validate, Deserialize, Serialize)]
+pub struct ListUserProfilesJsonREQUEST {
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    page_size: Option<i32>,
+    cursor: Option<String>
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserProfilesPageJsonRESPONSE {
+    user_profiles: Vec<UserProfileRowStruct>
+}
+
+pub async fn list_user_profiles<R: UserProfileRepository>(
+ caller: AuthorizedCaller,
+ user_profile_repository: web::Data<R>,
+ pagination_config: web::Data<PaginationConfig>,
+ get_page: ValidatedJson<ListUserProfilesJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    // every user's profile is in the page
+    caller.require(Permission::ListProfiles, None).await?;
+
+    list_user_profiles_page(user_profile_repository.get_ref(), &pagination_config, &get_page).await
+}
+
+/// Shared by the RPC route (JSON body) and the REST route (query string)
+pub(crate) async fn list_user_profiles_page<R: UserProfileRepository>(
+ user_profile_repository: &R,
+ pagination_config: &PaginationConfig,
+ get_page: &ListUserProfilesJsonREQUEST
+)-> Result<web::HttpResponse, AppError>{
+
+    // page_size >= 1 is already enforced by the validate rule
+    let page_size = get_page
+        .page_size
+        .map_or(pagination_config.default_page_size, |page_size| page_size.min(pagination_config.max_page_size));
 
-   
-    let uuid = match Uuid::parse_str(&user_id.to_string()) {
-        Ok(new_uuid)  => { new_uuid }
-        Err(e) => { 
-            return Err(GenericJsonErrorResponse {
-            custom_status: CustomStatusMessage::USER_REQUEST_FAILED.to_string(),
-            message: UserErrorMessages::InputError{ reason: "Invalid user id format".to_string()}.to_string(),
-            status: 401,
-        }) }
+    // the cursor is the driver paging state, opaque to clients
+    let paging_state = match &get_page.cursor {
+        None => None,
+        Some(cursor) => Some(
+            base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).map_err(|_| AppError::bad_request("Invalid cursor"))?
+        ),
     };
-    let time_uuid = match Uuid::parse_str(&time_uuid_order.to_string()) {
-        Ok(new_uuid)  => { new_uuid }
-        Err(e) => { 
-            return Err(GenericJsonErrorResponse {
-            custom_status: CustomStatusMessage::USER_REQUEST_FAILED.to_string(),
-            message: UserErrorMessages::InputError{ reason: "Invalid time_uuid_order format ".to_string()}.to_string(),
-            status: 401,
-        }) }
+
+    let page = user_profile_repository.list_user_profiles(page_size, paging_state).await?;
+    let next_cursor = page.paging_state.map(|paging_state| base64::encode_config(paging_state, base64::URL_SAFE_NO_PAD));
+    let pagination = PaginationBlock {
+        page_size,
+        has_more: next_cursor.is_some(),
+        next_cursor,
     };
-    // query
-    let new_update = delete_user_profile_query(&mut current_ycql_conn, uuid, time_uuid).await;
-    // let new_select = select_user_profile(&mut current_ycql_conn).await;
-    match new_update {
-        Ok(updated_ycql_result)  =>  {
-    
-        return Ok(web::HttpResponse::Ok()
-        .content_type("application/json")
-        .body(serde_json::to_string(
-            &GenericJsonSuccessResponse::<String>::generic_success_response(CustomStatusMessage::USER_REQUEST_SUCCESS.to_string(), "Successfuly deleted profile".to_string(), 200, None)
-        ).unwrap()))
-    
-    },
-        Err(e) =>{
-           // println!("{:?}", e); // You probably want to log this error for better tracing and you don't want to return this back to the user
-            return Err(GenericJsonErrorResponse {
-                custom_status: CustomStatusMessage::USER_REQUEST_FAILED.to_string(),
-                message: UserErrorMessages::InputError{ reason: "Could not delete user profile".to_string()}.to_string(),
-                status: 401,
+
+    Ok(web::HttpResponse::Ok()
+    .content_type("application/json")
+    .body(serde_json::to_string(
+        &GenericJsonSuccessResponse::<UserProfilesPageJsonRESPONSE>::generic_success_response(CustomStatusMessage::USER_REQUEST_SUCCESS.to_string(), "success".to_string(), 200, Some(UserProfilesPageJsonRESPONSE { user_profiles: page.user_profiles }))
+        .with_pagination(pagination)
+    ).unwrap()))
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler_test_app::{app_as, ops_admin};
+    use crate::user_profile_repository::{InMemoryUserProfileRepository, YcqlUserProfileRepository};
+    use crate::ycql_authenticators::YcqlAuthenticatorRegistry;
+    use crate::ycql_service_config::ServiceConfig;
+    use crate::yugabyte_db_ycql_config::{configure_yugabyte_db_session, CurrentYcqlDbSession};
+    use crate::ycql_schema_migrations::YcqlSchemaMigrator;
+    use crate::time_uuid_generator::FixedClock;
+    use crate::jwt_bearer_authentication::JwtClaims;
+    use crate::user_profile_access_policy::SUPPORT_ROLE;
+    use actix_web::test;
+    use futures::stream::{self, StreamExt};
+    use std::time::{Duration, Instant};
+
+    fn seeded_row(email_address: &str) -> UserProfileRowStruct {
+        UserProfileRowStruct::build_user_profile(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            email_address.to_string(),
+            "Jane Doe".to_string(),
+            26,
+            None,
+        )
+    }
+
+    const FIXED_NODE_ID: [u8; 6] = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
+
+    fn fixed_clock_time_uuid_generator() -> web::Data<TimeUuidGenerator> {
+        web::Data::new(TimeUuidGenerator::with_clock_sequence(
+            FIXED_NODE_ID,
+            0,
+            Box::new(FixedClock(Duration::from_secs(1_600_000_000))),
+        ))
+    }
+
+    /// Stands in for JwtAuthentication, requests carry ***claims*** as if their token was verified
+    macro_rules! in_memory_app {
+        ($repository:expr) => {
+            in_memory_app!($repository, ops_admin())
+        };
+        ($repository:expr, $claims:expr) => {
+            test::init_service(
+                app_as($claims)
+                    .app_data($repository.clone())
+                    .app_data(web::Data::new(PaginationConfig::default()))
+                    .app_data(fixed_clock_time_uuid_generator())
+                    .configure(user_profile_routes::<InMemoryUserProfileRepository>),
+            )
+            .await
+        };
+    }
+
+    #[actix_rt::test]
+    async fn create_user_stores_the_profile() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let app = in_memory_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/create_user")
+            .set_json(&UserProfileJsonREQUEST {
+                email_address: "jane@demo.com".to_string(),
+                full_name: "Jane Doe".to_string(),
+                age: 26,
+                comment: Some("prefers email".to_string()),
+                ttl_secs: None,
+                ..Default::default()
             })
-        },
-    
-    };
-    
-}
\ No newline at end of file
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let stored = repository.list_user_profiles(10, None).await.unwrap().user_profiles;
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].email_address, "jane@demo.com");
+        assert_eq!(stored[0].full_name, "Jane Doe");
+        assert_eq!(stored[0].age, 26);
+        assert_eq!(stored[0].comment.as_deref(), Some("prefers email"));
+    }
+
+    #[actix_rt::test]
+    async fn get_user_profile_returns_matching_rows() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let row = seeded_row("jane@demo.com");
+        repository.create_user_profile(row.clone()).await.unwrap();
+        repository.create_user_profile(seeded_row("john@demo.com")).await.unwrap();
+        let app = in_memory_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/get_user_profile")
+            .set_json(&json!({"user_id": row.user_id, "email_address": "Jane@Demo.com"}))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["custom_status"], CustomStatusMessage::USER_REQUEST_SUCCESS);
+        assert_eq!(body["data"].as_array().unwrap().len(), 1);
+        assert_eq!(body["data"][0]["time_uuid_order"], json!(row.time_uuid_order));
+    }
+
+    #[actix_rt::test]
+    async fn create_user_takes_time_uuid_order_from_the_generator() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let app = in_memory_app!(repository);
+
+        for email_address in &["jane@demo.com", "john@demo.com"] {
+            let request = test::TestRequest::post()
+                .uri("/create_user")
+                .set_json(&json!({"email_address": email_address, "full_name": "Same Second", "age": 30}))
+                .to_request();
+            assert_eq!(test::call_service(&app, request).await.status(), StatusCode::OK);
+        }
+
+        // the app's generator and this one start from the same fixed clock, so the ids are predictable
+        let expected = fixed_clock_time_uuid_generator();
+        let mut stored: Vec<Uuid> = repository
+            .list_user_profiles(10, None)
+            .await
+            .unwrap()
+            .user_profiles
+            .iter()
+            .map(|row| row.time_uuid_order)
+            .collect();
+        stored.sort_by_key(|time_uuid| time_uuid.as_bytes()[..4].to_vec());
+        assert_eq!(stored, vec![expected.generate(), expected.generate()]);
+    }
+
+    #[actix_rt::test]
+    async fn create_user_lists_every_invalid_field() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let app = in_memory_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/create_user")
+            .set_json(&json!({"email_address": "not-an-email", "full_name": "", "age": -3, "comment": null}))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["custom_status"], CustomStatusMessage::USER_REQUEST_FAILED);
+        let fields: Vec<&str> = body["field_errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field_error| field_error["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(fields, vec!["age", "email_address", "full_name"]);
+        assert!(repository.list_user_profiles(10, None).await.unwrap().user_profiles.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn nested_address_and_collection_errors_name_their_path() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let app = in_memory_app!(repository);
+
+        let field_errors = |body: serde_json::Value| -> Vec<String> {
+            body["field_errors"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|field_error| field_error["field"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let request = test::TestRequest::post()
+            .uri("/create_user")
+            .set_json(&json!({
+                "email_address": "jane@demo.com", "full_name": "Jane Doe", "age": 26,
+                "address": {"street": "1 Main St", "city": "Lisbon", "country": "Portugal"},
+                "tags": [""]
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(field_errors(test::read_body_json(response).await), vec!["address.country", "tags"]);
+
+        let request = test::TestRequest::post()
+            .uri("/update_user_collection")
+            .set_json(&json!({
+                "user_id": Uuid::new_v4().to_string(),
+                "time_uuid_order": Uuid::new_v4().to_string(),
+                "change": {"remove_attributes": []}
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(field_errors(test::read_body_json(response).await), vec!["change"]);
+    }
+
+    #[actix_rt::test]
+    async fn create_user_rejects_a_registered_email() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        repository.create_user_profile(seeded_row("jane@demo.com")).await.unwrap();
+        let app = in_memory_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/create_user")
+            .set_json(&UserProfileJsonREQUEST {
+                email_address: "  Jane@Demo.COM ".to_string(),
+                full_name: "Another Jane".to_string(),
+                age: 31,
+                comment: None,
+                ttl_secs: None,
+                ..Default::default()
+            })
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(repository.list_user_profiles(10, None).await.unwrap().user_profiles.len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn a_create_racing_past_the_email_check_is_still_a_conflict() {
+        // two creates that both passed find_by_email, the repository decides without parsing any server message
+        let repository = InMemoryUserProfileRepository::default();
+        let generator = fixed_clock_time_uuid_generator();
+        let create = |full_name: &str| {
+            serde_json::from_value::<UserProfileJsonREQUEST>(json!({"email_address": "Jane@Demo.com", "full_name": full_name, "age": 26})).unwrap()
+        };
+        let first = new_user_profile_row(&generator, &create("Jane Doe"));
+        let second = new_user_profile_row(&generator, &create("Another Jane"));
+        assert_eq!(second.email_address, "jane@demo.com");
+
+        assert_eq!(repository.create_user_profile(first).await.unwrap(), ConditionalWriteOutcome::Applied);
+        let error = repository.create_user_profile(second).await.unwrap_err();
+        assert!(matches!(error, AppError::Conflict { .. }), "{:?}", error);
+        assert_eq!(error.status_code(), StatusCode::CONFLICT);
+    }
+
+    #[actix_rt::test]
+    async fn find_user_by_email_returns_the_profile() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let row = seeded_row("jane@demo.com");
+        repository.create_user_profile(row.clone()).await.unwrap();
+        let app = in_memory_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/find_user_by_email")
+            .set_json(&json!({"email_address": " JANE@demo.com"}))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["data"]["user_id"], json!(row.user_id));
+
+        let request = test::TestRequest::post()
+            .uri("/find_user_by_email")
+            .set_json(&json!({"email_address": "nobody@demo.com"}))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn create_user_with_ttl_exposes_the_remaining_ttl_and_writes_keep_it() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let app = in_memory_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/create_user")
+            .set_json(&json!({"email_address": "trial@demo.com", "full_name": "Trial User", "age": 26, "ttl_secs": 0}))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::BAD_REQUEST);
+
+        let request = test::TestRequest::post()
+            .uri("/create_user")
+            .set_json(&json!({"email_address": "trial@demo.com", "full_name": "Trial User", "age": 26, "ttl_secs": 3600}))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::OK);
+
+        let row = repository.find_by_email("trial@demo.com".to_string()).await.unwrap().unwrap();
+        let ttl_secs = row.ttl_secs.unwrap();
+        assert!((3590..=3600).contains(&ttl_secs), "{}", ttl_secs);
+
+        let (user_id, time_uuid_order) = (row.user_id.to_string(), row.time_uuid_order.to_string());
+        for (uri, write) in [
+            ("/update_user_profile", json!({"user_id": user_id, "time_uuid_order": time_uuid_order, "age": 27, "full_name": "Trial User"})),
+            ("/patch_user_profile", json!({"user_id": user_id, "time_uuid_order": time_uuid_order, "age": 28})),
+        ] {
+            let request = test::TestRequest::post().uri(uri).set_json(&write).to_request();
+            assert_eq!(test::call_service(&app, request).await.status(), StatusCode::OK, "{}", uri);
+
+            let request = test::TestRequest::post()
+                .uri("/find_user_by_email")
+                .set_json(&json!({"email_address": "trial@demo.com"}))
+                .to_request();
+            let body: serde_json::Value = test::read_body_json(test::call_service(&app, request).await).await;
+            assert_eq!(body["data"]["age"], write["age"]);
+            let kept = body["data"]["ttl_secs"].as_i64().unwrap();
+            assert!((3580..=i64::from(ttl_secs)).contains(&kept), "{} after {}", kept, uri);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn get_user_profile_answers_not_found_for_an_unknown_user() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let app = in_memory_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/get_user_profile")
+            .set_json(&json!({"user_id": Uuid::new_v4(), "email_address": "nobody@demo.com"}))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn update_user_profile_changes_name_and_age() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let row = seeded_row("jane@demo.com");
+        repository.create_user_profile(row.clone()).await.unwrap();
+        let app = in_memory_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/update_user_profile")
+            .set_json(&json!({
+                "user_id": row.user_id.to_string(),
+                "time_uuid_order": row.time_uuid_order.to_string(),
+                "age": 27,
+                "full_name": "Jane Updated"
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let stored = repository.read_user_profile(row.user_id, row.email_address.clone()).await.unwrap();
+        assert_eq!(stored[0].age, 27);
+        assert_eq!(stored[0].full_name, "Jane Updated");
+    }
+
+    #[actix_rt::test]
+    async fn patch_user_profile_only_touches_supplied_fields() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let mut row = seeded_row("jane@demo.com");
+        row.comment = Some("first comment".to_string());
+        repository.create_user_profile(row.clone()).await.unwrap();
+        let app = in_memory_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/patch_user_profile")
+            .set_json(&json!({
+                "user_id": row.user_id.to_string(),
+                "time_uuid_order": row.time_uuid_order.to_string(),
+                "age": 40,
+                "comment": null
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let stored = repository.read_user_profile(row.user_id, row.email_address.clone()).await.unwrap();
+        assert_eq!(stored[0].age, 40);
+        assert_eq!(stored[0].full_name, "Jane Doe");
+        assert_eq!(stored[0].comment, None);
+    }
+
+    #[actix_rt::test]
+    async fn patch_user_profile_rejects_null_for_required_columns() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let row = seeded_row("jane@demo.com");
+        repository.create_user_profile(row.clone()).await.unwrap();
+        let app = in_memory_app!(repository);
+
+        for body in &[
+            json!({"user_id": row.user_id.to_string(), "time_uuid_order": row.time_uuid_order.to_string(), "full_name": null}),
+            json!({"user_id": row.user_id.to_string(), "time_uuid_order": row.time_uuid_order.to_string()}),
+        ] {
+            let request = test::TestRequest::post().uri("/patch_user_profile").set_json(body).to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn update_user_profile_with_stale_expected_values_returns_the_current_row() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let row = seeded_row("jane@demo.com");
+        repository.create_user_profile(row.clone()).await.unwrap();
+        let app = in_memory_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/update_user_profile")
+            .set_json(&json!({
+                "user_id": row.user_id.to_string(),
+                "time_uuid_order": row.time_uuid_order.to_string(),
+                "age": 27,
+                "full_name": "Jane Updated",
+                "expected": {"full_name": "Someone Else", "age": 26}
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["data"]["full_name"], "Jane Doe");
+
+        let stored = repository.read_user_profile_by_key(row.user_id, row.time_uuid_order).await.unwrap().unwrap();
+        assert_eq!(stored.full_name, "Jane Doe");
+    }
+
+    #[actix_rt::test]
+    async fn update_user_profile_with_stale_if_match_returns_412_and_a_fresh_etag_succeeds() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let row = seeded_row("jane@demo.com");
+        repository.create_user_profile(row.clone()).await.unwrap();
+        let app = in_memory_app!(repository);
+        let update = json!({
+            "user_id": row.user_id.to_string(),
+            "time_uuid_order": row.time_uuid_order.to_string(),
+            "age": 27,
+            "full_name": "Jane Updated"
+        });
+
+        let request = test::TestRequest::post()
+            .uri("/update_user_profile")
+            .insert_header((header::IF_MATCH, etag_for(Uuid::new_v4())))
+            .set_json(&update)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["data"]["version"], row.version.unwrap().to_string());
+
+        let request = test::TestRequest::post()
+            .uri("/update_user_profile")
+            .insert_header((header::IF_MATCH, etag_for(row.version.unwrap())))
+            .set_json(&update)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+
+        let stored = repository.read_user_profile_by_key(row.user_id, row.time_uuid_order).await.unwrap().unwrap();
+        assert_eq!(stored.full_name, "Jane Updated");
+        assert_eq!(etag, etag_for(stored.version.unwrap()));
+        assert_ne!(stored.version, row.version);
+    }
+
+    #[actix_rt::test]
+    async fn update_user_profile_rejects_if_match_together_with_expected() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let row = seeded_row("jane@demo.com");
+        repository.create_user_profile(row.clone()).await.unwrap();
+        let app = in_memory_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/update_user_profile")
+            .insert_header((header::IF_MATCH, "*"))
+            .set_json(&json!({
+                "user_id": row.user_id.to_string(),
+                "time_uuid_order": row.time_uuid_order.to_string(),
+                "age": 27,
+                "full_name": "Jane Updated",
+                "expected": {"full_name": "Jane Doe", "age": 26}
+            }))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn update_user_profile_does_not_create_missing_rows() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let app = in_memory_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/update_user_profile")
+            .set_json(&json!({
+                "user_id": Uuid::new_v4().to_string(),
+                "time_uuid_order": Uuid::new_v4().to_string(),
+                "age": 27,
+                "full_name": "Nobody"
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(repository.list_user_profiles(10, None).await.unwrap().user_profiles.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn create_never_overwrites_an_existing_primary_key() {
+        let repository = InMemoryUserProfileRepository::default();
+        let row = seeded_row("jane@demo.com");
+        assert_eq!(repository.create_user_profile(row.clone()).await.unwrap(), ConditionalWriteOutcome::Applied);
+
+        let mut same_key = row.clone();
+        same_key.full_name = "Overwritten".to_string();
+        assert_eq!(
+            repository.create_user_profile(same_key).await.unwrap(),
+            ConditionalWriteOutcome::NotApplied { current: Some(Box::new(row)) }
+        );
+    }
+
+    #[actix_rt::test]
+    async fn update_user_profile_rejects_malformed_user_id() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let app = in_memory_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/update_user_profile")
+            .set_json(&json!({
+                "user_id": "not-a-uuid",
+                "time_uuid_order": Uuid::new_v4().to_string(),
+                "age": 27,
+                "full_name": "Jane Updated"
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["custom_status"], CustomStatusMessage::USER_REQUEST_FAILED);
+    }
+
+    #[actix_rt::test]
+    async fn delete_user_removes_the_profile() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let row = seeded_row("jane@demo.com");
+        repository.create_user_profile(row.clone()).await.unwrap();
+        let app = in_memory_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/delete_user")
+            .set_json(&json!({
+                "user_id": row.user_id.to_string(),
+                "time_uuid_order": row.time_uuid_order.to_string()
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(repository.list_user_profiles(10, None).await.unwrap().user_profiles.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn list_user_profiles_follows_the_cursor() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        for n in 0..3 {
+            repository.create_user_profile(seeded_row(&format!("user{}@demo.com", n))).await.unwrap();
+        }
+        let app = in_memory_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/list_user_profiles")
+            .set_json(&json!({"page_size": 2}))
+            .to_request();
+        let first_page: serde_json::Value = test::read_body_json(test::call_service(&app, request).await).await;
+        assert_eq!(first_page["data"]["user_profiles"].as_array().unwrap().len(), 2);
+        assert_eq!(first_page["pagination"]["has_more"], true);
+
+        let request = test::TestRequest::post()
+            .uri("/list_user_profiles")
+            .set_json(&json!({"page_size": 2, "cursor": first_page["pagination"]["next_cursor"]}))
+            .to_request();
+        let second_page: serde_json::Value = test::read_body_json(test::call_service(&app, request).await).await;
+        assert_eq!(second_page["data"]["user_profiles"].as_array().unwrap().len(), 1);
+        assert_eq!(second_page["pagination"]["has_more"], false);
+        assert!(second_page["pagination"]["next_cursor"].is_null());
+    }
+
+    #[actix_rt::test]
+    async fn list_user_profiles_rejects_a_malformed_cursor() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let app = in_memory_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/list_user_profiles")
+            .set_json(&json!({"cursor": "not base64!"}))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn handlers_require_their_permission() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let row = seeded_row("jane@demo.com");
+        repository.create_user_profile(row.clone()).await.unwrap();
+        let stranger = in_memory_app!(repository, JwtClaims::for_tests(&Uuid::new_v4().to_string(), &[]));
+
+        let requests = vec![
+            ("/create_user", json!({"email_address": "mallory@demo.com", "full_name": "Mallory", "age": 40})),
+            ("/get_user_profile", json!({"user_id": row.user_id, "email_address": "jane@demo.com"})),
+            ("/update_user_profile", json!({"user_id": row.user_id, "time_uuid_order": row.time_uuid_order, "full_name": "Mallory", "age": 40})),
+            ("/delete_user", json!({"user_id": row.user_id, "time_uuid_order": row.time_uuid_order})),
+            ("/list_user_profiles", json!({})),
+        ];
+        for (uri, body) in requests {
+            let request = test::TestRequest::post().uri(uri).set_json(&body).to_request();
+            let response = test::call_service(&stranger, request).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", uri);
+            let body: serde_json::Value = test::read_body_json(response).await;
+            assert_eq!(body["custom_status"], CustomStatusMessage::USER_REQUEST_DENIED);
+        }
+        let stored = repository.read_user_profile_by_key(row.user_id, row.time_uuid_order).await.unwrap().unwrap();
+        assert_eq!(stored.full_name, "Jane Doe");
+
+        // registered and unknown emails look the same to a caller that may not read the profile
+        let mut answers = Vec::new();
+        for email_address in ["jane@demo.com", "nobody@demo.com"] {
+            let request = test::TestRequest::post()
+                .uri("/find_user_by_email")
+                .set_json(&json!({"email_address": email_address}))
+                .to_request();
+            let response = test::call_service(&stranger, request).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", email_address);
+            answers.push(test::read_body(response).await);
+        }
+        assert_eq!(answers[0], answers[1]);
+
+        let owner = in_memory_app!(repository, JwtClaims::for_tests(&row.user_id.to_string(), &[]));
+        let request = test::TestRequest::post()
+            .uri("/update_user_profile")
+            .set_json(&json!({"user_id": row.user_id, "time_uuid_order": row.time_uuid_order, "full_name": "Jane Roe", "age": 27}))
+            .to_request();
+        assert_eq!(test::call_service(&owner, request).await.status(), StatusCode::OK);
+
+        // support may change anyone's profile but not delete it
+        let support = in_memory_app!(repository, JwtClaims::for_tests("support@demo.com", &[SUPPORT_ROLE]));
+        let request = test::TestRequest::post()
+            .uri("/update_user_profile")
+            .set_json(&json!({"user_id": row.user_id, "time_uuid_order": row.time_uuid_order, "full_name": "Jane Poe", "age": 28}))
+            .to_request();
+        assert_eq!(test::call_service(&support, request).await.status(), StatusCode::OK);
+        let request = test::TestRequest::post()
+            .uri("/delete_user")
+            .set_json(&json!({"user_id": row.user_id, "time_uuid_order": row.time_uuid_order}))
+            .to_request();
+        assert_eq!(test::call_service(&support, request).await.status(), StatusCode::FORBIDDEN);
+    }
+
+    const LOAD_TEST_REQUESTS: usize = 400;
+    /// Well under the 16x the concurrency allows, a single node cluster on a laptop shares its cores with the test
+    /// and still clears it, while a handler that serialises requests stays near 1x
+    const LOAD_TEST_MIN_SPEEDUP: f64 = 1.5;
+
+    async fn create_users_with_concurrency<S>(app: &S, concurrency: usize) -> Duration
+    where
+        S: actix_web::dev::Service<actix_http::Request, Response = actix_web::dev::ServiceResponse, Error = actix_web::Error>,
+    {
+        let started = Instant::now();
+        stream::iter(0..LOAD_TEST_REQUESTS)
+            .for_each_concurrent(concurrency, |n| async move {
+                let request = test::TestRequest::post()
+                    .uri("/create_user")
+                    .set_json(&UserProfileJsonREQUEST {
+                        email_address: format!("load-{}-{}@demo.com", n, Uuid::new_v4()),
+                        full_name: "Load Test".to_string(),
+                        age: 30,
+                        comment: None,
+                        ttl_secs: None,
+                        ..Default::default()
+                    })
+                    .to_request();
+                let response = test::call_service(app, request).await;
+                assert!(response.status().is_success());
+            })
+            .await;
+        started.elapsed()
+    }
+
+    async fn live_ycql_session() -> CurrentYcqlDbSession {
+        let config = ServiceConfig::load().expect("valid service configuration");
+        let authenticator = YcqlAuthenticatorRegistry::default()
+            .build_authenticator(&config.yugabyte.auth)
+            .expect("authenticator");
+        let session = configure_yugabyte_db_session(&config.yugabyte, authenticator).await.expect("YCQL session");
+        YcqlSchemaMigrator::new(&session, config.yugabyte.replication_factor, config.retention.default_ttl_secs)
+            .migrate_up()
+            .await
+            .expect("schema migrations");
+        session
+    }
+
+    /// Needs a running cluster configured the same way as the service: `cargo test -- --ignored`
+    #[actix_rt::test]
+    #[ignore]
+    async fn create_user_throughput_scales_with_concurrency() {
+        let session = live_ycql_session().await;
+        let app = test::init_service(
+            app_as(ops_admin())
+                .app_data(web::Data::new(YcqlUserProfileRepository::new(session)))
+                .app_data(web::Data::new(TimeUuidGenerator::new(None, Box::new(crate::time_uuid_generator::SystemClock))))
+                .configure(user_profile_routes::<YcqlUserProfileRepository>),
+        )
+        .await;
+
+        let sequential = create_users_with_concurrency(&app, 1).await;
+        let concurrent = create_users_with_concurrency(&app, 16).await;
+        let speedup = sequential.as_secs_f64() / concurrent.as_secs_f64();
+        assert!(
+            speedup > LOAD_TEST_MIN_SPEEDUP,
+            "expected concurrent requests to run in parallel: {} inserts took {:?} at concurrency 1 and {:?} at concurrency 16, a {:.1}x speedup below {}x",
+            LOAD_TEST_REQUESTS, sequential, concurrent, speedup, LOAD_TEST_MIN_SPEEDUP
+        );
+    }
+
+    /// Pins ***is_condition_not_satisfied*** to the error a real server sends for a failed ***ELSE ERROR*** condition.
+    /// Needs a running cluster configured the same way as the service: `cargo test -- --ignored`
+    #[actix_rt::test]
+    #[ignore]
+    async fn conditions_report_not_applied_on_a_live_cluster() {
+        let repository = YcqlUserProfileRepository::new(live_ycql_session().await);
+        let row = seeded_row(&format!("condition-{}@demo.com", Uuid::new_v4()));
+        assert_eq!(repository.create_user_profile(row.clone()).await.expect("create"), ConditionalWriteOutcome::Applied);
+
+        match repository.create_user_profile(row.clone()).await.expect("second create") {
+            ConditionalWriteOutcome::NotApplied { current: Some(current) } => assert_eq!(current.user_id, row.user_id),
+            outcome => panic!("expected the existing row back, got {:?}", outcome),
+        }
+        let stale = repository
+            .delete_user_profile(row.user_id, row.time_uuid_order, RowCondition::VersionMatches(Uuid::new_v4()))
+            .await
+            .expect("stale delete");
+        assert!(matches!(stale, ConditionalWriteOutcome::NotApplied { current: Some(_) }), "got {:?}", stale);
+
+        assert_eq!(
+            repository.delete_user_profile(row.user_id, row.time_uuid_order, RowCondition::Exists).await.expect("delete"),
+            ConditionalWriteOutcome::Applied
+        );
+        let missing = repository
+            .delete_user_profile(row.user_id, row.time_uuid_order, RowCondition::Exists)
+            .await
+            .expect("delete of a missing row");
+        assert_eq!(missing, ConditionalWriteOutcome::NotApplied { current: None });
+    }
+
+    /// Updates carry the remaining TTL forward instead of the table default.
+    /// Needs a running cluster configured the same way as the service: `cargo test -- --ignored`
+    #[actix_rt::test]
+    #[ignore]
+    async fn updates_keep_a_custom_ttl_on_a_live_cluster() {
+        let repository = YcqlUserProfileRepository::new(live_ycql_session().await);
+        let row = UserProfileRowStruct {
+            ttl_secs: Some(3600),
+            ..seeded_row(&format!("ttl-{}@demo.com", Uuid::new_v4()))
+        };
+        assert_eq!(repository.create_user_profile(row.clone()).await.expect("create"), ConditionalWriteOutcome::Applied);
+
+        let outcome = repository
+            .update_full_name_and_age(row.user_id, row.time_uuid_order, 27, "Jane Roe".to_string(), RowCondition::Exists, Uuid::new_v4())
+            .await
+            .expect("update");
+        assert_eq!(outcome, ConditionalWriteOutcome::Applied);
+
+        let updated = repository.read_user_profile_by_key(row.user_id, row.time_uuid_order).await.expect("read").expect("row");
+        assert_eq!((updated.age, updated.full_name.as_str()), (27, "Jane Roe"));
+        let ttl_secs = updated.ttl_secs.expect("the update kept an expiring row");
+        assert!((3580..=3600).contains(&ttl_secs), "{}", ttl_secs);
+        repository.delete_user_profile(row.user_id, row.time_uuid_order, RowCondition::Exists).await.expect("cleanup");
+    }
+}
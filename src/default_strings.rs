@@ -25,6 +25,12 @@ impl ServiceCustomCodes {
     pub const BAD_REQUEST_ERROR_CODE: &'static str = "444-PS-DBR";
     /// DEFAULT Unsupported Media Type error code for user reference
     pub const UNSUPPORTED_MEDIA_TYPE_ERROR_CODE: &'static str = "444-PS-UNSM";
+    /// DEFAULT database unavailable error code for user reference
+    pub const SERVICE_UNAVAILABLE_ERROR_CODE: &'static str = "444-PS-UNAV";
+    /// DEFAULT database timeout error code for user reference
+    pub const TIMEOUT_ERROR_CODE: &'static str = "444-PS-TMO";
+    /// DEFAULT code for a valid caller lacking the permission an operation needs
+    pub const ACCESS_FORBIDDEN_CODE: &'static str = "444-PS-FRB";
 }
 
 
@@ -39,14 +45,21 @@ pub enum UserErrorMessages {
     /// DEFAULT bad request message for user reference
     #[display(fmt = "Bad request REFERENCE CODE: {}", ServiceCustomCodes::BAD_REQUEST_ERROR_CODE)]
     BadClientData,
-    #[display(fmt = "timeout")]
+    /// DEFAULT database timeout message for user reference
+    #[display(fmt = "Request timed out, please try again REFERENCE CODE: {}", ServiceCustomCodes::TIMEOUT_ERROR_CODE)]
     Timeout,
+    /// DEFAULT database unavailable message for user reference
+    #[display(fmt = "Service temporarily unavailable REFERENCE CODE: {}", ServiceCustomCodes::SERVICE_UNAVAILABLE_ERROR_CODE)]
+    ServiceUnavailable,
     ///DEAFULT user field input error
     #[display(fmt = "Input error for reference check: {}", reason)]
     InputError{reason: String},
     ///DEFAULT jwt access denied message
     #[display(fmt = "Access Denied, reference code: {}", ServiceCustomCodes::JWT_DENIED_CODE)]  
     JwtAccessTokenError,
+    ///DEFAULT message for a caller lacking the permission an operation needs
+    #[display(fmt = "Access Denied, you do not have permission for this operation REFERENCE CODE: {}", ServiceCustomCodes::ACCESS_FORBIDDEN_CODE)]
+    AccessForbidden,
     ///DEFAULT masking message: Use this message if the origin of the error is unclear
     #[display(fmt = "Process terminated, REFERENCE CODE: {}", ServiceCustomCodes::INTERNAL_MASKING_ERROR_CODE)]  
     ForMaskingError
@@ -26,4 +26,34 @@ impl ResponseError for GenericJsonErrorResponse {
         web::HttpResponse::build(StatusCode::from_u16(self.status).unwrap())
             .json(err_json)
     }
-}
\ No newline at end of file
+}
+
+/// One rejected request field
+#[derive(Debug, Serialize)]
+pub struct FieldErrorResponse {
+  pub  field: String,
+  pub  reason: String,
+}
+
+/// Json ERROR RESPONSE for a request body that parsed but broke its ***#[validate]*** rules, lists every failing field
+#[derive(Debug, Serialize)]
+pub struct ValidationErrorResponse {
+  pub  custom_status: String,
+  pub  message: String,
+  pub  status: u16,
+  pub  field_errors: Vec<FieldErrorResponse>,
+}
+
+impl Display for ValidationErrorResponse {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", to_string_pretty(self).unwrap())
+    }
+}
+
+impl ResponseError for ValidationErrorResponse {
+    fn error_response(&self) -> web::HttpResponse {
+        let err_json = json!({"custom_status": self.custom_status, "message": self.message, "field_errors": self.field_errors});
+        web::HttpResponse::build(StatusCode::from_u16(self.status).unwrap())
+            .json(err_json)
+    }
+}
@@ -11,7 +11,17 @@ pub struct GenericJsonSuccessResponse<T> {
   pub  custom_status: String,
   pub  message: String,
   pub  status: u16,
-  pub  data: Option<T>
+  pub  data: Option<T>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub  pagination: Option<PaginationBlock>
+}
+
+/// Pagination block for list responses, send ***next_cursor*** back unchanged to get the following page
+#[derive(Debug, Serialize)]
+pub struct PaginationBlock {
+  pub  page_size: i32,
+  pub  next_cursor: Option<String>,
+  pub  has_more: bool
 }
 
 
@@ -21,7 +31,13 @@ impl<T> GenericJsonSuccessResponse<T> {
             custom_status,
             message,
             status,
-            data
+            data,
+            pagination: None
         }
     }
+
+   pub fn with_pagination(mut self, pagination: PaginationBlock) -> GenericJsonSuccessResponse<T> {
+       self.pagination = Some(pagination);
+       self
+    }
 }
\ No newline at end of file
@@ -0,0 +1,30 @@
+use crate::jwt_bearer_authentication::JwtClaims;
+use crate::user_profile_access_policy::{AccessControl, ADMIN_ROLE};
+use actix_web::body::Body;
+use actix_service::ServiceFactory;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::{web, App, Error, HttpMessage};
+
+/// App the handler tests build on, every request acts as ***claims*** as if JwtAuthentication had let it through
+/// and roles come from ***AccessControl::for_tests***
+pub(crate) fn app_as(
+    claims: JwtClaims,
+) -> App<impl ServiceFactory<ServiceRequest, Config = (), Response = ServiceResponse<Body>, Error = Error, InitError = ()>, Body> {
+    app_sharing_roles_as(AccessControl::for_tests(), claims)
+}
+
+/// Same as ***app_as*** with the roles kept in ***access_control***, so apps acting as different callers see each other's grants
+pub(crate) fn app_sharing_roles_as(
+    access_control: web::Data<AccessControl>,
+    claims: JwtClaims,
+) -> App<impl ServiceFactory<ServiceRequest, Config = (), Response = ServiceResponse<Body>, Error = Error, InitError = ()>, Body> {
+    App::new().app_data(access_control).wrap_fn(move |request, service| {
+        request.extensions_mut().insert(claims.clone());
+        service.call(request)
+    })
+}
+
+/// Operator holding the admin role, every handler lets it through
+pub(crate) fn ops_admin() -> JwtClaims {
+    JwtClaims::for_tests("ops@demo.com", &[ADMIN_ROLE])
+}
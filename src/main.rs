@@ -1,16 +1,55 @@
 #![allow(unused)]
 #[macro_use]
-
 mod generic_json_error_response;
 mod generic_json_success_response;
 mod crud_controller;
 mod default_strings;
 mod yugabyte_db_ycql_config;
+mod ycql_service_config;
+mod ycql_authenticators;
+mod ycql_prepared_statements;
+mod user_profile_repository;
+mod ycql_schema_migrations;
 mod ycql_db_schema_and_queries;
+mod validated_json_extractor;
+mod application_error;
+mod time_uuid_generator;
+mod user_profile_resource_controller;
+mod user_profile_batch_controller;
+mod jwt_bearer_authentication;
+mod user_profile_access_policy;
+mod user_role_repository;
+mod user_role_controller;
+mod user_profile_etag;
+mod api_key_repository;
+mod api_key_authentication;
+mod api_key_controller;
+mod user_credentials_repository;
+mod user_password_hashing;
+mod user_login_controller;
+#[cfg(test)]
+mod handler_test_app;
 
 use default_strings::{UserErrorMessages, CustomStatusMessage};
-use yugabyte_db_ycql_config::{configure_yugabyte_db_session, create_demo_ycql_service_keyspace, create_demo_ycql_user_profile_table};
-use crud_controller::{create_user_profile, read_user_profile, delete_user_profile, update_user_profile};
+use yugabyte_db_ycql_config::configure_yugabyte_db_session;
+use ycql_schema_migrations::YcqlSchemaMigrator;
+use ycql_service_config::ServiceConfig;
+use ycql_authenticators::YcqlAuthenticatorRegistry;
+use crud_controller::user_profile_routes;
+use user_profile_resource_controller::user_profile_resource_routes;
+use user_profile_batch_controller::user_profile_batch_routes;
+use jwt_bearer_authentication::{JwtAuthentication, JwtIssuer, JwtVerifier};
+use user_profile_access_policy::AccessControl;
+use user_role_controller::user_role_routes;
+use user_role_repository::YcqlRoleRepository;
+use api_key_authentication::{ApiKeyAuthentication, ApiKeyVerifier};
+use api_key_controller::api_key_routes;
+use api_key_repository::YcqlApiKeyRepository;
+use user_credentials_repository::YcqlCredentialRepository;
+use user_password_hashing::PasswordHashing;
+use user_login_controller::{user_credential_routes, user_login_routes};
+use user_profile_repository::YcqlUserProfileRepository;
+use time_uuid_generator::{parse_node_id, SystemClock, TimeUuidGenerator};
 use actix_web::middleware::{ErrorHandlers, ErrorHandlerResponse};
 use actix_web::{middleware, dev, get, error, http, post, web, App, HttpResponse, HttpServer, Responder};
 use actix_web::rt::System;
@@ -19,7 +58,6 @@ use validator::{Validate, ValidationError};
 use serde_json::{json, to_string_pretty};
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Mutex;
 
 #[derive(Debug, Serialize)]
 pub struct CustomJsonErrorResponse {
@@ -58,44 +96,206 @@ fn json_error_handler(err: error::JsonPayloadError, _req: &web::HttpRequest) ->
     error::InternalError::from_response(err, response).into()
 }
 
+/// Malformed path segments, eg a user_id that is not a uuid
+fn path_error_handler(err: error::PathError, _req: &web::HttpRequest) -> error::Error {
+    let response = web::HttpResponse::BadRequest()
+        .content_type("application/json")
+        .body(serde_json::to_string(&CustomJsonErrorResponse::json_error_response(CustomStatusMessage::USER_REQUEST_FAILED.to_string(), UserErrorMessages::BadClientData.to_string())).unwrap());
+    error::InternalError::from_response(err, response).into()
+}
+
+/// Malformed query strings, eg a page_size that is not a number
+fn query_error_handler(err: error::QueryPayloadError, _req: &web::HttpRequest) -> error::Error {
+    let response = web::HttpResponse::BadRequest()
+        .content_type("application/json")
+        .body(serde_json::to_string(&CustomJsonErrorResponse::json_error_response(CustomStatusMessage::USER_REQUEST_FAILED.to_string(), UserErrorMessages::BadClientData.to_string())).unwrap());
+    error::InternalError::from_response(err, response).into()
+}
+
+
+/// What the binary was asked to do on the command line
+enum ServiceCommand {
+    /// No arguments, run the HTTP server
+    Serve,
+    /// ***migrate up***
+    MigrateUp,
+    /// ***migrate status***
+    MigrateStatus,
+}
+
+impl ServiceCommand {
+    fn from_args(args: &[String]) -> Result<ServiceCommand, std::io::Error> {
+        match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+            [] => Ok(ServiceCommand::Serve),
+            ["migrate", "up"] => Ok(ServiceCommand::MigrateUp),
+            ["migrate", "status"] => Ok(ServiceCommand::MigrateStatus),
+            _ => {
+                eprintln!("usage: yugabytedbrust [migrate up|migrate status]");
+                Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "unknown command"))
+            }
+        }
+    }
+}
 
 #[actix_web::main]
 async fn main() -> Result<(), std::io::Error> {
-    std::env::set_var("RUST_LOG", "actix_web=info");
+    std::env::set_var("RUST_LOG", "actix_web=info,yugabytedbrust=info");
     env_logger::init();
 
-    let mut yugabyte_db_connection = configure_yugabyte_db_session().await.expect("Fatal YCQL session DB connection Error");
+    let command = ServiceCommand::from_args(&std::env::args().skip(1).collect::<Vec<_>>())?;
+
+    let service_config = ServiceConfig::load().map_err(|e| {
+        eprintln!("Fatal configuration error: {}", e);
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, e)
+    })?;
+
+    // an unreadable RS256 public key stops the service before it opens any YCQL connection
+    let jwt_verifier = std::sync::Arc::new(JwtVerifier::from_config(&service_config.jwt).map_err(|e| {
+        eprintln!("Fatal configuration error: {}", e);
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, e)
+    })?);
+
+    // None for RS256 without a private key, /auth is then not mounted
+    let jwt_issuer = JwtIssuer::from_config(&service_config.jwt, &service_config.login).map_err(|e| {
+        eprintln!("Fatal configuration error: {}", e);
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, e)
+    })?;
+    let password_hashing = web::Data::new(PasswordHashing::new(argon2::Params::DEFAULT).map_err(|e| {
+        eprintln!("Fatal password hashing error: {}", e);
+        std::io::Error::other(e.to_string())
+    })?);
+
+    // register custom SASL authenticator providers here before building the authenticator
+    let authenticator_registry = YcqlAuthenticatorRegistry::default();
+    let authenticator = authenticator_registry.build_authenticator(&service_config.yugabyte.auth).map_err(|e| {
+        eprintln!("Fatal configuration error: {}", e);
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, e)
+    })?;
+
+    let yugabyte_db_connection = configure_yugabyte_db_session(&service_config.yugabyte, authenticator).await.map_err(|e| {
+        eprintln!("Fatal YCQL session DB connection Error: {}", e);
+        std::io::Error::new(std::io::ErrorKind::ConnectionRefused, e.to_string())
+    })?;
  
 
-    create_demo_ycql_service_keyspace(&mut yugabyte_db_connection).await;
-    create_demo_ycql_user_profile_table(&mut yugabyte_db_connection).await;
+    let migrator = YcqlSchemaMigrator::new(
+        &yugabyte_db_connection,
+        service_config.yugabyte.replication_factor,
+        service_config.retention.default_ttl_secs,
+    );
+    match command {
+        ServiceCommand::MigrateUp => {
+            let report = migrator.migrate_up().await.map_err(|e| {
+                eprintln!("Migration failed: {}", e);
+                std::io::Error::other(e.to_string())
+            })?;
+            print!("{}", report);
+            return Ok(());
+        }
+        ServiceCommand::MigrateStatus => {
+            let report = migrator.status().await.map_err(|e| {
+                eprintln!("Could not read migration status: {}", e);
+                std::io::Error::other(e.to_string())
+            })?;
+            print!("{}", report);
+            let ttl_mismatch = migrator.default_ttl_mismatch().await.map_err(|e| {
+                eprintln!("Could not read the user_profile table options: {}", e);
+                std::io::Error::other(e.to_string())
+            })?;
+            if let Some(mismatch) = ttl_mismatch {
+                println!("{}, `yugabytedbrust migrate up` applies it", mismatch);
+            }
+            return Ok(());
+        }
+        ServiceCommand::Serve => {
+            let report = migrator.status().await.map_err(|e| {
+                eprintln!("Could not read migration status: {}", e);
+                std::io::Error::other(e.to_string())
+            })?;
+            if !report.is_up_to_date() {
+                eprint!("Refusing to start, schema migrations are not up to date:\n{}Run `yugabytedbrust migrate up` first.\n", report);
+                return Err(std::io::Error::other("schema migrations are pending or failed"));
+            }
+            let ttl_mismatch = migrator.default_ttl_mismatch().await.map_err(|e| {
+                eprintln!("Could not read the user_profile table options: {}", e);
+                std::io::Error::other(e.to_string())
+            })?;
+            if let Some(mismatch) = ttl_mismatch {
+                eprintln!("Refusing to start, {}.\nRun `yugabytedbrust migrate up` to apply the configured value.", mismatch);
+                return Err(std::io::Error::other("user_profile default_time_to_live differs from the configuration"));
+            }
+        }
+    }
+
+    yugabyte_db_connection.prepare_user_profile_statements().await.map_err(|e| {
+        eprintln!("Fatal YCQL prepare statement Error: {}", e);
+        std::io::Error::other(e.to_string())
+    })?;
   
 
-    #[allow(clippy::mutex_atomic)]
-    let ycql_connection_data = web::Data::new(Mutex::new(yugabyte_db_connection));
+    // cloneable session handle, every worker and request shares the same driver pools without locking
+    let access_control = web::Data::new(AccessControl::new(
+        std::sync::Arc::new(YcqlRoleRepository::new(yugabyte_db_connection.clone())),
+        service_config.access_control.role_cache_ttl(),
+    ));
+    let api_key_repository = std::sync::Arc::new(YcqlApiKeyRepository::new(yugabyte_db_connection.clone()));
+    let api_key_verifier = std::sync::Arc::new(ApiKeyVerifier::new(api_key_repository.clone()));
+    let api_key_data = web::Data::from(api_key_repository);
+    let credential_data = web::Data::new(YcqlCredentialRepository::new(yugabyte_db_connection.clone()));
+    let jwt_issuer_data = jwt_issuer.map(web::Data::new);
+    let ycql_connection_data = web::Data::new(YcqlUserProfileRepository::new(yugabyte_db_connection));
+    let pagination_config = web::Data::new(service_config.pagination.clone());
+    let batch_config = service_config.batch.clone();
+    // node_id was checked by ServiceConfig::validate
+    let time_uuid_generator = web::Data::new(TimeUuidGenerator::new(
+        service_config.time_uuid.node_id.as_deref().and_then(parse_node_id),
+        Box::new(SystemClock),
+    ));
 
 
     HttpServer::new(move || {
         App::new()
         .app_data(ycql_connection_data.clone()) // add shared state
+        .app_data(pagination_config.clone())
+        .app_data(time_uuid_generator.clone())
+        // one role cache shared by every worker, so assignments invalidate it everywhere in this process
+        .app_data(access_control.clone())
+        .app_data(api_key_data.clone())
+        .app_data(credential_data.clone())
+        .app_data(password_hashing.clone())
         .app_data(web::JsonConfig::default()
         // limit request payload size
         .limit(4096)
         // use custom error handler
         .error_handler(json_error_handler)
         )
+        .app_data(web::PathConfig::default().error_handler(path_error_handler))
+        .app_data(web::QueryConfig::default().error_handler(query_error_handler))
             // enable logger
             .wrap(middleware::Logger::default())
+        // login, refresh and logout need no token, only mounted when the service can sign tokens itself
+        .configure(|cfg| {
+            if let Some(jwt_issuer) = &jwt_issuer_data {
+                cfg.app_data(jwt_issuer.clone())
+                    .service(web::scope("/auth").configure(user_login_routes::<YcqlUserProfileRepository, YcqlCredentialRepository>));
+            }
+        })
         .service(
             web::scope("/api_v1")
-            .service(create_user_profile)
-            .service(read_user_profile)
-            .service(delete_user_profile)
-            .service(update_user_profile)
+            // every route below needs a valid bearer token or X-Api-Key, the last wrap runs first
+            .wrap(JwtAuthentication::new(jwt_verifier.clone()))
+            .wrap(ApiKeyAuthentication::new(api_key_verifier.clone()))
+            .configure(user_profile_routes::<YcqlUserProfileRepository>)
+            // registered with its own JsonConfig, the 4096 bytes limit above does not apply to it
+            .configure(user_profile_batch_routes::<YcqlUserProfileRepository>(batch_config.clone()))
+            .service(web::scope("/users").configure(user_profile_resource_routes::<YcqlUserProfileRepository>))
+            .configure(user_role_routes)
+            .configure(api_key_routes::<YcqlApiKeyRepository>)
+            .configure(user_credential_routes::<YcqlUserProfileRepository, YcqlCredentialRepository>)
 
         )    
     })
-    .bind("127.0.0.1:4055")?
+    .bind(&service_config.server.bind_address)?
     .run()
     .await
 }
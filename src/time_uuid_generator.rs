@@ -0,0 +1,150 @@
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::v1::Timestamp;
+use uuid::Uuid;
+
+/// 100ns intervals between the RFC 4122 epoch (15 Oct 1582) and the Unix epoch
+const RFC4122_TICKS_BEFORE_UNIX_EPOCH: u64 = 0x01B2_1DD2_1381_4000;
+/// The clock sequence field holds 14 bits
+const CLOCK_SEQUENCE_MASK: u16 = 0x3FFF;
+
+/// Source of wall clock time, swapped for a fixed clock in tests
+pub trait Clock: Send + Sync + 'static {
+    /// Time elapsed since the Unix epoch
+    fn since_unix_epoch(&self) -> Duration;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn since_unix_epoch(&self) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+    }
+}
+
+/// Always reports the same instant, for deterministic ids in tests
+pub struct FixedClock(pub Duration);
+
+impl Clock for FixedClock {
+    fn since_unix_epoch(&self) -> Duration {
+        self.0
+    }
+}
+
+struct GeneratorState {
+    last_ticks: u64,
+}
+
+/// Version 1 UUID (TimeUUID) generator shared by every handler.
+/// Timestamps have 100ns resolution and are strictly increasing per generator: when the clock has not moved
+/// (or went backwards) the next free tick is used, so two calls never produce the same or an older TimeUUID.
+/// The clock sequence is random per process, which keeps ids unique across restarts even if the clock was set back.
+pub struct TimeUuidGenerator {
+    node_id: [u8; 6],
+    clock_sequence: u16,
+    clock: Box<dyn Clock>,
+    state: Mutex<GeneratorState>,
+}
+
+impl TimeUuidGenerator {
+    /// ***node_id*** None picks a random node id with the multicast bit set, as RFC 4122 asks for ids not taken from a MAC address
+    pub fn new(node_id: Option<[u8; 6]>, clock: Box<dyn Clock>) -> TimeUuidGenerator {
+        let random = Uuid::new_v4();
+        let random = random.as_bytes();
+        let node_id = node_id.unwrap_or_else(|| {
+            let mut node_id = [0u8; 6];
+            node_id.copy_from_slice(&random[..6]);
+            node_id[0] |= 0x01;
+            node_id
+        });
+        let clock_sequence = u16::from_be_bytes([random[6], random[7]]) & CLOCK_SEQUENCE_MASK;
+        TimeUuidGenerator::with_clock_sequence(node_id, clock_sequence, clock)
+    }
+
+    /// Fully deterministic generator, meant for tests
+    pub fn with_clock_sequence(node_id: [u8; 6], clock_sequence: u16, clock: Box<dyn Clock>) -> TimeUuidGenerator {
+        TimeUuidGenerator {
+            node_id,
+            clock_sequence: clock_sequence & CLOCK_SEQUENCE_MASK,
+            clock,
+            state: Mutex::new(GeneratorState { last_ticks: 0 }),
+        }
+    }
+
+    pub fn generate(&self) -> Uuid {
+        let elapsed = self.clock.since_unix_epoch();
+        let now_ticks = RFC4122_TICKS_BEFORE_UNIX_EPOCH + elapsed.as_secs() * 10_000_000 + u64::from(elapsed.subsec_nanos()) / 100;
+
+        let ticks = {
+            let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let ticks = now_ticks.max(state.last_ticks + 1);
+            state.last_ticks = ticks;
+            ticks
+        };
+
+        Uuid::new_v1(Timestamp::from_rfc4122(ticks, self.clock_sequence), &self.node_id)
+            .expect("node id is always 6 bytes")
+    }
+}
+
+/// Parse a node id written as 12 hex digits, optionally separated by ***:*** or ***-***
+pub fn parse_node_id(value: &str) -> Option<[u8; 6]> {
+    let digits: String = value.chars().filter(|c| *c != ':' && *c != '-').collect();
+    if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut node_id = [0u8; 6];
+    for (i, byte) in node_id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(node_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticks(time_uuid: &Uuid) -> u64 {
+        let bytes = time_uuid.as_bytes();
+        u64::from(bytes[6] & 0x0F) << 56
+            | u64::from(bytes[7]) << 48
+            | u64::from(bytes[4]) << 40
+            | u64::from(bytes[5]) << 32
+            | u64::from(bytes[0]) << 24
+            | u64::from(bytes[1]) << 16
+            | u64::from(bytes[2]) << 8
+            | u64::from(bytes[3])
+    }
+
+    #[test]
+    fn ids_from_the_same_instant_are_distinct_and_increasing() {
+        let generator = TimeUuidGenerator::with_clock_sequence([0x01, 2, 3, 4, 5, 6], 7, Box::new(FixedClock(Duration::from_secs(1_600_000_000))));
+        let ids: Vec<Uuid> = (0..1000).map(|_| generator.generate()).collect();
+        for pair in ids.windows(2) {
+            assert!(ticks(&pair[0]) < ticks(&pair[1]));
+        }
+        assert_eq!(ids[0].get_version_num(), 1);
+        assert_eq!(&ids[0].as_bytes()[10..], &[0x01, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn timestamp_keeps_sub_millisecond_resolution() {
+        let generator = TimeUuidGenerator::with_clock_sequence([0x01, 2, 3, 4, 5, 6], 0, Box::new(FixedClock(Duration::new(1_600_000_000, 123_456_700))));
+        let expected = RFC4122_TICKS_BEFORE_UNIX_EPOCH + 1_600_000_000 * 10_000_000 + 1_234_567;
+        assert_eq!(ticks(&generator.generate()), expected);
+    }
+
+    #[test]
+    fn random_node_ids_set_the_multicast_bit() {
+        let generator = TimeUuidGenerator::new(None, Box::new(SystemClock));
+        assert_eq!(generator.generate().as_bytes()[10] & 0x01, 0x01);
+    }
+
+    #[test]
+    fn parse_node_id_accepts_separators() {
+        assert_eq!(parse_node_id("02:42:ac:11:00:02"), Some([0x02, 0x42, 0xac, 0x11, 0x00, 0x02]));
+        assert_eq!(parse_node_id("0242ac110002"), Some([0x02, 0x42, 0xac, 0x11, 0x00, 0x02]));
+        assert_eq!(parse_node_id("02:42:ac"), None);
+        assert_eq!(parse_node_id("zz42ac110002"), None);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::application_error::AppResult;
+use crate::ycql_db_schema_and_queries::{
+    delete_refresh_token_query, delete_refresh_tokens_query, insert_refresh_token_query, select_user_credentials_query,
+    upsert_user_credentials_query, UserCredentialsRowStruct,
+};
+use crate::yugabyte_db_ycql_config::CurrentYcqlDbSession;
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Storage of ***user_credentials*** and the ***refresh_tokens*** sessions, implemented for YCQL and for in-memory tests
+#[async_trait]
+pub trait CredentialRepository: Send + Sync + 'static {
+    /// Writes the row, replacing the password hash stored for the same email address
+    async fn store_credentials(&self, credentials_row: UserCredentialsRowStruct) -> AppResult<()>;
+    async fn read_credentials(&self, email_address: &str) -> AppResult<Option<UserCredentialsRowStruct>>;
+    /// The session expires with the refresh token after ***ttl_secs***
+    async fn store_refresh_token(&self, user_id: Uuid, token_id: Uuid, email_address: &str, ttl_secs: i32) -> AppResult<()>;
+    /// True for the one call that removed the session, false when it was already gone
+    async fn delete_refresh_token(&self, user_id: Uuid, token_id: Uuid) -> AppResult<bool>;
+    /// Ends every session of the user
+    async fn delete_refresh_tokens(&self, user_id: Uuid) -> AppResult<()>;
+}
+
+/// CredentialRepository backed by the YCQL query functions
+#[derive(Clone)]
+pub struct YcqlCredentialRepository {
+    ycql_session: CurrentYcqlDbSession,
+}
+
+impl YcqlCredentialRepository {
+    pub fn new(ycql_session: CurrentYcqlDbSession) -> YcqlCredentialRepository {
+        YcqlCredentialRepository { ycql_session }
+    }
+}
+
+#[async_trait]
+impl CredentialRepository for YcqlCredentialRepository {
+    async fn store_credentials(&self, credentials_row: UserCredentialsRowStruct) -> AppResult<()> {
+        Ok(upsert_user_credentials_query(&self.ycql_session, credentials_row).await?)
+    }
+
+    async fn read_credentials(&self, email_address: &str) -> AppResult<Option<UserCredentialsRowStruct>> {
+        Ok(select_user_credentials_query(&self.ycql_session, email_address).await?)
+    }
+
+    async fn store_refresh_token(&self, user_id: Uuid, token_id: Uuid, email_address: &str, ttl_secs: i32) -> AppResult<()> {
+        Ok(insert_refresh_token_query(&self.ycql_session, user_id, token_id, email_address, ttl_secs).await?)
+    }
+
+    async fn delete_refresh_token(&self, user_id: Uuid, token_id: Uuid) -> AppResult<bool> {
+        Ok(delete_refresh_token_query(&self.ycql_session, user_id, token_id).await?)
+    }
+
+    async fn delete_refresh_tokens(&self, user_id: Uuid) -> AppResult<()> {
+        Ok(delete_refresh_tokens_query(&self.ycql_session, user_id).await?)
+    }
+}
+
+/// CredentialRepository kept in process memory, sessions carry the deadline their ***ttl_secs*** sets
+#[derive(Default)]
+pub struct InMemoryCredentialRepository {
+    credentials: Mutex<HashMap<String, UserCredentialsRowStruct>>,
+    refresh_tokens: Mutex<BTreeMap<(Uuid, Uuid), Instant>>,
+}
+
+impl InMemoryCredentialRepository {
+    /// Expired sessions are dropped first, like YCQL never returning them
+    fn refresh_tokens(&self) -> std::sync::MutexGuard<'_, BTreeMap<(Uuid, Uuid), Instant>> {
+        let mut refresh_tokens = self.refresh_tokens.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+        refresh_tokens.retain(|_, expires_at| *expires_at > now);
+        refresh_tokens
+    }
+
+    /// Live sessions of the user, for tests
+    #[cfg(test)]
+    pub(crate) fn session_count(&self, user_id: Uuid) -> usize {
+        self.refresh_tokens().keys().filter(|(session_user_id, _)| *session_user_id == user_id).count()
+    }
+}
+
+#[async_trait]
+impl CredentialRepository for InMemoryCredentialRepository {
+    async fn store_credentials(&self, credentials_row: UserCredentialsRowStruct) -> AppResult<()> {
+        let mut credentials = self.credentials.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        credentials.insert(credentials_row.email_address.clone(), credentials_row);
+        Ok(())
+    }
+
+    async fn read_credentials(&self, email_address: &str) -> AppResult<Option<UserCredentialsRowStruct>> {
+        let credentials = self.credentials.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Ok(credentials.get(email_address).cloned())
+    }
+
+    async fn store_refresh_token(&self, user_id: Uuid, token_id: Uuid, _email_address: &str, ttl_secs: i32) -> AppResult<()> {
+        let expires_at = Instant::now() + Duration::from_secs(ttl_secs.max(0) as u64);
+        self.refresh_tokens().insert((user_id, token_id), expires_at);
+        Ok(())
+    }
+
+    async fn delete_refresh_token(&self, user_id: Uuid, token_id: Uuid) -> AppResult<bool> {
+        Ok(self.refresh_tokens().remove(&(user_id, token_id)).is_some())
+    }
+
+    async fn delete_refresh_tokens(&self, user_id: Uuid) -> AppResult<()> {
+        self.refresh_tokens().retain(|(session_user_id, _), _| *session_user_id != user_id);
+        Ok(())
+    }
+}
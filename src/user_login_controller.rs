@@ -0,0 +1,395 @@
+use crate::application_error::AppError;
+use crate::crud_controller::{deserialize_email_address, written_response};
+use crate::default_strings::CustomStatusMessage;
+use crate::generic_json_success_response::GenericJsonSuccessResponse;
+use crate::jwt_bearer_authentication::{IssuedTokens, JwtIssuer};
+use crate::user_credentials_repository::CredentialRepository;
+use crate::user_password_hashing::PasswordHashing;
+use crate::user_profile_access_policy::{AuthorizedCaller, Permission};
+use crate::user_profile_repository::UserProfileRepository;
+use crate::validated_json_extractor::ValidatedJson;
+use crate::ycql_db_schema_and_queries::UserCredentialsRowStruct;
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Login, refresh and single session logout, mounted outside ***/api_v1*** since callers have no token yet.
+/// Needs ***web::Data*** of R, C, ***JwtIssuer*** and ***PasswordHashing***
+pub fn user_login_routes<R: UserProfileRepository, C: CredentialRepository>(cfg: &mut web::ServiceConfig) {
+    cfg.route("/login", web::post().to(login::<R, C>))
+        .route("/refresh", web::post().to(refresh::<C>))
+        .route("/logout", web::post().to(logout::<C>));
+}
+
+/// Password management and logging out every session, mounted inside the protected ***/api_v1*** scope
+pub fn user_credential_routes<R: UserProfileRepository, C: CredentialRepository>(cfg: &mut web::ServiceConfig) {
+    cfg.route("/set_user_credentials", web::post().to(set_user_credentials::<R, C>))
+        .route("/logout_all_sessions", web::post().to(logout_all_sessions::<C>));
+}
+
+fn success_response<T: Serialize>(message: &str, data: T) -> web::HttpResponse {
+    web::HttpResponse::Ok()
+    .content_type("application/json")
+    .body(serde_json::to_string(
+        &GenericJsonSuccessResponse::<T>::generic_success_response(CustomStatusMessage::USER_REQUEST_SUCCESS.to_string(), message.to_string(), 200, Some(data))
+    ).unwrap())
+}
+
+/// A new session: the tokens plus the ***refresh_tokens*** row that makes the refresh token usable
+async fn start_session<C: CredentialRepository>(
+    credential_repository: &C,
+    jwt_issuer: &JwtIssuer,
+    user_id: Uuid,
+    email_address: &str
+)-> Result<IssuedTokens, AppError>{
+
+    let tokens = jwt_issuer.issue(user_id, email_address)?;
+    credential_repository
+        .store_refresh_token(user_id, tokens.refresh_token_id, email_address, jwt_issuer.refresh_token_ttl_secs())
+        .await?;
+    Ok(tokens)
+}
+
+///Json Request Struct for |login| FUNCTION, no Debug so the password never reaches a log
+#[derive(Validate, Deserialize)]
+pub struct LoginJsonREQUEST {
+    #[validate(email(message = "must be a valid email address"))]
+    #[serde(deserialize_with = "deserialize_email_address")]
+    email_address: String,
+    #[validate(length(min = 1, max = 128, message = "must be between 1 and 128 characters"))]
+    password: String,
+}
+
+/// Unknown emails, wrong passwords and emails no longer owned by the profile all answer the same 401
+pub async fn login<R: UserProfileRepository, C: CredentialRepository>(
+    user_profile_repository: web::Data<R>,
+    credential_repository: web::Data<C>,
+    jwt_issuer: web::Data<JwtIssuer>,
+    password_hashing: web::Data<PasswordHashing>,
+    get_login: ValidatedJson<LoginJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    let credentials = credential_repository.read_credentials(&get_login.email_address).await?;
+    let stored_hash = credentials.as_ref().map(|credentials| credentials.password_hash.clone());
+    // verified even without credentials, against the dummy hash
+    let verified = password_hashing.verify(&get_login.password, stored_hash).await?;
+    let user_id = match credentials {
+        Some(credentials) if verified => credentials.user_id,
+        _ => return Err(AppError::denied(&format!("wrong email address or password for {}", get_login.email_address))),
+    };
+    // the email may have moved to another profile or the profile may be gone since the password was set
+    let owner = user_profile_repository.find_by_email(get_login.email_address.to_string()).await?;
+    if owner.map(|profile| profile.user_id) != Some(user_id) {
+        return Err(AppError::denied(&format!("{} no longer belongs to user {}", get_login.email_address, user_id)));
+    }
+
+    let tokens = start_session(credential_repository.get_ref(), &jwt_issuer, user_id, &get_login.email_address).await?;
+    log::info!("login: user_id={} session={}", user_id, tokens.refresh_token_id);
+
+    Ok(success_response("Successfuly logged in", tokens))
+}
+
+///Json Request Struct for |refresh| and |logout| FUNCTIONS
+#[derive(Validate, Deserialize)]
+pub struct RefreshTokenJsonREQUEST {
+    #[validate(length(min = 1, max = 4096, message = "must be between 1 and 4096 characters"))]
+    refresh_token: String,
+}
+
+/// Rotation: the refresh token is used up and a new pair is issued for the same session owner.
+/// A valid token whose row is gone was used already, or revoked, so every session of the user ends
+pub async fn refresh<C: CredentialRepository>(
+    credential_repository: web::Data<C>,
+    jwt_issuer: web::Data<JwtIssuer>,
+    get_refresh: ValidatedJson<RefreshTokenJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    let claims = jwt_issuer.verify_refresh_token(&get_refresh.refresh_token)?;
+    if !credential_repository.delete_refresh_token(claims.user_id, claims.token_id).await? {
+        credential_repository.delete_refresh_tokens(claims.user_id).await?;
+        log::warn!("refresh token reused or revoked: user_id={} session={}, every session revoked", claims.user_id, claims.token_id);
+        return Err(AppError::denied(&format!("refresh token {} was already used or revoked", claims.token_id)));
+    }
+
+    let tokens = start_session(credential_repository.get_ref(), &jwt_issuer, claims.user_id, &claims.email_address).await?;
+    log::info!("refresh: user_id={} session={} replaced_by={}", claims.user_id, claims.token_id, tokens.refresh_token_id);
+
+    Ok(success_response("Successfuly refreshed tokens", tokens))
+}
+
+/// Ends the session of the refresh token, logging out twice is not an error.
+/// Access tokens already issued stay valid until their ***exp***
+pub async fn logout<C: CredentialRepository>(
+    credential_repository: web::Data<C>,
+    jwt_issuer: web::Data<JwtIssuer>,
+    get_logout: ValidatedJson<RefreshTokenJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    let claims = jwt_issuer.verify_refresh_token(&get_logout.refresh_token)?;
+    credential_repository.delete_refresh_token(claims.user_id, claims.token_id).await?;
+    log::info!("logout: user_id={} session={}", claims.user_id, claims.token_id);
+
+    Ok(written_response("Successfuly logged out", None))
+}
+
+///Json Request Struct for |set_user_credentials| FUNCTION, no Debug so the password never reaches a log
+#[derive(Validate, Deserialize)]
+pub struct SetUserCredentialsJsonREQUEST {
+    user_id: Uuid,
+    /// Must be the email address of the user's profile
+    #[validate(email(message = "must be a valid email address"))]
+    #[serde(deserialize_with = "deserialize_email_address")]
+    email_address: String,
+    #[validate(length(min = 12, max = 128, message = "must be between 12 and 128 characters"))]
+    password: String,
+    /// Needed when the owner replaces its own password, a stolen access token alone must not take the account over
+    #[validate(length(min = 1, max = 128, message = "must be between 1 and 128 characters"))]
+    current_password: Option<String>,
+}
+
+/// Sets or replaces the password of a profile's email address and ends every session of the user.
+/// An owner without credentials:manage through a role proves the current password, an admin does not
+pub async fn set_user_credentials<R: UserProfileRepository, C: CredentialRepository>(
+    caller: AuthorizedCaller,
+    user_profile_repository: web::Data<R>,
+    credential_repository: web::Data<C>,
+    password_hashing: web::Data<PasswordHashing>,
+    get_credentials: ValidatedJson<SetUserCredentialsJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    caller.require(Permission::ManageCredentials, Some(get_credentials.user_id)).await?;
+    let owner = user_profile_repository.find_by_email(get_credentials.email_address.to_string()).await?;
+    if owner.map(|profile| profile.user_id) != Some(get_credentials.user_id) {
+        return Err(AppError::bad_request("email_address is not the email address of this user's profile"));
+    }
+    let is_owner = Uuid::parse_str(&caller.subject()).ok() == Some(get_credentials.user_id);
+    if is_owner && caller.decide(Permission::ManageCredentials, None).await?.granted_by().is_none() {
+        let stored_hash = credential_repository
+            .read_credentials(&get_credentials.email_address)
+            .await?
+            .filter(|credentials| credentials.user_id == get_credentials.user_id)
+            .map(|credentials| credentials.password_hash);
+        // a first password has nothing to prove
+        if let Some(stored_hash) = stored_hash {
+            let current_password = get_credentials.current_password.as_deref().unwrap_or_default();
+            if !password_hashing.verify(current_password, Some(stored_hash)).await? {
+                return Err(AppError::denied(&format!("wrong current password for user {}", get_credentials.user_id)));
+            }
+        }
+    }
+
+    let password_hash = password_hashing.hash(&get_credentials.password).await?;
+    credential_repository
+        .store_credentials(UserCredentialsRowStruct {
+            email_address: get_credentials.email_address.to_string(),
+            user_id: get_credentials.user_id,
+            password_hash,
+        })
+        .await?;
+    credential_repository.delete_refresh_tokens(get_credentials.user_id).await?;
+    log::info!("credentials set: user_id={} by={}", get_credentials.user_id, caller.subject());
+
+    Ok(written_response("Successfuly set credentials", None))
+}
+
+///Json Request Struct for |logout_all_sessions| FUNCTION, leave out ***user_id*** for the caller's own sessions
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct LogoutAllSessionsJsonREQUEST {
+    user_id: Option<Uuid>,
+}
+
+/// Revokes every refresh token of the user, access tokens already issued stay valid until their ***exp***
+pub async fn logout_all_sessions<C: CredentialRepository>(
+    caller: AuthorizedCaller,
+    credential_repository: web::Data<C>,
+    get_user: ValidatedJson<LogoutAllSessionsJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    let user_id = match get_user.user_id {
+        Some(user_id) => user_id,
+        None => Uuid::parse_str(&caller.subject())
+            .map_err(|_| AppError::bad_request("the caller is not a user, send the user_id whose sessions should end"))?,
+    };
+    caller.require(Permission::UpdateProfile, Some(user_id)).await?;
+    credential_repository.delete_refresh_tokens(user_id).await?;
+    log::info!("every session revoked: user_id={} by={}", user_id, caller.subject());
+
+    Ok(written_response("Successfuly logged out every session", None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler_test_app::{app_as, ops_admin};
+    use crate::jwt_bearer_authentication::JwtClaims;
+    use crate::user_credentials_repository::InMemoryCredentialRepository;
+    use crate::user_profile_access_policy::SUPPORT_ROLE;
+    use crate::user_profile_repository::InMemoryUserProfileRepository;
+    use crate::ycql_db_schema_and_queries::{RowCondition, UserProfileRowStruct};
+    use crate::ycql_service_config::{JwtConfig, JwtKeyConfig, LoginConfig};
+    use actix_web::{http::StatusCode, test};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    macro_rules! in_memory_login_app {
+        ($profiles:expr, $credentials:expr, $claims:expr) => {{
+            let jwt_config = JwtConfig {
+                key: JwtKeyConfig::Hs256 { secret: "an HS256 test secret of 32+ bytes".to_string() },
+                ..JwtConfig::default()
+            };
+            test::init_service(
+                app_as($claims)
+                    .app_data(web::Data::from($profiles.clone()))
+                    .app_data(web::Data::from($credentials.clone()))
+                    .app_data(web::Data::new(JwtIssuer::from_config(&jwt_config, &LoginConfig::default()).unwrap().unwrap()))
+                    .app_data(web::Data::new(PasswordHashing::for_tests()))
+                    .service(web::scope("/auth").configure(user_login_routes::<InMemoryUserProfileRepository, InMemoryCredentialRepository>))
+                    .service(web::scope("/api_v1").configure(user_credential_routes::<InMemoryUserProfileRepository, InMemoryCredentialRepository>)),
+            )
+            .await
+        }};
+    }
+
+    #[actix_rt::test]
+    async fn login_rotates_refresh_tokens_and_reuse_ends_every_session() {
+        let profiles = Arc::new(InMemoryUserProfileRepository::default());
+        let credentials = Arc::new(InMemoryCredentialRepository::default());
+        let user_id = Uuid::new_v4();
+        profiles
+            .create_user_profile(UserProfileRowStruct::build_user_profile(
+                user_id,
+                Uuid::new_v4(),
+                "jane@demo.com".to_string(),
+                "Jane Doe".to_string(),
+                30,
+                None,
+            ))
+            .await
+            .unwrap();
+        let owner_app = in_memory_login_app!(profiles, credentials, JwtClaims::for_tests(&user_id.to_string(), &[]));
+        let stranger_app = in_memory_login_app!(profiles, credentials, JwtClaims::for_tests(&Uuid::new_v4().to_string(), &[]));
+        let support_app = in_memory_login_app!(profiles, credentials, JwtClaims::for_tests("support@demo.com", &[SUPPORT_ROLE]));
+        let admin_app = in_memory_login_app!(profiles, credentials, ops_admin());
+
+        let set = json!({"user_id": user_id, "email_address": "jane@demo.com", "password": "correct horse battery"});
+        let request = test::TestRequest::post().uri("/api_v1/set_user_credentials").set_json(&set).to_request();
+        assert_eq!(test::call_service(&stranger_app, request).await.status(), StatusCode::FORBIDDEN);
+        // support may update the profile but setting its password would let it log in as the user
+        let request = test::TestRequest::post().uri("/api_v1/set_user_credentials").set_json(&set).to_request();
+        assert_eq!(test::call_service(&support_app, request).await.status(), StatusCode::FORBIDDEN);
+        let request = test::TestRequest::post().uri("/api_v1/set_user_credentials").set_json(&set).to_request();
+        assert_eq!(test::call_service(&admin_app, request).await.status(), StatusCode::OK);
+        let request = test::TestRequest::post()
+            .uri("/api_v1/set_user_credentials")
+            .set_json(&json!({"user_id": user_id, "email_address": "someone@demo.com", "password": "correct horse battery"}))
+            .to_request();
+        assert_eq!(test::call_service(&owner_app, request).await.status(), StatusCode::BAD_REQUEST);
+        // a bearer token alone does not let the owner replace an existing password
+        for current_password in [None, Some("wrong horse battery")] {
+            let request = test::TestRequest::post()
+                .uri("/api_v1/set_user_credentials")
+                .set_json(&json!({"user_id": user_id, "email_address": "jane@demo.com", "password": "stolen token password", "current_password": current_password}))
+                .to_request();
+            assert_eq!(test::call_service(&owner_app, request).await.status(), StatusCode::UNAUTHORIZED);
+        }
+        // the address is matched with the profile's once trimmed and lower-cased
+        let request = test::TestRequest::post()
+            .uri("/api_v1/set_user_credentials")
+            .set_json(&json!({
+                "user_id": user_id,
+                "email_address": " Jane@Demo.com",
+                "password": "correct horse battery",
+                "current_password": "correct horse battery"
+            }))
+            .to_request();
+        assert_eq!(test::call_service(&owner_app, request).await.status(), StatusCode::OK);
+
+        // unknown emails and wrong passwords get the same answer
+        for login in [
+            json!({"email_address": "jane@demo.com", "password": "wrong horse battery"}),
+            json!({"email_address": "nobody@demo.com", "password": "correct horse battery"}),
+        ] {
+            let request = test::TestRequest::post().uri("/auth/login").set_json(&login).to_request();
+            let response = test::call_service(&owner_app, request).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            let body: Value = test::read_body_json(response).await;
+            assert!(body["message"].as_str().unwrap().contains("444-PS-DJWT"));
+        }
+
+        let login = json!({"email_address": "JANE@demo.com ", "password": "correct horse battery"});
+        let request = test::TestRequest::post().uri("/auth/login").set_json(&login).to_request();
+        let body: Value = test::read_response_json(&owner_app, request).await;
+        assert_eq!(body["data"]["token_type"], "Bearer");
+        assert!(body["data"]["access_token"].is_string());
+        let first_refresh = body["data"]["refresh_token"].clone();
+        let request = test::TestRequest::post().uri("/auth/login").set_json(&login).to_request();
+        let body: Value = test::read_response_json(&owner_app, request).await;
+        let other_session = body["data"]["refresh_token"].clone();
+        assert_eq!(credentials.session_count(user_id), 2);
+
+        let request = test::TestRequest::post().uri("/auth/refresh").set_json(&json!({"refresh_token": first_refresh})).to_request();
+        let body: Value = test::read_response_json(&owner_app, request).await;
+        let second_refresh = body["data"]["refresh_token"].clone();
+        assert_ne!(second_refresh, first_refresh);
+        assert_eq!(credentials.session_count(user_id), 2);
+
+        // replaying the used token looks like a stolen one, every session of the user ends
+        let request = test::TestRequest::post().uri("/auth/refresh").set_json(&json!({"refresh_token": first_refresh})).to_request();
+        assert_eq!(test::call_service(&owner_app, request).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(credentials.session_count(user_id), 0);
+        for refresh_token in [second_refresh, other_session] {
+            let request = test::TestRequest::post().uri("/auth/refresh").set_json(&json!({"refresh_token": refresh_token})).to_request();
+            assert_eq!(test::call_service(&owner_app, request).await.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn logout_ends_one_session_and_logout_all_every_session() {
+        let profiles = Arc::new(InMemoryUserProfileRepository::default());
+        let credentials = Arc::new(InMemoryCredentialRepository::default());
+        let (user_id, time_uuid_order) = (Uuid::new_v4(), Uuid::new_v4());
+        profiles
+            .create_user_profile(UserProfileRowStruct::build_user_profile(
+                user_id,
+                time_uuid_order,
+                "john@demo.com".to_string(),
+                "John Doe".to_string(),
+                41,
+                None,
+            ))
+            .await
+            .unwrap();
+        let owner_app = in_memory_login_app!(profiles, credentials, JwtClaims::for_tests(&user_id.to_string(), &[]));
+        let request = test::TestRequest::post()
+            .uri("/api_v1/set_user_credentials")
+            .set_json(&json!({"user_id": user_id, "email_address": "john@demo.com", "password": "a long enough password"}))
+            .to_request();
+        assert_eq!(test::call_service(&owner_app, request).await.status(), StatusCode::OK);
+
+        let login = json!({"email_address": "john@demo.com", "password": "a long enough password"});
+        let mut refresh_tokens = Vec::new();
+        for _ in 0..3 {
+            let request = test::TestRequest::post().uri("/auth/login").set_json(&login).to_request();
+            let body: Value = test::read_response_json(&owner_app, request).await;
+            refresh_tokens.push(body["data"]["refresh_token"].clone());
+        }
+
+        for _ in 0..2 {
+            let request = test::TestRequest::post().uri("/auth/logout").set_json(&json!({"refresh_token": refresh_tokens[0]})).to_request();
+            assert_eq!(test::call_service(&owner_app, request).await.status(), StatusCode::OK);
+        }
+        assert_eq!(credentials.session_count(user_id), 2);
+        let request = test::TestRequest::post().uri("/auth/logout").set_json(&json!({"refresh_token": "not.a.jwt"})).to_request();
+        assert_eq!(test::call_service(&owner_app, request).await.status(), StatusCode::UNAUTHORIZED);
+
+        let request = test::TestRequest::post().uri("/api_v1/logout_all_sessions").set_json(&json!({})).to_request();
+        assert_eq!(test::call_service(&owner_app, request).await.status(), StatusCode::OK);
+        assert_eq!(credentials.session_count(user_id), 0);
+
+        // once the profile is gone the stored password no longer logs anyone in
+        profiles.delete_user_profile(user_id, time_uuid_order, RowCondition::Exists).await.unwrap();
+        let request = test::TestRequest::post().uri("/auth/login").set_json(&login).to_request();
+        assert_eq!(test::call_service(&owner_app, request).await.status(), StatusCode::UNAUTHORIZED);
+    }
+}
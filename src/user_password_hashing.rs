@@ -0,0 +1,92 @@
+use crate::application_error::{AppError, AppResult};
+use actix_web::web;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Argon2id hashing of ***user_credentials*** passwords, the work runs on the blocking thread pool
+/// so a login never stalls the worker serving other requests
+#[derive(Clone)]
+pub struct PasswordHashing {
+    argon2: Argon2<'static>,
+    /// Checked when the email has no credentials, so unknown emails take as long as wrong passwords
+    dummy_hash: String,
+}
+
+impl PasswordHashing {
+    /// ***Params::DEFAULT*** is argon2id with 19 MiB, 2 passes and 1 lane
+    pub fn new(params: Params) -> AppResult<PasswordHashing> {
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+        let dummy_hash = hash_with(&argon2, &random_bytes::<32>()?)?;
+        Ok(PasswordHashing { argon2, dummy_hash })
+    }
+
+    /// Cheapest parameters argon2 accepts, for handler tests
+    #[cfg(test)]
+    pub(crate) fn for_tests() -> PasswordHashing {
+        PasswordHashing::new(Params::new(Params::MIN_M_COST, Params::MIN_T_COST, Params::MIN_P_COST, None).unwrap()).unwrap()
+    }
+
+    /// PHC string with a fresh 16 byte salt, eg ***$argon2id$v=19$m=19456,t=2,p=1$...***
+    pub async fn hash(&self, password: &str) -> AppResult<String> {
+        let argon2 = self.argon2.clone();
+        let password = password.to_string();
+        web::block(move || hash_with(&argon2, password.as_bytes()))
+            .await
+            .map_err(|_| AppError::Internal { detail: "password hashing was cancelled".to_string() })?
+    }
+
+    /// False for a wrong password, for a stored hash that does not parse and when there is no stored hash at all.
+    /// The parameters come from the stored hash, so raising them later keeps older hashes working
+    pub async fn verify(&self, password: &str, stored_hash: Option<String>) -> AppResult<bool> {
+        let argon2 = self.argon2.clone();
+        let password = password.to_string();
+        let known = stored_hash.is_some();
+        let stored_hash = stored_hash.unwrap_or_else(|| self.dummy_hash.clone());
+        let matches = web::block(move || match PasswordHash::new(&stored_hash) {
+            Ok(parsed) => argon2.verify_password(password.as_bytes(), &parsed).is_ok(),
+            Err(e) => {
+                log::error!("stored password hash does not parse: {}", e);
+                false
+            }
+        })
+        .await
+        .map_err(|_| AppError::Internal { detail: "password verification was cancelled".to_string() })?;
+        Ok(known && matches)
+    }
+}
+
+fn random_bytes<const N: usize>() -> AppResult<[u8; N]> {
+    let mut bytes = [0u8; N];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| AppError::Internal { detail: "the system random generator failed".to_string() })?;
+    Ok(bytes)
+}
+
+fn hash_with(argon2: &Argon2<'static>, password: &[u8]) -> AppResult<String> {
+    let salt = SaltString::encode_b64(&random_bytes::<16>()?)
+        .map_err(|e| AppError::Internal { detail: format!("could not encode a password salt: {}", e) })?;
+    argon2
+        .hash_password(password, &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AppError::Internal { detail: format!("could not hash a password: {}", e) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn only_the_hashed_password_verifies() {
+        let hashing = PasswordHashing::for_tests();
+        let hash = hashing.hash("correct horse battery staple").await.unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert_ne!(hash, hashing.hash("correct horse battery staple").await.unwrap(), "every hash gets its own salt");
+
+        assert!(hashing.verify("correct horse battery staple", Some(hash.clone())).await.unwrap());
+        assert!(!hashing.verify("correct horse battery stapler", Some(hash)).await.unwrap());
+        assert!(!hashing.verify("correct horse battery staple", Some("not a PHC string".to_string())).await.unwrap());
+        assert!(!hashing.verify("correct horse battery staple", None).await.unwrap());
+    }
+}
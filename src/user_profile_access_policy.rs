@@ -0,0 +1,433 @@
+use crate::api_key_authentication::ApiKeyPrincipal;
+use crate::application_error::{AppError, AppResult};
+use crate::jwt_bearer_authentication::JwtClaims;
+use crate::user_role_repository::RoleRepository;
+use actix_web::{dev::Payload, web, FromRequest, HttpMessage, HttpRequest};
+use derive_more::Display;
+use futures::future::{ready, Ready};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Role that is granted every permission on every user_id, whatever the ***roles*** table says
+pub const ADMIN_ROLE: &str = "admin";
+pub const SUPPORT_ROLE: &str = "support";
+pub const READ_ONLY_ROLE: &str = "read_only";
+
+/// What an operation needs, stored as text in ***roles.permissions***
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Permission {
+    #[display(fmt = "profile:create")]
+    #[serde(rename = "profile:create")]
+    CreateProfile,
+    #[display(fmt = "profile:read")]
+    #[serde(rename = "profile:read")]
+    ReadProfile,
+    #[display(fmt = "profile:update")]
+    #[serde(rename = "profile:update")]
+    UpdateProfile,
+    #[display(fmt = "profile:delete")]
+    #[serde(rename = "profile:delete")]
+    DeleteProfile,
+    #[display(fmt = "profile:list")]
+    #[serde(rename = "profile:list")]
+    ListProfiles,
+    #[display(fmt = "audit:read")]
+    #[serde(rename = "audit:read")]
+    ReadAudit,
+    #[display(fmt = "roles:manage")]
+    #[serde(rename = "roles:manage")]
+    ManageRoles,
+    #[display(fmt = "api_keys:manage")]
+    #[serde(rename = "api_keys:manage")]
+    ManageApiKeys,
+    /// Setting a password, whoever holds it on a user_id can log in as that user
+    #[display(fmt = "credentials:manage")]
+    #[serde(rename = "credentials:manage")]
+    ManageCredentials,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 9] = [
+        Permission::CreateProfile,
+        Permission::ReadProfile,
+        Permission::UpdateProfile,
+        Permission::DeleteProfile,
+        Permission::ListProfiles,
+        Permission::ReadAudit,
+        Permission::ManageRoles,
+        Permission::ManageApiKeys,
+        Permission::ManageCredentials,
+    ];
+
+    pub fn parse(permission: &str) -> Option<Permission> {
+        Permission::ALL.iter().copied().find(|known| known.to_string() == permission)
+    }
+
+    /// The owner of a user_id, the token ***sub***, always has these on it without any role
+    fn granted_to_owner(self) -> bool {
+        matches!(
+            self,
+            Permission::ReadProfile
+                | Permission::UpdateProfile
+                | Permission::DeleteProfile
+                | Permission::ReadAudit
+                | Permission::ManageCredentials
+        )
+    }
+}
+
+/// Roles seeded by migrations 0007, 0008 and 0010, keep them in step
+pub const DEFAULT_ROLES: &[(&str, &[Permission])] = &[
+    (ADMIN_ROLE, &Permission::ALL),
+    (
+        SUPPORT_ROLE,
+        &[
+            Permission::CreateProfile,
+            Permission::ReadProfile,
+            Permission::UpdateProfile,
+            Permission::ListProfiles,
+            Permission::ReadAudit,
+        ],
+    ),
+    (READ_ONLY_ROLE, &[Permission::ReadProfile, Permission::ListProfiles, Permission::ReadAudit]),
+];
+
+/// Outcome of a permission check
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccessDecision {
+    /// The token subject is the user_id and the permission is one owners always have
+    Owner,
+    /// Granted through this role
+    Role(String),
+    /// An API key whose scopes hold the permission
+    ApiKey(Uuid),
+    Denied,
+}
+
+impl AccessDecision {
+    /// ***owner***, ***role:<name>*** or ***api_key:<key_id>***, None when denied
+    pub fn granted_by(&self) -> Option<String> {
+        match self {
+            AccessDecision::Owner => Some("owner".to_string()),
+            AccessDecision::Role(role) => Some(format!("role:{}", role)),
+            AccessDecision::ApiKey(key_id) => Some(format!("api_key:{}", key_id)),
+            AccessDecision::Denied => None,
+        }
+    }
+}
+
+/// Role name to the permissions it grants
+type RolePermissions = Arc<HashMap<String, HashSet<Permission>>>;
+
+/// A value read from YCQL together with when it was read
+struct Cached<T> {
+    value: T,
+    read_at: Instant,
+}
+
+impl<T: Clone> Cached<T> {
+    fn fresh(&self, ttl: Duration) -> Option<T> {
+        if self.read_at.elapsed() < ttl {
+            Some(self.value.clone())
+        } else {
+            None
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Decides which permissions a verified caller has.
+/// Effective roles are the token ***roles*** claim plus the subject's ***user_roles*** rows.
+/// Both the assignments and the role definitions are cached for ***cache_ttl***,
+/// assignments changed through ***assign_role*** or ***revoke_role*** drop the subject's entry at once.
+/// The locks are never held across an await point
+pub struct AccessControl {
+    roles: Arc<dyn RoleRepository>,
+    cache_ttl: Duration,
+    role_permissions: Mutex<Option<Cached<RolePermissions>>>,
+    user_roles: Mutex<HashMap<String, Cached<Vec<String>>>>,
+    /// Bumped by every invalidation, a read that started before one must not cache what it read
+    invalidations: AtomicU64,
+}
+
+impl AccessControl {
+    pub fn new(roles: Arc<dyn RoleRepository>, cache_ttl: Duration) -> AccessControl {
+        AccessControl {
+            roles,
+            cache_ttl,
+            role_permissions: Mutex::new(None),
+            user_roles: Mutex::new(HashMap::new()),
+            invalidations: AtomicU64::new(0),
+        }
+    }
+
+    /// Role name to permissions, unknown permission strings in the table are logged and ignored
+    async fn role_permissions(&self) -> AppResult<RolePermissions> {
+        let cached = lock(&self.role_permissions).as_ref().and_then(|cached| cached.fresh(self.cache_ttl));
+        if let Some(role_permissions) = cached {
+            return Ok(role_permissions);
+        }
+        let mut role_permissions = HashMap::new();
+        for row in self.roles.read_roles().await? {
+            let mut permissions = HashSet::new();
+            for permission in &row.permissions {
+                match Permission::parse(permission) {
+                    Some(permission) => {
+                        permissions.insert(permission);
+                    }
+                    None => log::warn!("role {} grants unknown permission {}, ignored", row.role, permission),
+                }
+            }
+            role_permissions.insert(row.role, permissions);
+        }
+        let role_permissions = Arc::new(role_permissions);
+        *lock(&self.role_permissions) = Some(Cached { value: role_permissions.clone(), read_at: Instant::now() });
+        Ok(role_permissions)
+    }
+
+    /// Roles assigned to the subject in ***user_roles***
+    pub async fn assigned_roles(&self, subject: &str) -> AppResult<Vec<String>> {
+        let cached = lock(&self.user_roles).get(subject).and_then(|cached| cached.fresh(self.cache_ttl));
+        if let Some(roles) = cached {
+            return Ok(roles);
+        }
+        let invalidations = self.invalidations.load(Ordering::SeqCst);
+        let roles = self.roles.read_user_roles(subject).await?;
+        let mut user_roles = lock(&self.user_roles);
+        if self.invalidations.load(Ordering::SeqCst) != invalidations {
+            return Ok(roles);
+        }
+        // stale entries go here so the map only holds subjects seen within the TTL
+        let cache_ttl = self.cache_ttl;
+        user_roles.retain(|_, cached| cached.read_at.elapsed() < cache_ttl);
+        user_roles.insert(subject.to_string(), Cached { value: roles.clone(), read_at: Instant::now() });
+        Ok(roles)
+    }
+
+    /// Token ***roles*** claim first, then the assigned roles it does not already list
+    pub async fn effective_roles(&self, subject: &str, token_roles: &[&str]) -> AppResult<Vec<String>> {
+        let mut roles: Vec<String> = token_roles.iter().map(|role| role.to_string()).collect();
+        for role in self.assigned_roles(subject).await? {
+            if !roles.contains(&role) {
+                roles.push(role);
+            }
+        }
+        Ok(roles)
+    }
+
+    /// ***user_id*** is the profile the operation is about, None for operations across users like listing
+    pub async fn decide(&self, claims: &JwtClaims, permission: Permission, user_id: Option<Uuid>) -> AppResult<AccessDecision> {
+        self.decide_for_subject(&claims.sub, &claims.roles(), permission, user_id).await
+    }
+
+    /// ***decide*** for any subject, pass no ***token_roles*** to judge it by its assigned roles alone
+    pub async fn decide_for_subject(
+        &self,
+        subject: &str,
+        token_roles: &[&str],
+        permission: Permission,
+        user_id: Option<Uuid>,
+    ) -> AppResult<AccessDecision> {
+        if permission.granted_to_owner() && user_id.is_some() && Uuid::parse_str(subject).ok() == user_id {
+            return Ok(AccessDecision::Owner);
+        }
+        let roles = self.effective_roles(subject, token_roles).await?;
+        if roles.iter().any(|role| role == ADMIN_ROLE) {
+            return Ok(AccessDecision::Role(ADMIN_ROLE.to_string()));
+        }
+        let role_permissions = self.role_permissions().await?;
+        Ok(roles
+            .into_iter()
+            .find(|role| role_permissions.get(role).is_some_and(|permissions| permissions.contains(&permission)))
+            .map_or(AccessDecision::Denied, AccessDecision::Role))
+    }
+
+    /// Only roles defined in the ***roles*** table can be assigned
+    pub async fn assign_role(&self, subject: &str, role: &str) -> AppResult<()> {
+        if !self.role_permissions().await?.contains_key(role) {
+            return Err(AppError::bad_request(&format!("Role {} does not exist", role)));
+        }
+        self.roles.assign_user_role(subject, role).await?;
+        self.invalidate(subject);
+        Ok(())
+    }
+
+    pub async fn revoke_role(&self, subject: &str, role: &str) -> AppResult<()> {
+        self.roles.revoke_user_role(subject, role).await?;
+        self.invalidate(subject);
+        Ok(())
+    }
+
+    /// Default roles kept in memory, for handler tests
+    #[cfg(test)]
+    pub(crate) fn for_tests() -> web::Data<AccessControl> {
+        web::Data::new(AccessControl::new(
+            Arc::new(crate::user_role_repository::InMemoryRoleRepository::default()),
+            Duration::from_secs(60),
+        ))
+    }
+
+    /// Drop the cached assignments of a subject, the next check reads ***user_roles*** again
+    pub fn invalidate(&self, subject: &str) {
+        let mut user_roles = lock(&self.user_roles);
+        self.invalidations.fetch_add(1, Ordering::SeqCst);
+        user_roles.remove(subject);
+    }
+}
+
+/// Who a request acts as, set by JwtAuthentication or ApiKeyAuthentication
+#[derive(Clone, Debug)]
+pub enum CallerIdentity {
+    Token(JwtClaims),
+    ApiKey(ApiKeyPrincipal),
+}
+
+/// Extractor for handlers behind JwtAuthentication or ApiKeyAuthentication.
+/// Each handler states the permission it needs with ***require***, every decision is logged with the route
+pub struct AuthorizedCaller {
+    identity: CallerIdentity,
+    access_control: web::Data<AccessControl>,
+    route: String,
+}
+
+impl AuthorizedCaller {
+    /// Token ***sub***, or ***api_key:<key_id>*** for API keys
+    pub fn subject(&self) -> String {
+        match &self.identity {
+            CallerIdentity::Token(claims) => claims.sub.clone(),
+            CallerIdentity::ApiKey(principal) => principal.subject(),
+        }
+    }
+
+    pub fn access_control(&self) -> &AccessControl {
+        &self.access_control
+    }
+
+    /// Tokens go through AccessControl, an API key holds exactly its scopes, never the owner rule or any role
+    pub async fn decide(&self, permission: Permission, user_id: Option<Uuid>) -> AppResult<AccessDecision> {
+        match &self.identity {
+            CallerIdentity::Token(claims) => self.access_control.decide(claims, permission, user_id).await,
+            CallerIdentity::ApiKey(principal) if principal.scopes.contains(&permission) => Ok(AccessDecision::ApiKey(principal.key_id)),
+            CallerIdentity::ApiKey(_) => Ok(AccessDecision::Denied),
+        }
+    }
+
+    /// 403 unless the caller holds ***permission***, on ***user_id*** when the operation is about one profile
+    pub async fn require(&self, permission: Permission, user_id: Option<Uuid>) -> AppResult<()> {
+        let target = user_id.map_or_else(|| "*".to_string(), |user_id| user_id.to_string());
+        let subject = self.subject();
+        match self.decide(permission, user_id).await?.granted_by() {
+            Some(granted_by) => {
+                log::info!(
+                    "access granted: subject={} permission={} user_id={} route={} as={}",
+                    subject, permission, target, self.route, granted_by
+                );
+                Ok(())
+            }
+            None => {
+                log::warn!("access denied: subject={} permission={} user_id={} route={}", subject, permission, target, self.route);
+                Err(AppError::forbidden(&format!("{} lacks {} on user {}", subject, permission, target)))
+            }
+        }
+    }
+}
+
+impl FromRequest for AuthorizedCaller {
+    type Config = ();
+    type Error = AppError;
+    type Future = Ready<Result<Self, AppError>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        // only missing when the route was mounted outside the protected scope
+        let identity = match (req.extensions().get::<JwtClaims>(), req.extensions().get::<ApiKeyPrincipal>()) {
+            (Some(claims), _) => CallerIdentity::Token(claims.clone()),
+            (None, Some(principal)) => CallerIdentity::ApiKey(principal.clone()),
+            (None, None) => return ready(Err(AppError::denied("route is not behind JwtAuthentication"))),
+        };
+        let access_control = match req.app_data::<web::Data<AccessControl>>().cloned() {
+            Some(access_control) => access_control,
+            None => {
+                return ready(Err(AppError::Internal { detail: "AccessControl is not registered as app data".to_string() }))
+            }
+        };
+        ready(Ok(AuthorizedCaller { identity, access_control, route: req.path().to_string() }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user_role_repository::InMemoryRoleRepository;
+
+    fn in_memory_access_control(cache_ttl: Duration) -> (Arc<InMemoryRoleRepository>, AccessControl) {
+        let roles = Arc::new(InMemoryRoleRepository::default());
+        (roles.clone(), AccessControl::new(roles, cache_ttl))
+    }
+
+    #[actix_rt::test]
+    async fn owners_roles_and_admins_are_granted_what_they_hold() {
+        let (_, access_control) = in_memory_access_control(Duration::from_secs(60));
+        let user_id = Uuid::new_v4();
+        let owner = JwtClaims::for_tests(&user_id.to_string().to_uppercase(), &[]);
+        let support = JwtClaims::for_tests("support@demo.com", &[SUPPORT_ROLE]);
+        let read_only = JwtClaims::for_tests("auditor@demo.com", &[READ_ONLY_ROLE]);
+        let admin = JwtClaims::for_tests("ops@demo.com", &[ADMIN_ROLE]);
+        let stranger = JwtClaims::for_tests(&Uuid::new_v4().to_string(), &[]);
+
+        let decide = |claims: &JwtClaims, permission: Permission, user_id: Option<Uuid>| {
+            let claims = claims.clone();
+            let access_control = &access_control;
+            async move { access_control.decide(&claims, permission, user_id).await.unwrap() }
+        };
+
+        assert_eq!(decide(&owner, Permission::DeleteProfile, Some(user_id)).await, AccessDecision::Owner);
+        assert_eq!(decide(&owner, Permission::ListProfiles, None).await, AccessDecision::Denied);
+        assert_eq!(decide(&owner, Permission::CreateProfile, None).await, AccessDecision::Denied);
+        assert_eq!(decide(&support, Permission::UpdateProfile, Some(user_id)).await, AccessDecision::Role(SUPPORT_ROLE.to_string()));
+        assert_eq!(decide(&support, Permission::DeleteProfile, Some(user_id)).await, AccessDecision::Denied);
+        assert_eq!(decide(&read_only, Permission::ReadAudit, Some(user_id)).await, AccessDecision::Role(READ_ONLY_ROLE.to_string()));
+        assert_eq!(decide(&read_only, Permission::UpdateProfile, Some(user_id)).await, AccessDecision::Denied);
+        assert_eq!(decide(&admin, Permission::ManageRoles, None).await, AccessDecision::Role(ADMIN_ROLE.to_string()));
+        assert_eq!(decide(&stranger, Permission::ReadProfile, Some(user_id)).await, AccessDecision::Denied);
+    }
+
+    #[actix_rt::test]
+    async fn assignments_are_cached_until_changed_through_access_control() {
+        let (roles, access_control) = in_memory_access_control(Duration::from_secs(60));
+        let user_id = Uuid::new_v4();
+        let caller = JwtClaims::for_tests("support@demo.com", &[]);
+
+        assert_eq!(access_control.decide(&caller, Permission::ReadProfile, Some(user_id)).await.unwrap(), AccessDecision::Denied);
+
+        // written behind its back, eg by another instance, the cached assignments still apply
+        roles.assign_user_role("support@demo.com", READ_ONLY_ROLE).await.unwrap();
+        assert_eq!(access_control.decide(&caller, Permission::ReadProfile, Some(user_id)).await.unwrap(), AccessDecision::Denied);
+
+        access_control.assign_role("support@demo.com", SUPPORT_ROLE).await.unwrap();
+        assert_eq!(access_control.effective_roles(&caller.sub, &caller.roles()).await.unwrap(), vec![READ_ONLY_ROLE.to_string(), SUPPORT_ROLE.to_string()]);
+        assert_eq!(
+            access_control.decide(&caller, Permission::UpdateProfile, Some(user_id)).await.unwrap(),
+            AccessDecision::Role(SUPPORT_ROLE.to_string())
+        );
+
+        access_control.revoke_role("support@demo.com", SUPPORT_ROLE).await.unwrap();
+        assert_eq!(access_control.decide(&caller, Permission::UpdateProfile, Some(user_id)).await.unwrap(), AccessDecision::Denied);
+
+        assert!(access_control.assign_role("support@demo.com", "superuser").await.is_err());
+
+        let (roles, uncached) = in_memory_access_control(Duration::from_secs(0));
+        uncached.decide(&caller, Permission::ReadProfile, Some(user_id)).await.unwrap();
+        roles.assign_user_role("support@demo.com", READ_ONLY_ROLE).await.unwrap();
+        assert_eq!(
+            uncached.decide(&caller, Permission::ReadProfile, Some(user_id)).await.unwrap(),
+            AccessDecision::Role(READ_ONLY_ROLE.to_string())
+        );
+    }
+}
@@ -0,0 +1,438 @@
+use crate::application_error::AppError;
+use crate::crud_controller::{new_user_profile_row, UserProfileJsonREQUEST};
+use crate::default_strings::CustomStatusMessage;
+use crate::generic_json_error_response::FieldErrorResponse;
+use crate::generic_json_success_response::GenericJsonSuccessResponse;
+use crate::time_uuid_generator::TimeUuidGenerator;
+use crate::user_profile_access_policy::{AuthorizedCaller, Permission};
+use crate::user_profile_repository::UserProfileRepository;
+use crate::validated_json_extractor::validation_error_response;
+use crate::ycql_db_schema_and_queries::{
+    ConditionalWriteOutcome, ExistingRowWrite, RowCondition, UserProfileBatchType, UserProfileRowStruct, UserProfileWrite,
+};
+use crate::ycql_service_config::BatchConfig;
+use actix_web::{web, ResponseError};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Register ***/batch_user_profiles*** with its own JSON body limit, every other route keeps the 4096 bytes set in main.rs
+pub fn user_profile_batch_routes<R: UserProfileRepository>(batch_config: BatchConfig) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |cfg| {
+        cfg.service(
+            web::resource("/batch_user_profiles")
+                .app_data(
+                    web::JsonConfig::default()
+                        .limit(batch_config.max_payload_bytes)
+                        .error_handler(crate::json_error_handler),
+                )
+                .app_data(web::Data::new(batch_config))
+                .route(web::post().to(batch_user_profiles::<R>)),
+        );
+    }
+}
+
+///Json Request Struct for |batch_user_profiles| FUNCTION
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BatchUserProfilesJsonREQUEST {
+    #[serde(default)]
+    batch_type: UserProfileBatchType,
+    /// Parsed one by one so a malformed operation only fails itself
+    operations: Vec<serde_json::Value>,
+}
+
+/// One batch operation, eg ***{"delete": {"user_id": .., "time_uuid_order": ..}}***
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchOperationJsonREQUEST {
+    Create(Box<UserProfileJsonREQUEST>),
+    Update(BatchUpdateJsonREQUEST),
+    Delete(BatchDeleteJsonREQUEST),
+}
+
+#[derive(Debug, Validate, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchUpdateJsonREQUEST {
+    user_id: Uuid,
+    time_uuid_order: Uuid,
+    #[validate(range(min = 0, max = 120, message = "must be between 0 and 120"))]
+    age: i8,
+    #[validate(length(min = 1, max = 100, message = "must be between 1 and 100 characters"))]
+    full_name: String,
+    /// The ETag value without quotes, the operation fails with 412 when the row no longer has it
+    version: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchDeleteJsonREQUEST {
+    user_id: Uuid,
+    time_uuid_order: Uuid,
+    version: Option<Uuid>,
+}
+
+///Json Response Struct for |batch_user_profiles| FUNCTION
+#[derive(Debug, Serialize)]
+pub struct BatchUserProfilesJsonRESPONSE {
+    batch_type: UserProfileBatchType,
+    applied: usize,
+    failed: usize,
+    /// One entry per operation, in request order
+    results: Vec<BatchItemJsonRESPONSE>,
+}
+
+/// Outcome of one operation, ***status*** is the HTTP status the single item route would have answered
+#[derive(Debug, Serialize)]
+pub struct BatchItemJsonRESPONSE {
+    index: usize,
+    status: u16,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time_uuid_order: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<Uuid>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    field_errors: Vec<FieldErrorResponse>,
+}
+
+impl BatchItemJsonRESPONSE {
+    fn applied(index: usize, message: &str, (user_id, time_uuid_order): (Uuid, Uuid), version: Option<Uuid>) -> BatchItemJsonRESPONSE {
+        BatchItemJsonRESPONSE {
+            index,
+            status: 200,
+            message: message.to_string(),
+            user_id: Some(user_id),
+            time_uuid_order: Some(time_uuid_order),
+            version,
+            field_errors: Vec::new(),
+        }
+    }
+
+    /// Same status and masked message the single item route sends for ***error***
+    fn failed(index: usize, key: Option<(Uuid, Uuid)>, error: &AppError) -> BatchItemJsonRESPONSE {
+        error.log_detail();
+        let (message, field_errors) = match error {
+            AppError::Validation(errors) => {
+                let response = validation_error_response(errors.clone());
+                (response.message, response.field_errors)
+            }
+            _ => (error.client_message(), Vec::new()),
+        };
+        BatchItemJsonRESPONSE {
+            index,
+            status: error.status_code().as_u16(),
+            message,
+            user_id: key.map(|(user_id, _)| user_id),
+            time_uuid_order: key.map(|(_, time_uuid_order)| time_uuid_order),
+            version: None,
+            field_errors,
+        }
+    }
+}
+
+/// A parsed and validated operation, creates already carry their generated ids
+enum PlannedWrite {
+    Create(Box<UserProfileRowStruct>),
+    Update { time_uuid_order: Uuid, age: i8, full_name: String, version: Option<Uuid> },
+    Delete { time_uuid_order: Uuid, version: Option<Uuid> },
+}
+
+/// Every operation needs the same permission as its single item route, a missing one fails only that operation with 403
+async fn plan_write(operation: serde_json::Value, caller: &AuthorizedCaller, time_uuid_generator: &TimeUuidGenerator) -> Result<(Uuid, PlannedWrite), AppError> {
+    let operation: BatchOperationJsonREQUEST = serde_json::from_value(operation).map_err(|e| AppError::BadRequest {
+        reason: format!("operation must be a create, update or delete object: {}", e),
+    })?;
+    Ok(match operation {
+        BatchOperationJsonREQUEST::Create(create) => {
+            caller.require(Permission::CreateProfile, None).await?;
+            create.validate()?;
+            let row = new_user_profile_row(time_uuid_generator, &create);
+            (row.user_id, PlannedWrite::Create(Box::new(row)))
+        }
+        BatchOperationJsonREQUEST::Update(update) => {
+            caller.require(Permission::UpdateProfile, Some(update.user_id)).await?;
+            update.validate()?;
+            (
+                update.user_id,
+                PlannedWrite::Update { time_uuid_order: update.time_uuid_order, age: update.age, full_name: update.full_name, version: update.version },
+            )
+        }
+        BatchOperationJsonREQUEST::Delete(delete) => {
+            caller.require(Permission::DeleteProfile, Some(delete.user_id)).await?;
+            (delete.user_id, PlannedWrite::Delete { time_uuid_order: delete.time_uuid_order, version: delete.version })
+        }
+    })
+}
+
+/// Create, update and delete many profiles in one request.
+/// Operations are grouped by user_id partition and every group is sent as one logged or unlogged CQL batch,
+/// so a group is written all or nothing while separate groups succeed or fail on their own.
+/// The answer is always 200, each operation reports its own status in ***results***
+pub async fn batch_user_profiles<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    user_profile_repository: web::Data<R>,
+    time_uuid_generator: web::Data<TimeUuidGenerator>,
+    batch_config: web::Data<BatchConfig>,
+    get_batch: web::Json<BatchUserProfilesJsonREQUEST>,
+) -> Result<web::HttpResponse, AppError> {
+    let BatchUserProfilesJsonREQUEST { batch_type, operations } = get_batch.into_inner();
+    if operations.is_empty() || operations.len() > batch_config.max_operations {
+        return Err(AppError::BadRequest {
+            reason: format!("operations must hold between 1 and {} entries", batch_config.max_operations),
+        });
+    }
+
+    let mut results: Vec<Option<BatchItemJsonRESPONSE>> = Vec::with_capacity(operations.len());
+    // BTreeMap keeps the groups, and the writes inside each group, in request order
+    let mut groups: BTreeMap<Uuid, Vec<(usize, PlannedWrite)>> = BTreeMap::new();
+    let mut batch_emails = HashSet::new();
+    for (index, operation) in operations.into_iter().enumerate() {
+        match plan_write(operation, &caller, &time_uuid_generator).await {
+            Ok((_, PlannedWrite::Create(row))) if !batch_emails.insert(row.email_address.to_string()) => {
+                results.push(Some(BatchItemJsonRESPONSE::failed(index, None, &AppError::Conflict {
+                    reason: "An earlier operation of this batch already creates a profile with this email address".to_string(),
+                })));
+            }
+            Ok((user_id, write)) => {
+                results.push(None);
+                groups.entry(user_id).or_default().push((index, write));
+            }
+            Err(error) => results.push(Some(BatchItemJsonRESPONSE::failed(index, None, &error))),
+        }
+    }
+
+    let repository = user_profile_repository.get_ref();
+    let group_results = stream::iter(groups)
+        .map(|(user_id, writes)| write_partition_group(repository, batch_type, user_id, writes))
+        .buffer_unordered(batch_config.max_concurrent_groups)
+        .collect::<Vec<_>>()
+        .await;
+    for item in group_results.into_iter().flatten() {
+        let index = item.index;
+        results[index] = Some(item);
+    }
+
+    let results: Vec<BatchItemJsonRESPONSE> = results.into_iter().flatten().collect();
+    let applied = results.iter().filter(|item| item.status < 300).count();
+    let failed = results.len() - applied;
+    Ok(web::HttpResponse::Ok()
+    .content_type("application/json")
+    .body(serde_json::to_string(
+        &GenericJsonSuccessResponse::<BatchUserProfilesJsonRESPONSE>::generic_success_response(
+            CustomStatusMessage::USER_REQUEST_SUCCESS.to_string(),
+            format!("Batch processed, {} applied, {} failed", applied, failed),
+            200,
+            Some(BatchUserProfilesJsonRESPONSE { batch_type, applied, failed, results }),
+        )
+    ).unwrap()))
+}
+
+/// Checks every write of one partition against the rows read up front, answering 404, 409 or 412 per operation,
+/// then sends the writes that passed as a single batch
+async fn write_partition_group<R: UserProfileRepository>(
+    user_profile_repository: &R,
+    batch_type: UserProfileBatchType,
+    user_id: Uuid,
+    writes: Vec<(usize, PlannedWrite)>,
+) -> Vec<BatchItemJsonRESPONSE> {
+    let mut results = Vec::with_capacity(writes.len());
+
+    // version and cells TTL of every row in the partition, replayed as the group's own writes are planned
+    let mut staged: HashMap<Uuid, (Option<Uuid>, i32)> = HashMap::new();
+    if writes.iter().any(|(_, write)| !matches!(write, PlannedWrite::Create(_))) {
+        match user_profile_repository.read_user_profiles_by_user_id(user_id).await {
+            Ok(rows) => staged.extend(rows.iter().map(|row| (row.time_uuid_order, (row.version, row.cells_ttl())))),
+            Err(error) => {
+                return writes
+                    .into_iter()
+                    .map(|(index, write)| BatchItemJsonRESPONSE::failed(index, Some((user_id, write.time_uuid_order())), &error))
+                    .collect()
+            }
+        }
+    }
+
+    let mut batch = Vec::new();
+    let mut pending = Vec::new();
+    for (index, write) in writes {
+        let key = (user_id, write.time_uuid_order());
+        let planned = match write {
+            PlannedWrite::Create(row) => match user_profile_repository.find_by_email(row.email_address.to_string()).await {
+                // friendly early answer, the unique index still rejects the batch when two creates race
+                Ok(Some(_)) => Err(AppError::email_already_registered()),
+                Ok(None) => {
+                    let version = row.version;
+                    Ok((UserProfileWrite::Create(row), "User profile created", version))
+                }
+                Err(error) => Err(error),
+            },
+            PlannedWrite::Update { time_uuid_order, age, full_name, version } => {
+                checked_condition(&staged, time_uuid_order, version).map(|(condition, ttl_secs)| {
+                    let new_version = Uuid::new_v4();
+                    staged.insert(time_uuid_order, (Some(new_version), ttl_secs));
+                    let write = ExistingRowWrite { condition, new_version, ttl_secs };
+                    (UserProfileWrite::UpdateFullNameAndAge { user_id, time_uuid_order, age, full_name, write }, "User profile updated", Some(new_version))
+                })
+            }
+            PlannedWrite::Delete { time_uuid_order, version } => checked_condition(&staged, time_uuid_order, version).map(|(condition, _)| {
+                staged.remove(&time_uuid_order);
+                (UserProfileWrite::Delete { user_id, time_uuid_order, condition }, "User profile deleted", None)
+            }),
+        };
+        match planned {
+            Ok((write, message, version)) => {
+                batch.push(write);
+                pending.push((index, key, message, version));
+            }
+            Err(error) => results.push(BatchItemJsonRESPONSE::failed(index, Some(key), &error)),
+        }
+    }
+    if batch.is_empty() {
+        return results;
+    }
+
+    match user_profile_repository.apply_user_profile_batch(batch_type, batch).await {
+        Ok(ConditionalWriteOutcome::Applied) => {
+            results.extend(pending.into_iter().map(|(index, key, message, version)| BatchItemJsonRESPONSE::applied(index, message, key, version)))
+        }
+        Ok(ConditionalWriteOutcome::NotApplied { .. }) => {
+            let error = AppError::Conflict {
+                reason: "The user profile changed while the batch ran, nothing in its user_id group was written, retry".to_string(),
+            };
+            results.extend(pending.into_iter().map(|(index, key, ..)| BatchItemJsonRESPONSE::failed(index, Some(key), &error)))
+        }
+        Err(error) => results.extend(pending.into_iter().map(|(index, key, ..)| BatchItemJsonRESPONSE::failed(index, Some(key), &error))),
+    }
+    results
+}
+
+/// 404 when the row is gone, 412 when ***version*** is stale, otherwise the batch condition and the row's cells TTL
+fn checked_condition(
+    staged: &HashMap<Uuid, (Option<Uuid>, i32)>,
+    time_uuid_order: Uuid,
+    version: Option<Uuid>,
+) -> Result<(RowCondition, i32), AppError> {
+    let (stored_version, ttl_secs) = staged
+        .get(&time_uuid_order)
+        .ok_or_else(|| AppError::not_found("No user profile with this user id and time_uuid_order"))?;
+    match version {
+        None => Ok((RowCondition::Exists, *ttl_secs)),
+        Some(version) if *stored_version == Some(version) => Ok((RowCondition::VersionMatches(version), *ttl_secs)),
+        Some(_) => Err(AppError::precondition_failed::<()>("The user profile version does not match", None)),
+    }
+}
+
+impl PlannedWrite {
+    fn time_uuid_order(&self) -> Uuid {
+        match self {
+            PlannedWrite::Create(row) => row.time_uuid_order,
+            PlannedWrite::Update { time_uuid_order, .. } | PlannedWrite::Delete { time_uuid_order, .. } => *time_uuid_order,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler_test_app::{app_as, ops_admin};
+    use crate::time_uuid_generator::SystemClock;
+    use crate::user_profile_repository::InMemoryUserProfileRepository;
+    use actix_web::{http::StatusCode, test};
+    use serde_json::json;
+
+    macro_rules! in_memory_batch_app {
+        ($repository:expr, $batch_config:expr) => {
+            in_memory_batch_app!($repository, $batch_config, ops_admin())
+        };
+        ($repository:expr, $batch_config:expr, $claims:expr) => {
+            test::init_service(
+                app_as($claims)
+                    .app_data($repository.clone())
+                    .app_data(web::Data::new(TimeUuidGenerator::new(None, Box::new(SystemClock))))
+                    .app_data(web::JsonConfig::default().limit(4096).error_handler(crate::json_error_handler))
+                    .configure(user_profile_batch_routes::<InMemoryUserProfileRepository>($batch_config)),
+            )
+            .await
+        };
+    }
+
+    fn seeded_row(email_address: &str) -> UserProfileRowStruct {
+        let mut row = UserProfileRowStruct::build_user_profile(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            email_address.to_string(),
+            "Jane Doe".to_string(),
+            26,
+            None,
+        );
+        row.version = Some(Uuid::new_v4());
+        row
+    }
+
+    #[actix_rt::test]
+    async fn mixed_batch_reports_every_operation() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let kept = seeded_row("kept@demo.com");
+        let removed = seeded_row("removed@demo.com");
+        repository.create_user_profile(kept.clone()).await.unwrap();
+        repository.create_user_profile(removed.clone()).await.unwrap();
+        let app = in_memory_batch_app!(repository, BatchConfig::default());
+
+        let request = test::TestRequest::post()
+            .uri("/batch_user_profiles")
+            .set_json(&json!({"batch_type": "unlogged", "operations": [
+                {"create": {"email_address": "new@demo.com", "full_name": "New Person", "age": 30}},
+                {"create": {"email_address": "new@demo.com", "full_name": "Same Email", "age": 31}},
+                {"update": {"user_id": kept.user_id, "time_uuid_order": kept.time_uuid_order, "full_name": "Kept Renamed", "age": 27, "version": kept.version}},
+                {"delete": {"user_id": removed.user_id, "time_uuid_order": removed.time_uuid_order, "version": Uuid::new_v4()}},
+                {"delete": {"user_id": Uuid::new_v4(), "time_uuid_order": Uuid::new_v4()}},
+                {"create": {"email_address": "not an email", "full_name": "Bad", "age": 30}},
+                {"upsert": {}}
+            ]}))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        let statuses: Vec<_> = body["data"]["results"].as_array().unwrap().iter().map(|item| item["status"].as_u64().unwrap()).collect();
+        assert_eq!(statuses, vec![200, 409, 200, 412, 404, 400, 400]);
+        assert_eq!(body["data"]["applied"], 2);
+        assert_eq!(body["data"]["failed"], 5);
+        assert_eq!(body["data"]["results"][5]["field_errors"][0]["field"], "email_address");
+
+        let renamed = repository.read_user_profile_by_key(kept.user_id, kept.time_uuid_order).await.unwrap().unwrap();
+        assert_eq!(renamed.full_name, "Kept Renamed");
+        assert_eq!(renamed.version.map(|version| version.to_string()), body["data"]["results"][2]["version"].as_str().map(String::from));
+        assert!(repository.read_user_profile_by_key(removed.user_id, removed.time_uuid_order).await.unwrap().is_some());
+        assert!(repository.find_by_email("new@demo.com".to_string()).await.unwrap().is_some());
+    }
+
+    #[actix_rt::test]
+    async fn batch_route_has_its_own_payload_limit() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let batch_config = BatchConfig { max_payload_bytes: 64 * 1024, ..BatchConfig::default() };
+        let app = in_memory_batch_app!(repository, batch_config);
+
+        // well above the 4096 bytes every other route accepts
+        let operations: Vec<_> = (0..60)
+            .map(|n| json!({"create": {"email_address": format!("user{}@demo.com", n), "full_name": "Onboarded Person", "age": 30}}))
+            .collect();
+        let body = json!({"operations": operations});
+        assert!(body.to_string().len() > 4096);
+        let request = test::TestRequest::post().uri("/batch_user_profiles").set_json(&body).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["data"]["applied"], 60);
+
+        let comment = "x".repeat(400);
+        let operations: Vec<_> = (0..200)
+            .map(|n| json!({"create": {"email_address": format!("big{}@demo.com", n), "full_name": "Onboarded Person", "age": 30, "comment": comment}}))
+            .collect();
+        let request = test::TestRequest::post().uri("/batch_user_profiles").set_json(&json!({"operations": operations})).to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::BAD_REQUEST);
+        assert!(repository.find_by_email("big0@demo.com".to_string()).await.unwrap().is_none());
+    }
+}
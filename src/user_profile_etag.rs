@@ -0,0 +1,65 @@
+use crate::application_error::AppError;
+use crate::ycql_db_schema_and_queries::RowCondition;
+use actix_web::{dev::HttpResponseBuilder, http::header, HttpRequest};
+use uuid::Uuid;
+
+/// Strong entity tag for a profile version
+pub fn etag_for(version: Uuid) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Adds the ***ETag*** header, rows written before the version column have none until their next write
+pub fn insert_etag(response: &mut HttpResponseBuilder, version: Option<Uuid>) {
+    if let Some(version) = version {
+        response.insert_header((header::ETAG, etag_for(version)));
+    }
+}
+
+/// ***If-Match*** as a write condition.
+/// No header or ***\**** only needs the row to exist, a single strong ETag needs the stored version to match,
+/// anything else (several tags, weak tags, garbage) is a 400
+pub fn if_match_condition(request: &HttpRequest) -> Result<Option<RowCondition>, AppError> {
+    let if_match = match request.headers().get(header::IF_MATCH) {
+        None => return Ok(None),
+        Some(if_match) => if_match
+            .to_str()
+            .map_err(|_| AppError::bad_request("If-Match must be * or a single ETag"))?
+            .trim(),
+    };
+    if if_match == "*" {
+        return Ok(Some(RowCondition::Exists));
+    }
+    if_match
+        .strip_prefix('"')
+        .and_then(|tag| tag.strip_suffix('"'))
+        .and_then(|tag| Uuid::parse_str(tag).ok())
+        .map(|version| Some(RowCondition::VersionMatches(version)))
+        .ok_or_else(|| AppError::bad_request("If-Match must be * or a single ETag"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn if_match_parses_star_tags_and_rejects_the_rest() {
+        let version = Uuid::new_v4();
+
+        let missing = TestRequest::default().to_http_request();
+        assert_eq!(if_match_condition(&missing).unwrap(), None);
+
+        let star = TestRequest::default().insert_header((header::IF_MATCH, "*")).to_http_request();
+        assert_eq!(if_match_condition(&star).unwrap(), Some(RowCondition::Exists));
+
+        let tag = TestRequest::default()
+            .insert_header((header::IF_MATCH, etag_for(version)))
+            .to_http_request();
+        assert_eq!(if_match_condition(&tag).unwrap(), Some(RowCondition::VersionMatches(version)));
+
+        for bad in &[format!("W/\"{}\"", version), version.to_string(), "\"a\", \"b\"".to_string()] {
+            let request = TestRequest::default().insert_header((header::IF_MATCH, bad.as_str())).to_http_request();
+            assert!(if_match_condition(&request).is_err(), "{}", bad);
+        }
+    }
+}
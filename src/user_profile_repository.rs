@@ -0,0 +1,558 @@
+use crate::application_error::{AppError, AppResult};
+use crate::ycql_db_schema_and_queries::{
+    create_new_user_profile_query, delete_user_profile_query, CollectionChange, ConditionalWriteOutcome, ExistingRowWrite, find_user_profile_by_email_query, list_user_profiles_query,
+    patch_user_profile_query, PreferencePath, RowCondition, select_preference_query, select_user_profile_audit_query, select_user_profile_by_key_query,
+    select_user_profile_query, select_user_profiles_by_user_id_query, set_preference_query, update_collection_query, update_full_name_and_age_query,
+    user_profile_batch_query, UserProfileAuditAction, UserProfileAuditRowStruct, UserProfileBatchType, UserProfilePage, UserProfilePatch,
+    UserProfileRowStruct, UserProfileWrite,
+};
+use serde_json::Value as JsonValue;
+use crate::yugabyte_db_ycql_config::CurrentYcqlDbSession;
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Storage operations the crud controllers need, implemented for YCQL and for in-memory tests.
+/// Every applied create, update and delete also adds a user_profile_audit row, atomically with the write
+#[async_trait]
+pub trait UserProfileRepository: Send + Sync + 'static {
+    /// Never overwrites an existing primary key, that comes back as ***NotApplied*** with the stored row.
+    /// Fails with ***AppError::Conflict*** when another profile already owns the email address
+    async fn create_user_profile(&self, user_profile_row: UserProfileRowStruct) -> AppResult<ConditionalWriteOutcome>;
+
+    async fn read_user_profile(&self, user_id: Uuid, email_address: String) -> AppResult<Vec<UserProfileRowStruct>>;
+
+    async fn find_by_email(&self, email_address: String) -> AppResult<Option<UserProfileRowStruct>>;
+
+    async fn read_user_profiles_by_user_id(&self, user_id: Uuid) -> AppResult<Vec<UserProfileRowStruct>>;
+
+    async fn read_user_profile_by_key(&self, user_id: Uuid, time_uuid_order: Uuid) -> AppResult<Option<UserProfileRowStruct>>;
+
+    /// Only a row passing ***condition*** is updated and then carries ***new_version***.
+    /// A rejected write reports the stored row, or None when there is no row.
+    /// Updates keep the row's remaining TTL, they never extend or remove it
+    async fn update_full_name_and_age(
+        &self,
+        user_id: Uuid,
+        time_uuid_order: Uuid,
+        age: i8,
+        full_name: String,
+        condition: RowCondition,
+        new_version: Uuid,
+    ) -> AppResult<ConditionalWriteOutcome>;
+
+    /// Only the columns set in ***patch*** change on a row passing ***condition***, callers make sure it is not empty
+    async fn patch_user_profile(
+        &self,
+        user_id: Uuid,
+        time_uuid_order: Uuid,
+        patch: UserProfilePatch,
+        condition: RowCondition,
+        new_version: Uuid,
+    ) -> AppResult<ConditionalWriteOutcome>;
+
+    /// Adds or removes collection elements on a row passing ***condition***, like patch_user_profile
+    async fn update_collection(
+        &self,
+        user_id: Uuid,
+        time_uuid_order: Uuid,
+        change: CollectionChange,
+        condition: RowCondition,
+        new_version: Uuid,
+    ) -> AppResult<ConditionalWriteOutcome>;
+
+    /// ***path*** of the preferences document as text, None when the row does not exist, Some(None) when the path does not
+    async fn read_preference(&self, user_id: Uuid, time_uuid_order: Uuid, path: PreferencePath) -> AppResult<Option<Option<String>>>;
+
+    /// Sets one path of the preferences document on a row passing ***condition***, like patch_user_profile.
+    /// Fails with ***AppError::Conflict*** when the object holding the last segment does not exist yet
+    async fn set_preference(
+        &self,
+        user_id: Uuid,
+        time_uuid_order: Uuid,
+        path: PreferencePath,
+        value: JsonValue,
+        condition: RowCondition,
+        new_version: Uuid,
+    ) -> AppResult<ConditionalWriteOutcome>;
+
+    async fn delete_user_profile(&self, user_id: Uuid, time_uuid_order: Uuid, condition: RowCondition) -> AppResult<ConditionalWriteOutcome>;
+
+    /// Applies every write of one user_id partition together with its audit rows, all or nothing.
+    /// A missing row, an existing create key or a failed condition rejects the whole group as ***NotApplied*** without a row
+    async fn apply_user_profile_batch(&self, batch_type: UserProfileBatchType, writes: Vec<UserProfileWrite>) -> AppResult<ConditionalWriteOutcome>;
+
+    /// Audit rows of one profile oldest first, still there after the profile is deleted
+    async fn read_user_profile_audit(&self, user_id: Uuid, time_uuid_order: Uuid) -> AppResult<Vec<UserProfileAuditRowStruct>>;
+
+    /// ***paging_state*** is the opaque value returned with the previous page, None for the first page
+    async fn list_user_profiles(&self, page_size: i32, paging_state: Option<Vec<u8>>) -> AppResult<UserProfilePage>;
+}
+
+/// A path can only be set below an object that is already stored, YCQL does not create the missing levels
+fn ensure_preference_parent(row: &UserProfileRowStruct, path: &PreferencePath) -> AppResult<()> {
+    if row.preferences.as_ref().is_some_and(|preferences| preferences.has_parent_of(path)) {
+        return Ok(());
+    }
+    Err(AppError::Conflict {
+        reason: format!("The preferences holding {} do not exist yet, store the enclosing object first", path),
+    })
+}
+
+/// UserProfileRepository backed by the YCQL query functions
+#[derive(Clone)]
+pub struct YcqlUserProfileRepository {
+    ycql_session: CurrentYcqlDbSession,
+}
+
+impl YcqlUserProfileRepository {
+    pub fn new(ycql_session: CurrentYcqlDbSession) -> YcqlUserProfileRepository {
+        YcqlUserProfileRepository { ycql_session }
+    }
+
+    /// ***cells_ttl*** of the stored row.
+    /// None when the row does not exist, the update is then answered without writing.
+    /// Costs a read before every update: YCQL has no ***USING TTL*** that keeps a row's remaining TTL, cells written
+    /// without one get the table default instead. The read is not atomic with the write, but the write still checks
+    /// its condition, and every write carries the TTL forward, so the row only lives the few milliseconds in between longer
+    async fn remaining_ttl(&self, user_id: Uuid, time_uuid_order: Uuid) -> AppResult<Option<i32>> {
+        Ok(self.read_user_profile_by_key(user_id, time_uuid_order).await?.as_ref().map(UserProfileRowStruct::cells_ttl))
+    }
+
+    /// A write rejected inside a batch returns no row, read it so callers can tell missing from stale.
+    /// Best-effort: the read is not atomic with the write, a concurrent write may change the row in between,
+    /// so the row reported with a 409 or 412 can be newer than the one the condition failed against
+    async fn with_current_row(&self, user_id: Uuid, time_uuid_order: Uuid, outcome: ConditionalWriteOutcome) -> AppResult<ConditionalWriteOutcome> {
+        match outcome {
+            ConditionalWriteOutcome::NotApplied { current: None } => Ok(ConditionalWriteOutcome::NotApplied {
+                current: self.read_user_profile_by_key(user_id, time_uuid_order).await?.map(Box::new),
+            }),
+            outcome => Ok(outcome),
+        }
+    }
+}
+
+#[async_trait]
+impl UserProfileRepository for YcqlUserProfileRepository {
+    async fn create_user_profile(&self, user_profile_row: UserProfileRowStruct) -> AppResult<ConditionalWriteOutcome> {
+        let (user_id, time_uuid_order) = (user_profile_row.user_id, user_profile_row.time_uuid_order);
+        let outcome = create_new_user_profile_query(&self.ycql_session, user_profile_row).await?;
+        self.with_current_row(user_id, time_uuid_order, outcome).await
+    }
+
+    async fn read_user_profile(&self, user_id: Uuid, email_address: String) -> AppResult<Vec<UserProfileRowStruct>> {
+        Ok(select_user_profile_query(&self.ycql_session, user_id, email_address).await?)
+    }
+
+    async fn find_by_email(&self, email_address: String) -> AppResult<Option<UserProfileRowStruct>> {
+        Ok(find_user_profile_by_email_query(&self.ycql_session, email_address).await?)
+    }
+
+    async fn read_user_profiles_by_user_id(&self, user_id: Uuid) -> AppResult<Vec<UserProfileRowStruct>> {
+        Ok(select_user_profiles_by_user_id_query(&self.ycql_session, user_id).await?)
+    }
+
+    async fn read_user_profile_by_key(&self, user_id: Uuid, time_uuid_order: Uuid) -> AppResult<Option<UserProfileRowStruct>> {
+        Ok(select_user_profile_by_key_query(&self.ycql_session, user_id, time_uuid_order).await?)
+    }
+
+    async fn update_full_name_and_age(
+        &self,
+        user_id: Uuid,
+        time_uuid_order: Uuid,
+        age: i8,
+        full_name: String,
+        condition: RowCondition,
+        new_version: Uuid,
+    ) -> AppResult<ConditionalWriteOutcome> {
+        let ttl_secs = match self.remaining_ttl(user_id, time_uuid_order).await? {
+            Some(ttl_secs) => ttl_secs,
+            None => return Ok(ConditionalWriteOutcome::NotApplied { current: None }),
+        };
+        let write = ExistingRowWrite { condition, new_version, ttl_secs };
+        let outcome = update_full_name_and_age_query(&self.ycql_session, user_id, time_uuid_order, age, full_name, write).await?;
+        self.with_current_row(user_id, time_uuid_order, outcome).await
+    }
+
+    async fn patch_user_profile(
+        &self,
+        user_id: Uuid,
+        time_uuid_order: Uuid,
+        patch: UserProfilePatch,
+        condition: RowCondition,
+        new_version: Uuid,
+    ) -> AppResult<ConditionalWriteOutcome> {
+        let ttl_secs = match self.remaining_ttl(user_id, time_uuid_order).await? {
+            Some(ttl_secs) => ttl_secs,
+            None => return Ok(ConditionalWriteOutcome::NotApplied { current: None }),
+        };
+        let write = ExistingRowWrite { condition, new_version, ttl_secs };
+        let outcome = patch_user_profile_query(&self.ycql_session, user_id, time_uuid_order, patch, write).await?;
+        self.with_current_row(user_id, time_uuid_order, outcome).await
+    }
+
+    async fn update_collection(
+        &self,
+        user_id: Uuid,
+        time_uuid_order: Uuid,
+        change: CollectionChange,
+        condition: RowCondition,
+        new_version: Uuid,
+    ) -> AppResult<ConditionalWriteOutcome> {
+        let ttl_secs = match self.remaining_ttl(user_id, time_uuid_order).await? {
+            Some(ttl_secs) => ttl_secs,
+            None => return Ok(ConditionalWriteOutcome::NotApplied { current: None }),
+        };
+        let write = ExistingRowWrite { condition, new_version, ttl_secs };
+        let outcome = update_collection_query(&self.ycql_session, user_id, time_uuid_order, change, write).await?;
+        self.with_current_row(user_id, time_uuid_order, outcome).await
+    }
+
+    async fn read_preference(&self, user_id: Uuid, time_uuid_order: Uuid, path: PreferencePath) -> AppResult<Option<Option<String>>> {
+        Ok(select_preference_query(&self.ycql_session, user_id, time_uuid_order, &path).await?)
+    }
+
+    async fn set_preference(
+        &self,
+        user_id: Uuid,
+        time_uuid_order: Uuid,
+        path: PreferencePath,
+        value: JsonValue,
+        condition: RowCondition,
+        new_version: Uuid,
+    ) -> AppResult<ConditionalWriteOutcome> {
+        let current = match self.read_user_profile_by_key(user_id, time_uuid_order).await? {
+            Some(current) => current,
+            None => return Ok(ConditionalWriteOutcome::NotApplied { current: None }),
+        };
+        // answer a stale condition before blaming the document shape, the write still checks it again
+        if !condition.matches(&current) {
+            return Ok(ConditionalWriteOutcome::NotApplied { current: Some(Box::new(current)) });
+        }
+        ensure_preference_parent(&current, &path)?;
+        let write = ExistingRowWrite { condition, new_version, ttl_secs: current.cells_ttl() };
+        let outcome = set_preference_query(&self.ycql_session, user_id, time_uuid_order, &path, value, write).await?;
+        self.with_current_row(user_id, time_uuid_order, outcome).await
+    }
+
+    async fn delete_user_profile(&self, user_id: Uuid, time_uuid_order: Uuid, condition: RowCondition) -> AppResult<ConditionalWriteOutcome> {
+        let outcome = delete_user_profile_query(&self.ycql_session, user_id, time_uuid_order, condition).await?;
+        self.with_current_row(user_id, time_uuid_order, outcome).await
+    }
+
+    async fn apply_user_profile_batch(&self, batch_type: UserProfileBatchType, writes: Vec<UserProfileWrite>) -> AppResult<ConditionalWriteOutcome> {
+        Ok(user_profile_batch_query(&self.ycql_session, batch_type, writes).await?)
+    }
+
+    async fn read_user_profile_audit(&self, user_id: Uuid, time_uuid_order: Uuid) -> AppResult<Vec<UserProfileAuditRowStruct>> {
+        Ok(select_user_profile_audit_query(&self.ycql_session, user_id, time_uuid_order).await?)
+    }
+
+    async fn list_user_profiles(&self, page_size: i32, paging_state: Option<Vec<u8>>) -> AppResult<UserProfilePage> {
+        Ok(list_user_profiles_query(&self.ycql_session, page_size, paging_state).await?)
+    }
+}
+
+/// UserProfileRepository kept in process memory, rows are ordered by (user_id, time_uuid_order) like the YCQL primary key.
+/// The locks are never held across an await point, audit rows are added while the rows lock is still held.
+#[derive(Default)]
+pub struct InMemoryUserProfileRepository {
+    rows: Mutex<BTreeMap<(Uuid, Uuid), StoredUserProfile>>,
+    audit: Mutex<Vec<UserProfileAuditRowStruct>>,
+}
+
+/// A row plus the deadline its ***USING TTL*** sets
+#[derive(Clone)]
+struct StoredUserProfile {
+    row: UserProfileRowStruct,
+    expires_at: Option<Instant>,
+}
+
+impl StoredUserProfile {
+    /// The row as a read returns it, ***ttl_secs*** counting down like ***TTL(email_address)***
+    fn read(&self) -> UserProfileRowStruct {
+        let mut row = self.row.clone();
+        row.ttl_secs = self
+            .expires_at
+            .map(|expires_at| expires_at.saturating_duration_since(Instant::now()).as_secs() as i32);
+        row
+    }
+}
+
+impl InMemoryUserProfileRepository {
+    /// Expired rows are dropped first, like YCQL never returning them
+    fn rows(&self) -> std::sync::MutexGuard<'_, BTreeMap<(Uuid, Uuid), StoredUserProfile>> {
+        let mut rows = self.rows.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+        rows.retain(|_, stored| stored.expires_at.is_none_or(|expires_at| expires_at > now));
+        rows
+    }
+
+    /// Call with the rows lock held so the audit row lands together with the write
+    fn record(&self, user_id: Uuid, time_uuid_order: Uuid, action: UserProfileAuditAction, version: Option<Uuid>) {
+        self.audit.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(UserProfileAuditRowStruct {
+            user_id,
+            time_uuid_order,
+            audit_id: Uuid::new_v4(),
+            action: action.to_string(),
+            version,
+        });
+    }
+}
+
+#[async_trait]
+impl UserProfileRepository for InMemoryUserProfileRepository {
+    async fn create_user_profile(&self, user_profile_row: UserProfileRowStruct) -> AppResult<ConditionalWriteOutcome> {
+        // same checks as INSERT ... IF NOT EXISTS plus the unique email index
+        let key = (user_profile_row.user_id, user_profile_row.time_uuid_order);
+        let mut rows = self.rows();
+        if let Some(current) = rows.get(&key) {
+            return Ok(ConditionalWriteOutcome::NotApplied { current: Some(Box::new(current.read())) });
+        }
+        if rows
+            .iter()
+            .any(|(existing_key, stored)| *existing_key != key && stored.row.email_address == user_profile_row.email_address)
+        {
+            return Err(AppError::email_already_registered());
+        }
+        let expires_at = user_profile_row
+            .ttl_secs
+            .map(|ttl_secs| Instant::now() + Duration::from_secs(ttl_secs.max(0) as u64));
+        self.record(key.0, key.1, UserProfileAuditAction::Created, user_profile_row.version);
+        rows.insert(key, StoredUserProfile { row: UserProfileRowStruct { ttl_secs: None, ..user_profile_row }, expires_at });
+        Ok(ConditionalWriteOutcome::Applied)
+    }
+
+    async fn read_user_profile(&self, user_id: Uuid, email_address: String) -> AppResult<Vec<UserProfileRowStruct>> {
+        Ok(self
+            .rows()
+            .values()
+            .filter(|stored| stored.row.user_id == user_id && stored.row.email_address == email_address)
+            .map(StoredUserProfile::read)
+            .collect())
+    }
+
+    async fn find_by_email(&self, email_address: String) -> AppResult<Option<UserProfileRowStruct>> {
+        Ok(self.rows().values().find(|stored| stored.row.email_address == email_address).map(StoredUserProfile::read))
+    }
+
+    async fn read_user_profiles_by_user_id(&self, user_id: Uuid) -> AppResult<Vec<UserProfileRowStruct>> {
+        Ok(self.rows().values().filter(|stored| stored.row.user_id == user_id).map(StoredUserProfile::read).collect())
+    }
+
+    async fn read_user_profile_by_key(&self, user_id: Uuid, time_uuid_order: Uuid) -> AppResult<Option<UserProfileRowStruct>> {
+        Ok(self.rows().get(&(user_id, time_uuid_order)).map(StoredUserProfile::read))
+    }
+
+    async fn update_full_name_and_age(
+        &self,
+        user_id: Uuid,
+        time_uuid_order: Uuid,
+        age: i8,
+        full_name: String,
+        condition: RowCondition,
+        new_version: Uuid,
+    ) -> AppResult<ConditionalWriteOutcome> {
+        let mut rows = self.rows();
+        let stored = match rows.get_mut(&(user_id, time_uuid_order)) {
+            Some(stored) => stored,
+            None => return Ok(ConditionalWriteOutcome::NotApplied { current: None }),
+        };
+        if !condition.matches(&stored.row) {
+            return Ok(ConditionalWriteOutcome::NotApplied { current: Some(Box::new(stored.read())) });
+        }
+        stored.row.age = age;
+        stored.row.full_name = full_name;
+        stored.row.version = Some(new_version);
+        self.record(user_id, time_uuid_order, UserProfileAuditAction::Updated, Some(new_version));
+        Ok(ConditionalWriteOutcome::Applied)
+    }
+
+    async fn patch_user_profile(
+        &self,
+        user_id: Uuid,
+        time_uuid_order: Uuid,
+        patch: UserProfilePatch,
+        condition: RowCondition,
+        new_version: Uuid,
+    ) -> AppResult<ConditionalWriteOutcome> {
+        let mut rows = self.rows();
+        let stored = match rows.get_mut(&(user_id, time_uuid_order)) {
+            Some(stored) => stored,
+            None => return Ok(ConditionalWriteOutcome::NotApplied { current: None }),
+        };
+        if !condition.matches(&stored.row) {
+            return Ok(ConditionalWriteOutcome::NotApplied { current: Some(Box::new(stored.read())) });
+        }
+        let row = &mut stored.row;
+        row.version = Some(new_version);
+        if let Some(full_name) = patch.full_name {
+            row.full_name = full_name;
+        }
+        if let Some(age) = patch.age {
+            row.age = age;
+        }
+        if let Some(comment) = patch.comment {
+            row.comment = comment;
+        }
+        if let Some(address) = patch.address {
+            row.address = address;
+        }
+        if let Some(preferences) = patch.preferences {
+            row.preferences = preferences;
+        }
+        self.record(user_id, time_uuid_order, UserProfileAuditAction::Updated, Some(new_version));
+        Ok(ConditionalWriteOutcome::Applied)
+    }
+
+    async fn update_collection(
+        &self,
+        user_id: Uuid,
+        time_uuid_order: Uuid,
+        change: CollectionChange,
+        condition: RowCondition,
+        new_version: Uuid,
+    ) -> AppResult<ConditionalWriteOutcome> {
+        let mut rows = self.rows();
+        let stored = match rows.get_mut(&(user_id, time_uuid_order)) {
+            Some(stored) => stored,
+            None => return Ok(ConditionalWriteOutcome::NotApplied { current: None }),
+        };
+        if !condition.matches(&stored.row) {
+            return Ok(ConditionalWriteOutcome::NotApplied { current: Some(Box::new(stored.read())) });
+        }
+        change.apply_to(&mut stored.row);
+        stored.row.version = Some(new_version);
+        self.record(user_id, time_uuid_order, UserProfileAuditAction::Updated, Some(new_version));
+        Ok(ConditionalWriteOutcome::Applied)
+    }
+
+    async fn read_preference(&self, user_id: Uuid, time_uuid_order: Uuid, path: PreferencePath) -> AppResult<Option<Option<String>>> {
+        Ok(self
+            .rows()
+            .get(&(user_id, time_uuid_order))
+            .map(|stored| stored.row.preferences.as_ref().and_then(|preferences| preferences.text_at(&path))))
+    }
+
+    async fn set_preference(
+        &self,
+        user_id: Uuid,
+        time_uuid_order: Uuid,
+        path: PreferencePath,
+        value: JsonValue,
+        condition: RowCondition,
+        new_version: Uuid,
+    ) -> AppResult<ConditionalWriteOutcome> {
+        let mut rows = self.rows();
+        let stored = match rows.get_mut(&(user_id, time_uuid_order)) {
+            Some(stored) => stored,
+            None => return Ok(ConditionalWriteOutcome::NotApplied { current: None }),
+        };
+        if !condition.matches(&stored.row) {
+            return Ok(ConditionalWriteOutcome::NotApplied { current: Some(Box::new(stored.read())) });
+        }
+        ensure_preference_parent(&stored.row, &path)?;
+        if let Some(preferences) = stored.row.preferences.as_mut() {
+            preferences.set_at(&path, value);
+        }
+        stored.row.version = Some(new_version);
+        self.record(user_id, time_uuid_order, UserProfileAuditAction::Updated, Some(new_version));
+        Ok(ConditionalWriteOutcome::Applied)
+    }
+
+    async fn delete_user_profile(&self, user_id: Uuid, time_uuid_order: Uuid, condition: RowCondition) -> AppResult<ConditionalWriteOutcome> {
+        let mut rows = self.rows();
+        let key = (user_id, time_uuid_order);
+        match rows.get(&key) {
+            None => Ok(ConditionalWriteOutcome::NotApplied { current: None }),
+            Some(stored) if !condition.matches(&stored.row) => Ok(ConditionalWriteOutcome::NotApplied { current: Some(Box::new(stored.read())) }),
+            Some(_) => {
+                rows.remove(&key);
+                self.record(user_id, time_uuid_order, UserProfileAuditAction::Deleted, None);
+                Ok(ConditionalWriteOutcome::Applied)
+            }
+        }
+    }
+
+    /// Applied to a copy of the rows first, the copy only replaces them when every write passed
+    async fn apply_user_profile_batch(&self, _batch_type: UserProfileBatchType, writes: Vec<UserProfileWrite>) -> AppResult<ConditionalWriteOutcome> {
+        let mut rows = self.rows();
+        let mut staged = rows.clone();
+        let mut audit = Vec::with_capacity(writes.len());
+        for write in writes {
+            let key = write.key();
+            match write {
+                UserProfileWrite::Create(row) => {
+                    if staged.contains_key(&key) {
+                        return Ok(ConditionalWriteOutcome::NotApplied { current: None });
+                    }
+                    if staged.values().any(|stored| stored.row.email_address == row.email_address) {
+                        return Err(AppError::email_already_registered());
+                    }
+                    let expires_at = row.ttl_secs.map(|ttl_secs| Instant::now() + Duration::from_secs(ttl_secs.max(0) as u64));
+                    audit.push((key, UserProfileAuditAction::Created, row.version));
+                    staged.insert(key, StoredUserProfile { row: UserProfileRowStruct { ttl_secs: None, ..*row }, expires_at });
+                }
+                UserProfileWrite::UpdateFullNameAndAge { age, full_name, write, .. } => match staged.get_mut(&key) {
+                    Some(stored) if write.condition.matches(&stored.row) => {
+                        stored.row.age = age;
+                        stored.row.full_name = full_name;
+                        stored.row.version = Some(write.new_version);
+                        audit.push((key, UserProfileAuditAction::Updated, Some(write.new_version)));
+                    }
+                    _ => return Ok(ConditionalWriteOutcome::NotApplied { current: None }),
+                },
+                UserProfileWrite::Delete { condition, .. } => match staged.get(&key) {
+                    Some(stored) if condition.matches(&stored.row) => {
+                        staged.remove(&key);
+                        audit.push((key, UserProfileAuditAction::Deleted, None));
+                    }
+                    _ => return Ok(ConditionalWriteOutcome::NotApplied { current: None }),
+                },
+            }
+        }
+        *rows = staged;
+        for ((user_id, time_uuid_order), action, version) in audit {
+            self.record(user_id, time_uuid_order, action, version);
+        }
+        Ok(ConditionalWriteOutcome::Applied)
+    }
+
+    async fn read_user_profile_audit(&self, user_id: Uuid, time_uuid_order: Uuid) -> AppResult<Vec<UserProfileAuditRowStruct>> {
+        Ok(self
+            .audit
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .filter(|audit_row| audit_row.user_id == user_id && audit_row.time_uuid_order == time_uuid_order)
+            .cloned()
+            .collect())
+    }
+
+    /// Paging state is the big endian offset of the next row
+    async fn list_user_profiles(&self, page_size: i32, paging_state: Option<Vec<u8>>) -> AppResult<UserProfilePage> {
+        let offset = match paging_state {
+            None => 0,
+            Some(bytes) => {
+                let offset: [u8; 8] = bytes
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| AppError::bad_request("Invalid cursor"))?;
+                u64::from_be_bytes(offset) as usize
+            }
+        };
+        let page_size = page_size.max(1) as usize;
+        let rows = self.rows();
+        let user_profiles: Vec<UserProfileRowStruct> = rows.values().skip(offset).take(page_size).map(StoredUserProfile::read).collect();
+        let next_offset = offset + user_profiles.len();
+        let paging_state = if next_offset < rows.len() {
+            Some((next_offset as u64).to_be_bytes().to_vec())
+        } else {
+            None
+        };
+        Ok(UserProfilePage { user_profiles, paging_state })
+    }
+}
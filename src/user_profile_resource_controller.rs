@@ -0,0 +1,540 @@
+use crate::application_error::AppError;
+use crate::crud_controller::{
+    apply_collection_change, apply_preference_update, apply_user_profile_patch, insert_new_user_profile, list_user_profiles_page, read_preference_value,
+    remove_user_profile, validate_preference_value, CollectionChangeJsonREQUEST, ListUserProfilesJsonREQUEST, UserProfileJsonREQUEST,
+    UserProfilePatchJsonREQUEST,
+};
+use crate::default_strings::CustomStatusMessage;
+use crate::generic_json_success_response::GenericJsonSuccessResponse;
+use crate::time_uuid_generator::TimeUuidGenerator;
+use crate::user_profile_access_policy::{AuthorizedCaller, Permission};
+use crate::user_profile_etag::insert_etag;
+use crate::user_profile_repository::UserProfileRepository;
+use crate::validated_json_extractor::ValidatedJson;
+use crate::ycql_db_schema_and_queries::{PreferencePath, UserProfileAuditRowStruct, UserProfileRowStruct};
+use crate::ycql_service_config::PaginationConfig;
+use actix_web::{http::header, web, HttpRequest};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+use validator::Validate;
+
+/// REST surface for user profiles, mounted under ***/api_v1/users*** next to the RPC style POST routes.
+/// Malformed path or query values go through the ***PathConfig*** / ***QueryConfig*** error handlers registered in main.rs.
+pub fn user_profile_resource_routes<R: UserProfileRepository>(cfg: &mut web::ServiceConfig) {
+    cfg.route("", web::post().to(create_user_resource::<R>))
+        .route("", web::get().to(list_user_resources::<R>))
+        .route("/{user_id}", web::get().to(read_user_resource::<R>))
+        .route("/{user_id}/profiles/{time_uuid_order}", web::get().to(read_user_profile_resource::<R>))
+        .route("/{user_id}/profiles/{time_uuid_order}", web::patch().to(patch_user_profile_resource::<R>))
+        .route("/{user_id}/profiles/{time_uuid_order}", web::delete().to(delete_user_profile_resource::<R>))
+        .route("/{user_id}/profiles/{time_uuid_order}/audit", web::get().to(read_user_profile_audit_resource::<R>))
+        .route("/{user_id}/profiles/{time_uuid_order}/tags/{tag}", web::put().to(add_tag_resource::<R>))
+        .route("/{user_id}/profiles/{time_uuid_order}/tags/{tag}", web::delete().to(remove_tag_resource::<R>))
+        .route("/{user_id}/profiles/{time_uuid_order}/phone_numbers", web::post().to(append_phone_number_resource::<R>))
+        .route("/{user_id}/profiles/{time_uuid_order}/phone_numbers/{phone_number}", web::delete().to(remove_phone_number_resource::<R>))
+        .route("/{user_id}/profiles/{time_uuid_order}/attributes/{key}", web::put().to(put_attribute_resource::<R>))
+        .route("/{user_id}/profiles/{time_uuid_order}/attributes/{key}", web::delete().to(remove_attribute_resource::<R>))
+        .route("/{user_id}/profiles/{time_uuid_order}/preferences/{path}", web::get().to(read_preference_resource::<R>))
+        .route("/{user_id}/profiles/{time_uuid_order}/preferences/{path}", web::put().to(put_preference_resource::<R>));
+}
+
+fn success_body<T: serde::Serialize>(message: &str, status: u16, data: Option<T>) -> String {
+    serde_json::to_string(&GenericJsonSuccessResponse::<T>::generic_success_response(
+        CustomStatusMessage::USER_REQUEST_SUCCESS.to_string(),
+        message.to_string(),
+        status,
+        data,
+    ))
+    .unwrap()
+}
+
+/// POST /users, answers 201 with the new row and its Location
+pub async fn create_user_resource<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    request: HttpRequest,
+    user_profile_repository: web::Data<R>,
+    time_uuid_generator: web::Data<TimeUuidGenerator>,
+    get_user_profile: ValidatedJson<UserProfileJsonREQUEST>,
+) -> Result<web::HttpResponse, AppError> {
+    caller.require(Permission::CreateProfile, None).await?;
+    let row = insert_new_user_profile(user_profile_repository.get_ref(), &time_uuid_generator, &get_user_profile).await?;
+    let location = format!("{}/{}/profiles/{}", request.path().trim_end_matches('/'), row.user_id, row.time_uuid_order);
+
+    let mut response = web::HttpResponse::Created();
+    insert_etag(&mut response, row.version);
+    Ok(response
+        .content_type("application/json")
+        .insert_header((header::LOCATION, location))
+        .body(success_body("User profile created successfuly", 201, Some(row))))
+}
+
+/// GET /users?page_size=&cursor=
+pub async fn list_user_resources<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    user_profile_repository: web::Data<R>,
+    pagination_config: web::Data<PaginationConfig>,
+    get_page: web::Query<ListUserProfilesJsonREQUEST>,
+) -> Result<web::HttpResponse, AppError> {
+    caller.require(Permission::ListProfiles, None).await?;
+    get_page.validate()?;
+    list_user_profiles_page(user_profile_repository.get_ref(), &pagination_config, &get_page).await
+}
+
+/// GET /users/{user_id}, every profile row of the user
+pub async fn read_user_resource<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    user_profile_repository: web::Data<R>,
+    user_id: web::Path<Uuid>,
+) -> Result<web::HttpResponse, AppError> {
+    caller.require(Permission::ReadProfile, Some(*user_id)).await?;
+    let user_profiles = user_profile_repository.read_user_profiles_by_user_id(*user_id).await?;
+    if user_profiles.is_empty() {
+        return Err(AppError::not_found("No user profile with this user id"));
+    }
+
+    Ok(web::HttpResponse::Ok()
+        .content_type("application/json")
+        .body(success_body::<Vec<UserProfileRowStruct>>("success", 200, Some(user_profiles))))
+}
+
+/// GET /users/{user_id}/profiles/{time_uuid_order}
+pub async fn read_user_profile_resource<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    user_profile_repository: web::Data<R>,
+    key: web::Path<(Uuid, Uuid)>,
+) -> Result<web::HttpResponse, AppError> {
+    let (user_id, time_uuid_order) = key.into_inner();
+    caller.require(Permission::ReadProfile, Some(user_id)).await?;
+    let user_profile = user_profile_repository
+        .read_user_profile_by_key(user_id, time_uuid_order)
+        .await?
+        .ok_or_else(|| AppError::not_found("No user profile with this user id and time_uuid_order"))?;
+
+    let mut response = web::HttpResponse::Ok();
+    insert_etag(&mut response, user_profile.version);
+    Ok(response
+        .content_type("application/json")
+        .body(success_body("success", 200, Some(user_profile))))
+}
+
+/// PATCH /users/{user_id}/profiles/{time_uuid_order}, honours If-Match
+pub async fn patch_user_profile_resource<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    request: HttpRequest,
+    user_profile_repository: web::Data<R>,
+    key: web::Path<(Uuid, Uuid)>,
+    get_patch_fields: ValidatedJson<UserProfilePatchJsonREQUEST>,
+) -> Result<web::HttpResponse, AppError> {
+    let (user_id, time_uuid_order) = key.into_inner();
+    caller.require(Permission::UpdateProfile, Some(user_id)).await?;
+    let patch = get_patch_fields.to_patch()?;
+    apply_user_profile_patch(user_profile_repository.get_ref(), &request, user_id, time_uuid_order, patch).await
+}
+
+/// DELETE /users/{user_id}/profiles/{time_uuid_order}, honours If-Match
+pub async fn delete_user_profile_resource<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    request: HttpRequest,
+    user_profile_repository: web::Data<R>,
+    key: web::Path<(Uuid, Uuid)>,
+) -> Result<web::HttpResponse, AppError> {
+    let (user_id, time_uuid_order) = key.into_inner();
+    caller.require(Permission::DeleteProfile, Some(user_id)).await?;
+    remove_user_profile(user_profile_repository.get_ref(), &request, user_id, time_uuid_order).await
+}
+
+/// GET /users/{user_id}/profiles/{time_uuid_order}/audit, every recorded write oldest first, also for deleted profiles
+pub async fn read_user_profile_audit_resource<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    user_profile_repository: web::Data<R>,
+    key: web::Path<(Uuid, Uuid)>,
+) -> Result<web::HttpResponse, AppError> {
+    let (user_id, time_uuid_order) = key.into_inner();
+    caller.require(Permission::ReadAudit, Some(user_id)).await?;
+    let audit_rows = user_profile_repository.read_user_profile_audit(user_id, time_uuid_order).await?;
+    if audit_rows.is_empty() {
+        return Err(AppError::not_found("No audit rows for this user id and time_uuid_order"));
+    }
+
+    Ok(web::HttpResponse::Ok()
+        .content_type("application/json")
+        .body(success_body::<Vec<UserProfileAuditRowStruct>>("success", 200, Some(audit_rows))))
+}
+
+///Json Request Struct for |append_phone_number_resource| FUNCTION, checked with the collection rules
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct PhoneNumberJsonREQUEST {
+    phone_number: String,
+}
+
+///Json Request Struct for |put_attribute_resource| FUNCTION, checked with the collection rules
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct AttributeValueJsonREQUEST {
+    value: String,
+}
+
+/// Validates a single element change and applies it, honours If-Match
+async fn change_collection_element<R: UserProfileRepository>(
+    request: &HttpRequest,
+    user_profile_repository: &R,
+    user_id: Uuid,
+    time_uuid_order: Uuid,
+    change: CollectionChangeJsonREQUEST,
+) -> Result<web::HttpResponse, AppError> {
+    let change = change.to_change()?;
+    apply_collection_change(user_profile_repository, request, user_id, time_uuid_order, change).await
+}
+
+/// PUT /users/{user_id}/profiles/{time_uuid_order}/tags/{tag}, adding a tag twice is a no-op
+pub async fn add_tag_resource<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    request: HttpRequest,
+    user_profile_repository: web::Data<R>,
+    key: web::Path<(Uuid, Uuid, String)>,
+) -> Result<web::HttpResponse, AppError> {
+    let (user_id, time_uuid_order, tag) = key.into_inner();
+    caller.require(Permission::UpdateProfile, Some(user_id)).await?;
+    let change = CollectionChangeJsonREQUEST::AddTags(vec![tag]);
+    change_collection_element(&request, user_profile_repository.get_ref(), user_id, time_uuid_order, change).await
+}
+
+/// DELETE /users/{user_id}/profiles/{time_uuid_order}/tags/{tag}
+pub async fn remove_tag_resource<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    request: HttpRequest,
+    user_profile_repository: web::Data<R>,
+    key: web::Path<(Uuid, Uuid, String)>,
+) -> Result<web::HttpResponse, AppError> {
+    let (user_id, time_uuid_order, tag) = key.into_inner();
+    caller.require(Permission::UpdateProfile, Some(user_id)).await?;
+    let change = CollectionChangeJsonREQUEST::RemoveTags(vec![tag]);
+    change_collection_element(&request, user_profile_repository.get_ref(), user_id, time_uuid_order, change).await
+}
+
+/// POST /users/{user_id}/profiles/{time_uuid_order}/phone_numbers, appends to the end of the list
+pub async fn append_phone_number_resource<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    request: HttpRequest,
+    user_profile_repository: web::Data<R>,
+    key: web::Path<(Uuid, Uuid)>,
+    get_phone_number: ValidatedJson<PhoneNumberJsonREQUEST>,
+) -> Result<web::HttpResponse, AppError> {
+    let (user_id, time_uuid_order) = key.into_inner();
+    caller.require(Permission::UpdateProfile, Some(user_id)).await?;
+    let change = CollectionChangeJsonREQUEST::AppendPhoneNumbers(vec![get_phone_number.phone_number.clone()]);
+    change_collection_element(&request, user_profile_repository.get_ref(), user_id, time_uuid_order, change).await
+}
+
+/// DELETE /users/{user_id}/profiles/{time_uuid_order}/phone_numbers/{phone_number}, removes every occurrence
+pub async fn remove_phone_number_resource<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    request: HttpRequest,
+    user_profile_repository: web::Data<R>,
+    key: web::Path<(Uuid, Uuid, String)>,
+) -> Result<web::HttpResponse, AppError> {
+    let (user_id, time_uuid_order, phone_number) = key.into_inner();
+    caller.require(Permission::UpdateProfile, Some(user_id)).await?;
+    let change = CollectionChangeJsonREQUEST::RemovePhoneNumbers(vec![phone_number]);
+    change_collection_element(&request, user_profile_repository.get_ref(), user_id, time_uuid_order, change).await
+}
+
+/// PUT /users/{user_id}/profiles/{time_uuid_order}/attributes/{key}, inserts or overwrites one entry
+pub async fn put_attribute_resource<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    request: HttpRequest,
+    user_profile_repository: web::Data<R>,
+    key: web::Path<(Uuid, Uuid, String)>,
+    get_value: ValidatedJson<AttributeValueJsonREQUEST>,
+) -> Result<web::HttpResponse, AppError> {
+    let (user_id, time_uuid_order, attribute_key) = key.into_inner();
+    caller.require(Permission::UpdateProfile, Some(user_id)).await?;
+    let mut attributes = HashMap::new();
+    attributes.insert(attribute_key, get_value.value.clone());
+    let change = CollectionChangeJsonREQUEST::PutAttributes(attributes);
+    change_collection_element(&request, user_profile_repository.get_ref(), user_id, time_uuid_order, change).await
+}
+
+/// DELETE /users/{user_id}/profiles/{time_uuid_order}/attributes/{key}
+pub async fn remove_attribute_resource<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    request: HttpRequest,
+    user_profile_repository: web::Data<R>,
+    key: web::Path<(Uuid, Uuid, String)>,
+) -> Result<web::HttpResponse, AppError> {
+    let (user_id, time_uuid_order, attribute_key) = key.into_inner();
+    caller.require(Permission::UpdateProfile, Some(user_id)).await?;
+    let change = CollectionChangeJsonREQUEST::RemoveAttributes(vec![attribute_key]);
+    change_collection_element(&request, user_profile_repository.get_ref(), user_id, time_uuid_order, change).await
+}
+
+///Json Request Struct for |put_preference_resource| FUNCTION
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct PreferenceValueJsonREQUEST {
+    #[validate(custom = "validate_preference_value")]
+    value: serde_json::Value,
+}
+
+fn preference_path(path: &str) -> Result<PreferencePath, AppError> {
+    PreferencePath::parse(path).map_err(|reason| AppError::bad_request(&format!("path {}", reason)))
+}
+
+/// GET /users/{user_id}/profiles/{time_uuid_order}/preferences/{path}, eg ***.../preferences/theme.color***
+pub async fn read_preference_resource<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    user_profile_repository: web::Data<R>,
+    key: web::Path<(Uuid, Uuid, String)>,
+) -> Result<web::HttpResponse, AppError> {
+    let (user_id, time_uuid_order, path) = key.into_inner();
+    caller.require(Permission::ReadProfile, Some(user_id)).await?;
+    read_preference_value(user_profile_repository.get_ref(), user_id, time_uuid_order, preference_path(&path)?).await
+}
+
+/// PUT /users/{user_id}/profiles/{time_uuid_order}/preferences/{path}, honours If-Match
+pub async fn put_preference_resource<R: UserProfileRepository>(
+    caller: AuthorizedCaller,
+    request: HttpRequest,
+    user_profile_repository: web::Data<R>,
+    key: web::Path<(Uuid, Uuid, String)>,
+    get_value: ValidatedJson<PreferenceValueJsonREQUEST>,
+) -> Result<web::HttpResponse, AppError> {
+    let (user_id, time_uuid_order, path) = key.into_inner();
+    caller.require(Permission::UpdateProfile, Some(user_id)).await?;
+    let path = preference_path(&path)?;
+    apply_preference_update(user_profile_repository.get_ref(), &request, user_id, time_uuid_order, path, get_value.value.clone()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler_test_app::{app_as, ops_admin};
+    use crate::time_uuid_generator::SystemClock;
+    use crate::user_profile_repository::InMemoryUserProfileRepository;
+    use actix_web::{http::StatusCode, test};
+    use serde_json::json;
+
+    macro_rules! in_memory_resource_app {
+        ($repository:expr) => {
+            in_memory_resource_app!($repository, ops_admin())
+        };
+        ($repository:expr, $claims:expr) => {
+            test::init_service(
+                app_as($claims)
+                    .app_data($repository.clone())
+                    .app_data(web::Data::new(PaginationConfig::default()))
+                    .app_data(web::Data::new(TimeUuidGenerator::new(None, Box::new(SystemClock))))
+                    .app_data(web::PathConfig::default().error_handler(crate::path_error_handler))
+                    .app_data(web::QueryConfig::default().error_handler(crate::query_error_handler))
+                    .service(web::scope("/users").configure(user_profile_resource_routes::<InMemoryUserProfileRepository>)),
+            )
+            .await
+        };
+    }
+
+    #[actix_rt::test]
+    async fn created_profile_is_reachable_at_its_location() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let app = in_memory_resource_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/users")
+            .set_json(&json!({"email_address": "jane@demo.com", "full_name": "Jane Doe", "age": 26}))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let location = response.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+
+        let request = test::TestRequest::get().uri(&location).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["data"]["email_address"], "jane@demo.com");
+
+        let request = test::TestRequest::patch().uri(&location).set_json(&json!({"comment": "hello"})).to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::OK);
+
+        let request = test::TestRequest::delete().uri(&location).to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::OK);
+
+        let request = test::TestRequest::get().uri(&location).to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn every_applied_write_leaves_an_audit_row() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let app = in_memory_resource_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/users")
+            .set_json(&json!({"email_address": "jane@demo.com", "full_name": "Jane Doe", "age": 26}))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        let location = response.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+
+        let request = test::TestRequest::patch().uri(&location).set_json(&json!({"age": 27})).to_request();
+        let response = test::call_service(&app, request).await;
+        let patched_etag = response.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+
+        // rejected writes are rolled back together with their audit row
+        let request = test::TestRequest::delete()
+            .uri(&location)
+            .insert_header((header::IF_MATCH, format!("\"{}\"", Uuid::new_v4())))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::PRECONDITION_FAILED);
+
+        let request = test::TestRequest::delete().uri(&location).to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::OK);
+
+        let request = test::TestRequest::get().uri(&format!("{}/audit", location)).to_request();
+        let body: serde_json::Value = test::read_body_json(test::call_service(&app, request).await).await;
+        let audit_rows = body["data"].as_array().unwrap();
+        let actions: Vec<&str> = audit_rows.iter().map(|audit_row| audit_row["action"].as_str().unwrap()).collect();
+        assert_eq!(actions, vec!["created", "updated", "deleted"]);
+        assert_eq!(format!("\"{}\"", audit_rows[1]["version"].as_str().unwrap()), patched_etag);
+        assert!(audit_rows[2]["version"].is_null());
+    }
+
+    #[actix_rt::test]
+    async fn reads_carry_an_etag_that_guards_deletes() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let app = in_memory_resource_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/users")
+            .set_json(&json!({"email_address": "jane@demo.com", "full_name": "Jane Doe", "age": 26}))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        let location = response.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+        let created_etag = response.headers().get(header::ETAG).unwrap().clone();
+
+        let request = test::TestRequest::get().uri(&location).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.headers().get(header::ETAG), Some(&created_etag));
+
+        let request = test::TestRequest::patch()
+            .uri(&location)
+            .insert_header((header::IF_MATCH, created_etag.clone()))
+            .set_json(&json!({"age": 27}))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let patched_etag = response.headers().get(header::ETAG).unwrap().clone();
+
+        let request = test::TestRequest::delete()
+            .uri(&location)
+            .insert_header((header::IF_MATCH, created_etag))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::PRECONDITION_FAILED);
+
+        let request = test::TestRequest::delete()
+            .uri(&location)
+            .insert_header((header::IF_MATCH, patched_etag))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn collection_elements_round_trip_through_their_routes() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let app = in_memory_resource_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/users")
+            .set_json(&json!({
+                "email_address": "jane@demo.com", "full_name": "Jane Doe", "age": 26,
+                "address": {"street": "1 Main St", "city": "Lisbon", "postal_code": "1000-001", "country": "PT"},
+                "tags": ["vip", "beta", "vip"],
+                "phone_numbers": ["+351 210 000 000"],
+                "attributes": {"plan": "gold"}
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let location = response.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["data"]["tags"], json!(["beta", "vip"]));
+
+        let changes = vec![
+            test::TestRequest::put().uri(&format!("{}/tags/new", location)),
+            test::TestRequest::delete().uri(&format!("{}/tags/beta", location)),
+            test::TestRequest::post()
+                .uri(&format!("{}/phone_numbers", location))
+                .set_json(&json!({"phone_number": "555-0100"})),
+            test::TestRequest::put()
+                .uri(&format!("{}/attributes/locale", location))
+                .set_json(&json!({"value": "pt-PT"})),
+            test::TestRequest::delete().uri(&format!("{}/attributes/plan", location)),
+        ];
+        for change in changes {
+            assert_eq!(test::call_service(&app, change.to_request()).await.status(), StatusCode::OK);
+        }
+
+        let request = test::TestRequest::get().uri(&location).to_request();
+        let body: serde_json::Value = test::read_body_json(test::call_service(&app, request).await).await;
+        assert_eq!(body["data"]["address"]["city"], "Lisbon");
+        assert_eq!(body["data"]["tags"], json!(["new", "vip"]));
+        assert_eq!(body["data"]["phone_numbers"], json!(["+351 210 000 000", "555-0100"]));
+        assert_eq!(body["data"]["attributes"], json!({"locale": "pt-PT"}));
+
+        let request = test::TestRequest::post()
+            .uri(&format!("{}/phone_numbers", location))
+            .set_json(&json!({"phone_number": "call me"}))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn preference_paths_are_read_and_set_without_replacing_the_document() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let app = in_memory_resource_app!(repository);
+
+        let request = test::TestRequest::post()
+            .uri("/users")
+            .set_json(&json!({
+                "email_address": "jane@demo.com", "full_name": "Jane Doe", "age": 26,
+                "preferences": {"theme": {"color": "dark", "font_size": 14}, "language": "en"}
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        let location = response.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+
+        let request = test::TestRequest::get().uri(&format!("{}/preferences/theme.color", location)).to_request();
+        let body: serde_json::Value = test::read_body_json(test::call_service(&app, request).await).await;
+        assert_eq!(body["data"], json!({"path": "theme.color", "value": "dark"}));
+
+        let request = test::TestRequest::put()
+            .uri(&format!("{}/preferences/theme.color", location))
+            .set_json(&json!({"value": "light"}))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::OK);
+
+        let request = test::TestRequest::get().uri(&location).to_request();
+        let body: serde_json::Value = test::read_body_json(test::call_service(&app, request).await).await;
+        assert_eq!(body["data"]["preferences"], json!({"theme": {"color": "light", "font_size": 14}, "language": "en"}));
+
+        // YCQL only sets a path below an object that already exists
+        let request = test::TestRequest::put()
+            .uri(&format!("{}/preferences/layout.density", location))
+            .set_json(&json!({"value": "compact"}))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::CONFLICT);
+
+        let request = test::TestRequest::get().uri(&format!("{}/preferences/theme'color", location)).to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn malformed_path_and_query_answer_json_bad_request() {
+        let repository = web::Data::new(InMemoryUserProfileRepository::default());
+        let app = in_memory_resource_app!(repository);
+
+        for uri in &["/users/not-a-uuid", "/users?page_size=lots"] {
+            let request = test::TestRequest::get().uri(uri).to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            let body: serde_json::Value = test::read_body_json(response).await;
+            assert_eq!(body["custom_status"], CustomStatusMessage::USER_REQUEST_FAILED);
+        }
+    }
+}
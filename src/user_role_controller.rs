@@ -0,0 +1,198 @@
+use crate::application_error::AppError;
+use crate::crud_controller::written_response;
+use crate::default_strings::CustomStatusMessage;
+use crate::generic_json_success_response::GenericJsonSuccessResponse;
+use crate::user_profile_access_policy::{AuthorizedCaller, Permission};
+use crate::validated_json_extractor::ValidatedJson;
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Permission checks and role assignment, mounted inside the protected ***/api_v1*** scope
+pub fn user_role_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/check_permission", web::post().to(check_permission))
+        .route("/assign_user_role", web::post().to(assign_user_role))
+        .route("/revoke_user_role", web::post().to(revoke_user_role))
+        .route("/list_user_roles", web::post().to(list_user_roles));
+}
+
+fn success_response<T: Serialize>(data: T) -> web::HttpResponse {
+    web::HttpResponse::Ok()
+    .content_type("application/json")
+    .body(serde_json::to_string(
+        &GenericJsonSuccessResponse::<T>::generic_success_response(CustomStatusMessage::USER_REQUEST_SUCCESS.to_string(), "success".to_string(), 200, Some(data))
+    ).unwrap())
+}
+
+///Json Request Struct for |check_permission| FUNCTION, leave out ***subject*** to check the caller
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct CheckPermissionJsonREQUEST {
+    #[validate(length(min = 1, max = 200, message = "must be between 1 and 200 characters"))]
+    subject: Option<String>,
+    permission: Permission,
+    /// The profile the operation would be about, leave out for operations across users
+    user_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PermissionCheckJsonRESPONSE {
+    subject: String,
+    permission: Permission,
+    user_id: Option<Uuid>,
+    granted: bool,
+    /// ***owner***, ***role:<name>*** or ***api_key:<key_id>***
+    granted_by: Option<String>,
+}
+
+/// The caller's own check uses its token roles too, checking another subject needs roles:manage
+/// and only counts the roles assigned in ***user_roles***
+pub async fn check_permission(
+    caller: AuthorizedCaller,
+    get_check: ValidatedJson<CheckPermissionJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    let caller_subject = caller.subject();
+    let decision = match get_check.subject.as_deref() {
+        Some(subject) if subject != caller_subject => {
+            caller.require(Permission::ManageRoles, None).await?;
+            caller.access_control().decide_for_subject(subject, &[], get_check.permission, get_check.user_id).await?
+        }
+        _ => caller.decide(get_check.permission, get_check.user_id).await?,
+    };
+    let granted_by = decision.granted_by();
+
+    Ok(success_response(PermissionCheckJsonRESPONSE {
+        subject: get_check.subject.clone().unwrap_or(caller_subject),
+        permission: get_check.permission,
+        user_id: get_check.user_id,
+        granted: granted_by.is_some(),
+        granted_by,
+    }))
+}
+
+///Json Request Struct for |assign_user_role| and |revoke_user_role| FUNCTIONS
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct UserRoleJsonREQUEST {
+    /// Token ***sub*** the role is assigned to
+    #[validate(length(min = 1, max = 200, message = "must be between 1 and 200 characters"))]
+    subject: String,
+    #[validate(length(min = 1, max = 64, message = "must be between 1 and 64 characters"))]
+    role: String,
+}
+
+pub async fn assign_user_role(
+    caller: AuthorizedCaller,
+    get_assignment: ValidatedJson<UserRoleJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    caller.require(Permission::ManageRoles, None).await?;
+    caller.access_control().assign_role(&get_assignment.subject, &get_assignment.role).await?;
+    log::info!("role assigned: subject={} role={} by={}", get_assignment.subject, get_assignment.role, caller.subject());
+
+    Ok(written_response("Successfuly assigned role", None))
+}
+
+pub async fn revoke_user_role(
+    caller: AuthorizedCaller,
+    get_assignment: ValidatedJson<UserRoleJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    caller.require(Permission::ManageRoles, None).await?;
+    caller.access_control().revoke_role(&get_assignment.subject, &get_assignment.role).await?;
+    log::info!("role revoked: subject={} role={} by={}", get_assignment.subject, get_assignment.role, caller.subject());
+
+    Ok(written_response("Successfuly revoked role", None))
+}
+
+///Json Request Struct for |list_user_roles| FUNCTION, leave out ***subject*** for the caller's own roles
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct ListUserRolesJsonREQUEST {
+    #[validate(length(min = 1, max = 200, message = "must be between 1 and 200 characters"))]
+    subject: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserRolesJsonRESPONSE {
+    subject: String,
+    /// Only the ***user_roles*** assignments, roles carried in the token are not stored anywhere
+    roles: Vec<String>,
+}
+
+pub async fn list_user_roles(
+    caller: AuthorizedCaller,
+    get_subject: ValidatedJson<ListUserRolesJsonREQUEST>
+)-> Result<web::HttpResponse, AppError>{
+
+    let subject = get_subject.subject.clone().unwrap_or_else(|| caller.subject());
+    if subject != caller.subject() {
+        caller.require(Permission::ManageRoles, None).await?;
+    }
+    let roles = caller.access_control().assigned_roles(&subject).await?;
+
+    Ok(success_response(UserRolesJsonRESPONSE { subject, roles }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler_test_app::{app_sharing_roles_as, ops_admin};
+    use crate::jwt_bearer_authentication::JwtClaims;
+    use crate::user_profile_access_policy::AccessControl;
+    use crate::user_role_repository::InMemoryRoleRepository;
+    use actix_web::{http::StatusCode, test};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    macro_rules! in_memory_role_app {
+        ($access_control:expr, $claims:expr) => {
+            test::init_service(app_sharing_roles_as($access_control.clone(), $claims).service(web::scope("/api_v1").configure(user_role_routes))).await
+        };
+    }
+
+    #[actix_rt::test]
+    async fn assigned_roles_take_effect_at_once_and_need_roles_manage() {
+        let access_control = web::Data::new(AccessControl::new(Arc::new(InMemoryRoleRepository::default()), Duration::from_secs(60)));
+        let admin_app = in_memory_role_app!(access_control, ops_admin());
+        let user_app = in_memory_role_app!(access_control, JwtClaims::for_tests("agent@demo.com", &[]));
+        let user_id = Uuid::new_v4();
+
+        let check = json!({"permission": "profile:update", "user_id": user_id});
+        let request = test::TestRequest::post().uri("/api_v1/check_permission").set_json(&check).to_request();
+        let body: Value = test::read_response_json(&user_app, request).await;
+        assert_eq!(body["data"]["granted"], json!(false));
+
+        let assignment = json!({"subject": "agent@demo.com", "role": "support"});
+        let request = test::TestRequest::post().uri("/api_v1/assign_user_role").set_json(&assignment).to_request();
+        assert_eq!(test::call_service(&user_app, request).await.status(), StatusCode::FORBIDDEN);
+        let request = test::TestRequest::post().uri("/api_v1/assign_user_role").set_json(&assignment).to_request();
+        assert_eq!(test::call_service(&admin_app, request).await.status(), StatusCode::OK);
+
+        // the cached empty assignment was dropped by the assign
+        let request = test::TestRequest::post().uri("/api_v1/check_permission").set_json(&check).to_request();
+        let body: Value = test::read_response_json(&user_app, request).await;
+        assert_eq!(body["data"]["granted_by"], json!("role:support"));
+
+        let request = test::TestRequest::post().uri("/api_v1/list_user_roles").set_json(&json!({})).to_request();
+        let body: Value = test::read_response_json(&user_app, request).await;
+        assert_eq!(body["data"]["roles"], json!(["support"]));
+
+        let request = test::TestRequest::post()
+            .uri("/api_v1/check_permission")
+            .set_json(&json!({"subject": "agent@demo.com", "permission": "profile:delete", "user_id": user_id}))
+            .to_request();
+        let body: Value = test::read_response_json(&admin_app, request).await;
+        assert_eq!(body["data"]["granted"], json!(false));
+
+        let request = test::TestRequest::post().uri("/api_v1/revoke_user_role").set_json(&assignment).to_request();
+        assert_eq!(test::call_service(&admin_app, request).await.status(), StatusCode::OK);
+        let request = test::TestRequest::post().uri("/api_v1/check_permission").set_json(&check).to_request();
+        let body: Value = test::read_response_json(&user_app, request).await;
+        assert_eq!(body["data"]["granted"], json!(false));
+
+        let unknown = json!({"subject": "agent@demo.com", "role": "superuser"});
+        let request = test::TestRequest::post().uri("/api_v1/assign_user_role").set_json(&unknown).to_request();
+        assert_eq!(test::call_service(&admin_app, request).await.status(), StatusCode::BAD_REQUEST);
+    }
+}
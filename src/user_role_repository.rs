@@ -0,0 +1,104 @@
+use crate::application_error::AppResult;
+use crate::user_profile_access_policy::DEFAULT_ROLES;
+use crate::ycql_db_schema_and_queries::{delete_user_role_query, insert_user_role_query, select_roles_query, select_user_roles_query, RoleRowStruct};
+use crate::yugabyte_db_ycql_config::CurrentYcqlDbSession;
+use async_trait::async_trait;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+
+/// Storage of the ***roles*** definitions and the ***user_roles*** assignments, implemented for YCQL and for in-memory tests
+#[async_trait]
+pub trait RoleRepository: Send + Sync + 'static {
+    /// Every role with the permissions it grants
+    async fn read_roles(&self) -> AppResult<Vec<RoleRowStruct>>;
+    /// Role names assigned to a token subject
+    async fn read_user_roles(&self, subject: &str) -> AppResult<Vec<String>>;
+    async fn assign_user_role(&self, subject: &str, role: &str) -> AppResult<()>;
+    async fn revoke_user_role(&self, subject: &str, role: &str) -> AppResult<()>;
+}
+
+/// RoleRepository backed by the YCQL query functions
+#[derive(Clone)]
+pub struct YcqlRoleRepository {
+    ycql_session: CurrentYcqlDbSession,
+}
+
+impl YcqlRoleRepository {
+    pub fn new(ycql_session: CurrentYcqlDbSession) -> YcqlRoleRepository {
+        YcqlRoleRepository { ycql_session }
+    }
+}
+
+#[async_trait]
+impl RoleRepository for YcqlRoleRepository {
+    async fn read_roles(&self) -> AppResult<Vec<RoleRowStruct>> {
+        Ok(select_roles_query(&self.ycql_session).await?)
+    }
+
+    async fn read_user_roles(&self, subject: &str) -> AppResult<Vec<String>> {
+        Ok(select_user_roles_query(&self.ycql_session, subject).await?)
+    }
+
+    async fn assign_user_role(&self, subject: &str, role: &str) -> AppResult<()> {
+        Ok(insert_user_role_query(&self.ycql_session, subject, role).await?)
+    }
+
+    async fn revoke_user_role(&self, subject: &str, role: &str) -> AppResult<()> {
+        Ok(delete_user_role_query(&self.ycql_session, subject, role).await?)
+    }
+}
+
+/// RoleRepository kept in process memory, seeded with the same roles as migration 0007
+pub struct InMemoryRoleRepository {
+    roles: Mutex<BTreeMap<String, Vec<String>>>,
+    user_roles: Mutex<BTreeSet<(String, String)>>,
+}
+
+impl Default for InMemoryRoleRepository {
+    fn default() -> Self {
+        let roles = DEFAULT_ROLES
+            .iter()
+            .map(|(role, permissions)| (role.to_string(), permissions.iter().map(ToString::to_string).collect()))
+            .collect();
+        InMemoryRoleRepository {
+            roles: Mutex::new(roles),
+            user_roles: Mutex::new(BTreeSet::new()),
+        }
+    }
+}
+
+impl InMemoryRoleRepository {
+    fn user_roles(&self) -> std::sync::MutexGuard<'_, BTreeSet<(String, String)>> {
+        self.user_roles.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl RoleRepository for InMemoryRoleRepository {
+    async fn read_roles(&self) -> AppResult<Vec<RoleRowStruct>> {
+        let roles = self.roles.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Ok(roles
+            .iter()
+            .map(|(role, permissions)| RoleRowStruct { role: role.clone(), permissions: permissions.clone() })
+            .collect())
+    }
+
+    async fn read_user_roles(&self, subject: &str) -> AppResult<Vec<String>> {
+        Ok(self
+            .user_roles()
+            .iter()
+            .filter(|(assigned_to, _)| assigned_to == subject)
+            .map(|(_, role)| role.clone())
+            .collect())
+    }
+
+    async fn assign_user_role(&self, subject: &str, role: &str) -> AppResult<()> {
+        self.user_roles().insert((subject.to_string(), role.to_string()));
+        Ok(())
+    }
+
+    async fn revoke_user_role(&self, subject: &str, role: &str) -> AppResult<()> {
+        self.user_roles().remove(&(subject.to_string(), role.to_string()));
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use crate::application_error::AppError;
+use crate::default_strings::{CustomStatusMessage, UserErrorMessages};
+use crate::generic_json_error_response::{FieldErrorResponse, ValidationErrorResponse};
+use actix_web::dev::Payload;
+use actix_web::{web, Error, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+use std::ops::Deref;
+use validator::{Validate, ValidationErrors, ValidationErrorsKind};
+
+/// Drop in replacement for ***web::Json<T>*** that also runs ***T::validate()***.
+/// Payload errors still go through the ***JsonConfig*** error handler, rule violations become ***AppError::Validation***.
+#[derive(Debug)]
+pub struct ValidatedJson<T>(pub T);
+
+impl<T> ValidatedJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ValidatedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> FromRequest for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate + 'static,
+{
+    type Config = web::JsonConfig;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+        Box::pin(async move {
+            let json = json.await?;
+            json.validate().map_err(AppError::from)?;
+            Ok(ValidatedJson(json.into_inner()))
+        })
+    }
+}
+
+/// One entry per failing rule, nested structs (eg ***address***) are reported as ***address.city***
+fn flatten_field_errors(prefix: &str, errors: &ValidationErrors, field_errors: &mut Vec<FieldErrorResponse>) {
+    for (field, kind) in errors.errors() {
+        let field = if prefix.is_empty() { field.to_string() } else { format!("{}.{}", prefix, field) };
+        match kind {
+            ValidationErrorsKind::Field(errors) => field_errors.extend(errors.iter().map(|error| FieldErrorResponse {
+                field: field.to_string(),
+                reason: error
+                    .message
+                    .as_ref()
+                    .map(|message| message.to_string())
+                    .unwrap_or_else(|| error.code.to_string()),
+            })),
+            ValidationErrorsKind::Struct(errors) => flatten_field_errors(&field, errors, field_errors),
+            ValidationErrorsKind::List(errors) => {
+                for (index, errors) in errors {
+                    flatten_field_errors(&format!("{}[{}]", field, index), errors, field_errors);
+                }
+            }
+        }
+    }
+}
+
+/// Flatten validator errors into one entry per failing rule, sorted by field so responses are stable
+pub fn validation_error_response(errors: ValidationErrors) -> ValidationErrorResponse {
+    let mut field_errors = Vec::new();
+    flatten_field_errors("", &errors, &mut field_errors);
+    field_errors.sort_by(|a, b| a.field.cmp(&b.field));
+
+    let reason = field_errors
+        .iter()
+        .map(|field_error| format!("{} {}", field_error.field, field_error.reason))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    ValidationErrorResponse {
+        custom_status: CustomStatusMessage::USER_REQUEST_FAILED.to_string(),
+        message: UserErrorMessages::InputError { reason }.to_string(),
+        status: 400,
+        field_errors,
+    }
+}
@@ -0,0 +1,220 @@
+use cdrs_tokio::authenticators::{Authenticator, NoneAuthenticator, StaticPasswordAuthenticator};
+use cdrs_tokio::types::CBytes;
+use crate::ycql_service_config::{ConfigError, YcqlAuthConfig};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+pub type SharedYcqlAuthenticator = Arc<dyn Authenticator + Send + Sync>;
+
+/// Hook for SASL mechanisms the driver does not ship with.
+/// The driver sends ***initial_response*** once in reply to the server AUTHENTICATE frame.
+pub trait YcqlSaslAuthenticator: Send + Sync {
+    /// Server side authenticator class, eg ***org.apache.cassandra.auth.PasswordAuthenticator***
+    fn authenticator_class(&self) -> &str;
+    /// SASL initial response token
+    fn initial_response(&self) -> Vec<u8>;
+}
+
+/// Builds a custom authenticator from the ***options*** table of its configuration entry
+pub trait YcqlSaslAuthenticatorProvider: Send + Sync {
+    fn build(&self, options: &toml::value::Table) -> Result<Box<dyn YcqlSaslAuthenticator>, String>;
+}
+
+/// Adapts a YcqlSaslAuthenticator to the driver Authenticator trait
+struct SaslAuthenticatorAdapter(Box<dyn YcqlSaslAuthenticator>);
+
+impl Authenticator for SaslAuthenticatorAdapter {
+    fn get_auth_token(&self) -> CBytes {
+        CBytes::new(self.0.initial_response())
+    }
+
+    fn get_cassandra_name(&self) -> Option<&str> {
+        Some(self.0.authenticator_class())
+    }
+}
+
+/// Custom authenticator providers, looked up by the ***provider*** name used in configuration
+#[derive(Default)]
+pub struct YcqlAuthenticatorRegistry {
+    providers: HashMap<String, Box<dyn YcqlSaslAuthenticatorProvider>>,
+}
+
+impl YcqlAuthenticatorRegistry {
+    pub fn register<P>(&mut self, name: &str, provider: P) -> &mut Self
+    where
+        P: YcqlSaslAuthenticatorProvider + 'static,
+    {
+        self.providers.insert(name.to_string(), Box::new(provider));
+        self
+    }
+
+    /// Resolve the configured auth mode into the authenticator every node pool will use
+    pub fn build_authenticator(&self, auth_config: &YcqlAuthConfig) -> Result<SharedYcqlAuthenticator, ConfigError> {
+        match auth_config {
+            YcqlAuthConfig::None => Ok(Arc::new(NoneAuthenticator {})),
+            YcqlAuthConfig::Password { username, password } => {
+                Ok(Arc::new(StaticPasswordAuthenticator::new(username.as_str(), password.as_str())))
+            }
+            YcqlAuthConfig::SecretsFile { path } => {
+                let secrets = read_secrets_file(path)?;
+                Ok(Arc::new(StaticPasswordAuthenticator::new(secrets.username, secrets.password)))
+            }
+            YcqlAuthConfig::Custom { provider, options } => {
+                let sasl_provider = self.providers.get(provider).ok_or_else(|| {
+                    ConfigError::Invalid(vec![format!(
+                        "yugabyte.auth.provider `{}` is not registered, known providers: [{}]",
+                        provider,
+                        self.providers.keys().cloned().collect::<Vec<_>>().join(", ")
+                    )])
+                })?;
+                let authenticator = sasl_provider.build(options).map_err(|reason| {
+                    ConfigError::Invalid(vec![format!("yugabyte.auth provider `{}` rejected its options: {}", provider, reason)])
+                })?;
+                Ok(Arc::new(SaslAuthenticatorAdapter(authenticator)))
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct YcqlSecretsFile {
+    username: String,
+    password: String,
+}
+
+fn read_secrets_file(path: &Path) -> Result<YcqlSecretsFile, ConfigError> {
+    let raw = fs::read_to_string(path).map_err(|e| ConfigError::Unreadable {
+        path: path.display().to_string(),
+        reason: e.to_string(),
+    })?;
+    // never echo the parser message, it may quote the secret
+    let secrets: YcqlSecretsFile = toml::from_str(&raw).map_err(|_| ConfigError::Malformed {
+        path: path.display().to_string(),
+        reason: "expected string keys `username` and `password`".to_string(),
+    })?;
+    if secrets.username.is_empty() || secrets.password.is_empty() {
+        return Err(ConfigError::Invalid(vec![format!(
+            "secrets file {} must hold a non empty username and password",
+            path.display()
+        )]));
+    }
+    Ok(secrets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ycql_service_config::ServiceConfig;
+    use std::path::PathBuf;
+
+    fn auth_token(authenticator: &SharedYcqlAuthenticator) -> Vec<u8> {
+        authenticator.get_auth_token().into_plain().unwrap()
+    }
+
+    fn tempfile_with(contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-ycql_secrets.toml", uuid::Uuid::new_v4()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn auth_mode_after(toml: &str, variables: &[(&str, &str)]) -> Result<YcqlAuthConfig, ConfigError> {
+        let mut config: ServiceConfig = toml::from_str(toml).unwrap();
+        config.apply_overrides(|variable| variables.iter().find(|(name, _)| *name == variable).map(|(_, value)| value.to_string()))?;
+        Ok(config.yugabyte.auth)
+    }
+
+    #[test]
+    fn auth_mode_comes_from_the_file_unless_ycql_auth_mode_overrides_it() {
+        let registry = YcqlAuthenticatorRegistry::default();
+        let password_toml = "[yugabyte.auth]\nmode = \"password\"\nusername = \"cassandra\"\npassword = \"s3cret\"\n";
+
+        let none = registry.build_authenticator(&auth_mode_after("", &[]).unwrap()).unwrap();
+        assert_eq!(none.get_cassandra_name(), None);
+
+        let password = registry.build_authenticator(&auth_mode_after(password_toml, &[]).unwrap()).unwrap();
+        assert_eq!(password.get_cassandra_name(), Some("org.apache.cassandra.auth.PasswordAuthenticator"));
+        assert_eq!(auth_token(&password), b"\0cassandra\0s3cret".to_vec());
+
+        assert!(matches!(auth_mode_after(password_toml, &[("YCQL_AUTH_MODE", "none")]), Ok(YcqlAuthConfig::None)));
+        assert!(matches!(
+            auth_mode_after("", &[("YCQL_USERNAME", "cassandra"), ("YCQL_PASSWORD", "s3cret")]),
+            Ok(YcqlAuthConfig::Password { username, password }) if username == "cassandra" && password == "s3cret"
+        ));
+        assert!(matches!(
+            auth_mode_after("", &[("YCQL_AUTH_MODE", "secrets_file"), ("YCQL_SECRETS_FILE", "/run/secrets/ycql.toml")]),
+            Ok(YcqlAuthConfig::SecretsFile { path }) if path == Path::new("/run/secrets/ycql.toml")
+        ));
+        for mode in &["custom", "kerberos"] {
+            assert!(matches!(
+                auth_mode_after("", &[("YCQL_AUTH_MODE", mode)]),
+                Err(ConfigError::InvalidEnvironment { variable, .. }) if variable == "YCQL_AUTH_MODE"
+            ));
+        }
+    }
+
+    #[test]
+    fn secrets_file_needs_both_keys() {
+        let secrets_path = tempfile_with("username = \"cassandra\"\npassword = \"s3cret\"\n");
+        let secrets = read_secrets_file(&secrets_path).unwrap();
+        assert_eq!((secrets.username.as_str(), secrets.password.as_str()), ("cassandra", "s3cret"));
+        fs::remove_file(&secrets_path).ok();
+
+        assert!(matches!(read_secrets_file(&secrets_path), Err(ConfigError::Unreadable { .. })));
+
+        let no_password_path = tempfile_with("username = \"cassandra\"\n");
+        match read_secrets_file(&no_password_path) {
+            Err(ConfigError::Malformed { reason, .. }) => assert_eq!(reason, "expected string keys `username` and `password`"),
+            other => panic!("expected ConfigError::Malformed, got {:?}", other.map(|_| ())),
+        }
+        fs::remove_file(&no_password_path).ok();
+
+        let empty_password_path = tempfile_with("username = \"cassandra\"\npassword = \"\"\n");
+        assert!(matches!(read_secrets_file(&empty_password_path), Err(ConfigError::Invalid(_))));
+        fs::remove_file(&empty_password_path).ok();
+    }
+
+    struct TokenAuthenticator(String);
+
+    impl YcqlSaslAuthenticator for TokenAuthenticator {
+        fn authenticator_class(&self) -> &str {
+            "com.example.TokenAuthenticator"
+        }
+
+        fn initial_response(&self) -> Vec<u8> {
+            self.0.as_bytes().to_vec()
+        }
+    }
+
+    struct TokenAuthenticatorProvider;
+
+    impl YcqlSaslAuthenticatorProvider for TokenAuthenticatorProvider {
+        fn build(&self, options: &toml::value::Table) -> Result<Box<dyn YcqlSaslAuthenticator>, String> {
+            let token = options.get("token").and_then(|token| token.as_str()).ok_or("missing string option `token`")?;
+            Ok(Box::new(TokenAuthenticator(token.to_string())))
+        }
+    }
+
+    #[test]
+    fn custom_modes_use_the_registered_provider() {
+        let mut registry = YcqlAuthenticatorRegistry::default();
+        registry.register("token", TokenAuthenticatorProvider);
+        let custom = |provider: &str, options: &str| YcqlAuthConfig::Custom { provider: provider.to_string(), options: toml::from_str(options).unwrap() };
+
+        let authenticator = registry.build_authenticator(&custom("token", "token = \"abc\"")).unwrap();
+        assert_eq!(authenticator.get_cassandra_name(), Some("com.example.TokenAuthenticator"));
+        assert_eq!(auth_token(&authenticator), b"abc".to_vec());
+
+        match registry.build_authenticator(&custom("token", "")) {
+            Err(ConfigError::Invalid(problems)) => assert!(problems[0].contains("missing string option `token`"), "{:?}", problems),
+            _ => panic!("a provider rejecting its options must fail the startup"),
+        }
+        match registry.build_authenticator(&custom("kerberos", "")) {
+            Err(ConfigError::Invalid(problems)) => assert!(problems[0].contains("known providers: [token]"), "{:?}", problems),
+            _ => panic!("an unregistered provider must fail the startup"),
+        }
+    }
+}
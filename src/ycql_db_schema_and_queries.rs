@@ -1,20 +1,147 @@
 use crate::yugabyte_db_ycql_config::CurrentYcqlDbSession;
+use crate::ycql_prepared_statements::{BatchStatement, UserProfileStatement};
 use cdrs_tokio::query::*;
 use cdrs_tokio::query_values;
 use cdrs_tokio::types::prelude::*;
 use cdrs_tokio::frame::AsBytes;
+use cdrs_tokio::frame::frame_result::RowsMetadataFlag;
+use cdrs_tokio::types::CBytes;
 use cdrs_tokio_helpers_derive::*;
 use cdrs_tokio::types::from_cdrs::FromCDRSByName;
+use cdrs_tokio::types::IntoRustByName;
+use cdrs_tokio::frame::TryFromRow;
+use cdrs_tokio::frame::frame_batch::BatchType;
+use cdrs_tokio::types::rows::Row;
+use cdrs_tokio::types::list::List;
+use cdrs_tokio::types::map::Map;
+use cdrs_tokio::types::udt::UDT;
+use cdrs_tokio::types::AsRustType;
+use std::collections::HashMap;
+use std::fmt;
+use validator::Validate;
 use uuid::Uuid;
 use cdrs_tokio::Result as YugabyteDBResult;
 use serde_json::Value as JsonValue;
 use serde::{Deserialize, Serialize};
+use derive_more::Display;
 // use chrono::DateTime::timestamp;
 use uuid::v1::{Timestamp, Context};
 // use std::u32;
 
+/// Longest ***USING TTL*** or ***default_time_to_live*** accepted, 20 years like Cassandra
+pub const MAX_TTL_SECS: i32 = 630_720_000;
+
+/// Column list every profile SELECT uses, ***ttl_secs*** is the time left before the row expires
+pub const USER_PROFILE_COLUMNS: &str =
+  "user_id, time_uuid_order, email_address, full_name, age, comment, version, address, tags, phone_numbers, attributes, preferences, \
+   TTL(email_address) AS ttl_secs";
+
+/// ***FROZEN<address>*** UDT from migration 0004, fields must stay in the CREATE TYPE order because
+/// ***IntoCDRSValue*** writes them positionally
+#[derive(Clone, Debug, Serialize, Deserialize, Validate, IntoCDRSValue, TryFromUDT, PartialEq)]
+pub struct Address {
+  #[validate(length(min = 1, max = 200, message = "must be between 1 and 200 characters"))]
+  pub street: String,
+  #[validate(length(min = 1, max = 100, message = "must be between 1 and 100 characters"))]
+  pub city: String,
+  #[validate(length(max = 20, message = "must be at most 20 characters"))]
+  pub postal_code: Option<String>,
+  #[validate(length(min = 2, max = 2, message = "must be a 2 letter ISO 3166 country code"))]
+  pub country: String,
+}
+
+/// Largest preferences document accepted, in bytes of serialized JSON
+pub const MAX_PREFERENCES_BYTES: usize = 16 * 1024;
+
+/// ***JSONB*** preferences document. YCQL sends JSONB as JSON text, so it is written and read as a String
+/// and converted with serde, the API always sees the parsed value
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub struct Preferences(pub JsonValue);
+
+impl From<Preferences> for Bytes {
+  fn from(preferences: Preferences) -> Bytes {
+    Bytes::new(preferences.0.to_string().into_bytes())
+  }
+}
+
+impl Preferences {
+  fn at(&self, path: &PreferencePath) -> Option<&JsonValue> {
+    path.segments.iter().try_fold(&self.0, |value, segment| value.get(segment))
+  }
+
+  /// What ***->>*** returns for ***path***: strings without quotes, other values as JSON text, None when missing or null
+  pub fn text_at(&self, path: &PreferencePath) -> Option<String> {
+    match self.at(path)? {
+      JsonValue::Null => None,
+      JsonValue::String(text) => Some(text.to_string()),
+      value => Some(value.to_string()),
+    }
+  }
+
+  /// True when the object holding the last segment exists, YCQL only sets a path below an existing object
+  pub fn has_parent_of(&self, path: &PreferencePath) -> bool {
+    path.segments[..path.segments.len() - 1]
+      .iter()
+      .try_fold(&self.0, |value, segment| value.get(segment))
+      .is_some_and(JsonValue::is_object)
+  }
+
+  /// Sets ***path*** in place, callers check ***has_parent_of*** first
+  pub fn set_at(&mut self, path: &PreferencePath, value: JsonValue) {
+    let (last, parents) = path.segments.split_last().expect("a preference path has at least one segment");
+    let parent = parents.iter().try_fold(&mut self.0, |value, segment| value.get_mut(segment));
+    if let Some(JsonValue::Object(parent)) = parent {
+      parent.insert(last.to_string(), value);
+    }
+  }
+}
+
+/// Dotted path into the preferences document, eg ***theme.color***.
+/// Segments end up quoted inside the CQL text (YCQL has no bind markers for JSON paths),
+/// so they are limited to letters, digits, ***_*** and ***-***
+#[derive(Clone, Debug, PartialEq)]
+pub struct PreferencePath {
+  segments: Vec<String>,
+}
+
+impl PreferencePath {
+  pub const MAX_DEPTH: usize = 8;
+
+  pub fn parse(path: &str) -> std::result::Result<PreferencePath, &'static str> {
+    let segments: Vec<String> = path.split('.').map(str::to_string).collect();
+    if segments.len() > PreferencePath::MAX_DEPTH {
+      return Err("must have at most 8 segments");
+    }
+    let is_segment = |segment: &String| {
+      (1..=64).contains(&segment.len()) && segment.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    };
+    if !segments.iter().all(is_segment) {
+      return Err("must be dot separated keys of letters, digits, _ or -");
+    }
+    Ok(PreferencePath { segments })
+  }
+
+  /// ***preferences->'theme'->>'color'***, the last step is ***->>*** so the value comes back as text
+  pub fn selector(&self) -> String {
+    let (last, parents) = self.segments.split_last().expect("a preference path has at least one segment");
+    let mut selector = String::from("preferences");
+    for segment in parents {
+      selector.push_str(&format!("->'{}'", segment));
+    }
+    selector.push_str(&format!("->>'{}'", last));
+    selector
+  }
+}
+
+impl fmt::Display for PreferencePath {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.segments.join("."))
+  }
+}
+
 ///YCQL User Profile Database Schema
-#[derive(Clone, Serialize, Debug, IntoCDRSValue, TryFromRow, PartialEq)]
+#[derive(Clone, Serialize, Debug, IntoCDRSValue, PartialEq)]
 pub struct UserProfileRowStruct {
   pub user_id: Uuid,
   pub time_uuid_order: Uuid,
@@ -24,19 +151,100 @@ pub struct UserProfileRowStruct {
   pub age: i8,
 
   pub comment: Option<String>,
+
+  /// Replaced on every write and sent as the ETag, null on rows written before migration 0003
+  pub version: Option<Uuid>,
+
+  pub address: Option<Address>,
+  /// ***SET<TEXT>***, YCQL keeps it sorted and stores an empty set as null
+  pub tags: Vec<String>,
+  /// ***LIST<TEXT>***, in insertion order
+  pub phone_numbers: Vec<String>,
+  /// ***MAP<TEXT, TEXT>***
+  pub attributes: HashMap<String, String>,
+
+  /// ***JSONB***, null until preferences are first stored
+  pub preferences: Option<Preferences>,
+
+  /// Seconds before the row expires, None when it never does.
+  /// Not a column: reads fill it from ***TTL(email_address)***, creates write it as ***USING TTL***
+  pub ttl_secs: Option<i32>,
+}
+
+/// A null or empty collection column as an empty Vec, the same for SET and LIST
+fn text_collection(row: &Row, column: &str) -> YugabyteDBResult<Vec<String>> {
+  let list: Option<List> = row.get_by_name(column)?;
+  Ok(match list {
+    Some(list) => list.as_rust_type()?.unwrap_or_default(),
+    None => Vec::new(),
+  })
+}
+
+fn udt_column(row: &Row, column: &str) -> YugabyteDBResult<Option<UDT>> {
+  row.get_by_name(column)
+}
+
+fn preferences_column(row: &Row) -> YugabyteDBResult<Option<Preferences>> {
+  let preferences: Option<String> = row.get_by_name("preferences")?;
+  preferences
+    .map(|json| serde_json::from_str(&json).map(Preferences))
+    .transpose()
+    .map_err(|error| cdrs_tokio::error::Error::General(format!("preferences is not valid JSON: {}", error)))
+}
+
+fn text_map(row: &Row, column: &str) -> YugabyteDBResult<HashMap<String, String>> {
+  let map: Option<Map> = row.get_by_name(column)?;
+  Ok(match map {
+    Some(map) => map.as_rust_type()?.unwrap_or_default(),
+    None => HashMap::new(),
+  })
+}
+
+/// Hand written so rows without the ***ttl_secs*** column (conditional write results) still parse
+impl TryFromRow for UserProfileRowStruct {
+  fn try_from_row(row: Row) -> YugabyteDBResult<UserProfileRowStruct> {
+    Ok(UserProfileRowStruct {
+      user_id: row.get_r_by_name("user_id")?,
+      time_uuid_order: row.get_r_by_name("time_uuid_order")?,
+      email_address: row.get_r_by_name("email_address")?,
+      full_name: row.get_r_by_name("full_name")?,
+      age: row.get_r_by_name("age")?,
+      comment: row.get_by_name("comment")?,
+      version: row.get_by_name("version")?,
+      address: udt_column(&row, "address")?.map(Address::try_from_udt).transpose()?,
+      tags: text_collection(&row, "tags")?,
+      phone_numbers: text_collection(&row, "phone_numbers")?,
+      attributes: text_map(&row, "attributes")?,
+      preferences: preferences_column(&row)?,
+      ttl_secs: row.get_by_name("ttl_secs").unwrap_or(None),
+    })
+  }
 }
 
 impl UserProfileRowStruct {
-  pub  fn into_query_values(self) -> QueryValues {
-        query_values!(
-            "user_id" => self.user_id, 
-            "time_uuid_order" => self.time_uuid_order, 
-
-            "email_address" => self.email_address, 
-            "full_name" => self.full_name,
-            "age" => self.age,
-            "comment"=> self.comment
-        )
+  /// TTL to write updated cells with so they expire together with the row, 0 when the row never expires
+  pub fn cells_ttl(&self) -> i32 {
+    // an expiring row must never be rewritten with 0, that would keep the new cells forever
+    self.ttl_secs.map_or(0, |ttl_secs| ttl_secs.max(1))
+  }
+
+  /// Values in the column order of the CreateUserProfile statements, without the TTL
+  pub  fn into_query_values(self) -> Vec<Value> {
+        vec![
+            Value::from(self.user_id),
+            Value::from(self.time_uuid_order),
+
+            Value::from(self.email_address),
+            Value::from(self.full_name),
+            Value::from(self.age),
+            Value::from(self.comment),
+            Value::from(self.version),
+            Value::from(self.address),
+            Value::from(self.tags),
+            Value::from(self.phone_numbers),
+            Value::from(self.attributes),
+            Value::from(self.preferences),
+        ]
   }
  pub fn build_user_profile(
   user_id: Uuid,
@@ -54,47 +262,262 @@ impl UserProfileRowStruct {
             email_address,
             full_name,
             age,
-            comment
+            comment,
+            version: Some(Uuid::new_v4()),
+            address: None,
+            tags: Vec::new(),
+            phone_numbers: Vec::new(),
+            attributes: HashMap::new(),
+            preferences: None,
+            ttl_secs: None
         }
 
     }
 
 }
 
-///Insert or Create a new user function
+/// YugabyteDB error text when a write would break the ***user_profile_by_email*** unique index
+pub const UNIQUE_EMAIL_VIOLATION: &str = "Duplicate value disallowed by unique index";
+
+/// True when the insert failed because another profile already owns the email address
+pub fn is_unique_email_violation(error: &cdrs_tokio::error::Error) -> bool {
+    error.to_string().contains(UNIQUE_EMAIL_VIOLATION)
+}
+
+/// YugabyteDB error text when an ***IF ... ELSE ERROR*** condition fails, eg ***Condition on table user_profile was not satisfied.***
+/// Inside a logged batch it aborts every statement
+pub const CONDITION_NOT_SATISFIED: &str = "was not satisfied";
+
+/// ***ELSE ERROR*** reports a failed condition only through the server's message, there is no ***[applied]*** row to read.
+/// Only an error frame from the server counts, a driver or parsing error quoting the same words stays an error.
+/// ***conditions_report_not_applied_on_a_live_cluster*** in crud_controller.rs pins this against a real server
+pub fn is_condition_not_satisfied(error: &cdrs_tokio::error::Error) -> bool {
+    match error {
+        cdrs_tokio::error::Error::Server(server_error) => server_error.message.as_str().contains(CONDITION_NOT_SATISFIED),
+        _ => false,
+    }
+}
+
+/// Result of a conditional write (***IF NOT EXISTS***, ***IF EXISTS***, ***IF column = ?***)
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConditionalWriteOutcome {
+    Applied,
+    /// ***current*** is the row the condition was checked against, None when the row does not exist.
+    /// A write run in a batch returns no row, the YCQL repository reads it afterwards on a best-effort basis
+    /// and a concurrent write may already have changed it
+    NotApplied { current: Option<Box<UserProfileRowStruct>> },
+}
+
+/// What a profile write did, stored in ***user_profile_audit.action***
+#[derive(Clone, Copy, Debug, Display, PartialEq)]
+pub enum UserProfileAuditAction {
+    #[display(fmt = "created")]
+    Created,
+    #[display(fmt = "updated")]
+    Updated,
+    #[display(fmt = "deleted")]
+    Deleted,
+}
+
+/// ***user_profile_audit*** row, written in the same logged batch as the profile write it records
+#[derive(Clone, Serialize, Debug, TryFromRow, PartialEq)]
+pub struct UserProfileAuditRowStruct {
+  pub user_id: Uuid,
+  pub time_uuid_order: Uuid,
+  /// TIMEUUID from ***now()***, orders the audit rows of a profile
+  pub audit_id: Uuid,
+  pub action: String,
+  /// Version the write stored, null for deletes
+  pub version: Option<Uuid>,
+}
+
+/// Values for ***InsertUserProfileAudit***, audit_id comes from ***now()*** on the server
+fn audit_values(user_id: Uuid, time_uuid_order: Uuid, action: UserProfileAuditAction, version: Option<Uuid>) -> Vec<Value> {
+    vec![Value::from(user_id), Value::from(time_uuid_order), Value::from(action.to_string()), Value::from(version)]
+}
+
+///Insert or Create a new user function, never overwrites an existing primary key.
+/// Without ***ttl_secs*** the table ***default_time_to_live*** applies, the profile and its audit row are written in one logged batch
 pub async fn create_new_user_profile_query(
-  ycql_session: &mut CurrentYcqlDbSession, 
+  ycql_session: &CurrentYcqlDbSession, 
   user_profile_row: UserProfileRowStruct
-) ->YugabyteDBResult<()>{
-    static CREATE_USER_PROFILE: &'static str = r#"
-    INSERT INTO demo_ycql_service_keyspace.user_profile (
-            user_id,
-            time_uuid_order,
-            email_address,
-            full_name,
-            age,
-            comment      
-    )
-      VALUES (?, ?, ?, ?, ?, ?);
-    "#;
-    ycql_session.query_with_values(CREATE_USER_PROFILE, user_profile_row.into_query_values()).await?;
-    Ok(())  
+) ->YugabyteDBResult<ConditionalWriteOutcome>{
+    user_profile_batch_query(ycql_session, UserProfileBatchType::Logged, vec![UserProfileWrite::Create(Box::new(user_profile_row))]).await
+}
+
+/// A create, update or delete from the statement registry, run alone or grouped with others in one batch
+#[derive(Clone, Debug, PartialEq)]
+pub enum UserProfileWrite {
+    Create(Box<UserProfileRowStruct>),
+    UpdateFullNameAndAge { user_id: Uuid, time_uuid_order: Uuid, age: i8, full_name: String, write: ExistingRowWrite },
+    Delete { user_id: Uuid, time_uuid_order: Uuid, condition: RowCondition },
+}
+
+impl UserProfileWrite {
+    /// Primary key of the row written, batches are grouped by its user_id partition
+    pub fn key(&self) -> (Uuid, Uuid) {
+        match self {
+            UserProfileWrite::Create(row) => (row.user_id, row.time_uuid_order),
+            UserProfileWrite::UpdateFullNameAndAge { user_id, time_uuid_order, .. } | UserProfileWrite::Delete { user_id, time_uuid_order, .. } => {
+                (*user_id, *time_uuid_order)
+            }
+        }
+    }
+
+    /// The write followed by its audit row, with the values of each statement
+    pub fn statements(self) -> Vec<(BatchStatement, Vec<Value>)> {
+        let (user_id, time_uuid_order) = self.key();
+        let (statement, values, audit) = match self {
+            UserProfileWrite::Create(row) => {
+                let ttl_secs = row.ttl_secs;
+                let audit = audit_values(user_id, time_uuid_order, UserProfileAuditAction::Created, row.version);
+                let mut values = row.into_query_values();
+                let statement = match ttl_secs {
+                    None => UserProfileStatement::CreateUserProfile,
+                    Some(ttl_secs) => {
+                        values.push(Value::from(ttl_secs));
+                        UserProfileStatement::CreateUserProfileWithTtl
+                    }
+                };
+                (statement, values, audit)
+            }
+            UserProfileWrite::UpdateFullNameAndAge { age, full_name, write, .. } => {
+                let statement = match write.condition {
+                    RowCondition::Exists => UserProfileStatement::UpdateFullNameAndAge,
+                    RowCondition::Unchanged(_) => UserProfileStatement::UpdateFullNameAndAgeIfUnchanged,
+                    RowCondition::VersionMatches(_) => UserProfileStatement::UpdateFullNameAndAgeIfVersion,
+                };
+                let mut values = vec![
+                    Value::from(write.ttl_secs),
+                    Value::from(age),
+                    Value::from(full_name),
+                    Value::from(write.new_version),
+                    Value::from(user_id),
+                    Value::from(time_uuid_order),
+                ];
+                values.extend(write.condition.if_clause().1);
+                (statement, values, audit_values(user_id, time_uuid_order, UserProfileAuditAction::Updated, Some(write.new_version)))
+            }
+            UserProfileWrite::Delete { condition, .. } => {
+                let statement = match condition {
+                    RowCondition::Exists => UserProfileStatement::DeleteUserProfile,
+                    RowCondition::Unchanged(_) => UserProfileStatement::DeleteUserProfileIfUnchanged,
+                    RowCondition::VersionMatches(_) => UserProfileStatement::DeleteUserProfileIfVersion,
+                };
+                let mut values = vec![Value::from(user_id), Value::from(time_uuid_order)];
+                values.extend(condition.if_clause().1);
+                (statement, values, audit_values(user_id, time_uuid_order, UserProfileAuditAction::Deleted, None))
+            }
+        };
+        vec![(BatchStatement::Prepared(statement), values), (BatchStatement::Prepared(UserProfileStatement::InsertUserProfileAudit), audit)]
+    }
+}
+
+/// How ***user_profile_batch_query*** sends a group of writes
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UserProfileBatchType {
+    /// Applied all or nothing through the batch log
+    #[default]
+    Logged,
+    /// Skips the batch log, only atomic because every group stays inside one partition
+    Unlogged,
+}
+
+/// Sends ***writes*** and their audit rows as one CQL batch of prepared statements.
+/// Callers group the writes by user_id so a batch never spans partitions.
+/// A failed ***ELSE ERROR*** condition rejects the whole batch as ***NotApplied*** without a row
+pub async fn user_profile_batch_query(
+  ycql_session: &CurrentYcqlDbSession,
+  batch_type: UserProfileBatchType,
+  writes: Vec<UserProfileWrite>
+) ->YugabyteDBResult<ConditionalWriteOutcome>{
+    let batch_type = match batch_type {
+      UserProfileBatchType::Logged => BatchType::Logged,
+      UserProfileBatchType::Unlogged => BatchType::Unlogged,
+    };
+    let statements = writes.into_iter().flat_map(UserProfileWrite::statements).collect();
+    conditional_batch(ycql_session, batch_type, statements).await
+}
+
+/// Runs ***statements*** as one batch, a failed ***ELSE ERROR*** condition is ***NotApplied*** without a row and nothing was written
+async fn conditional_batch(
+  ycql_session: &CurrentYcqlDbSession,
+  batch_type: BatchType,
+  statements: Vec<(BatchStatement, Vec<Value>)>
+) ->YugabyteDBResult<ConditionalWriteOutcome>{
+    let statements = statements
+      .into_iter()
+      .map(|(statement, values)| (statement, QueryValues::SimpleValues(values)))
+      .collect();
+    match ycql_session.execute_statement_batch(batch_type, statements).await {
+      Ok(_) => Ok(ConditionalWriteOutcome::Applied),
+      Err(error) if is_condition_not_satisfied(&error) => Ok(ConditionalWriteOutcome::NotApplied { current: None }),
+      Err(error) => Err(error),
+    }
+}
+
+/// Values an update expects to find, the write is rejected when the row changed in between
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpectedFullNameAndAge {
+    pub full_name: String,
+    pub age: i8,
+}
+
+/// Guard for writes to an existing row, every variant also rejects missing rows
+#[derive(Clone, Debug, PartialEq)]
+pub enum RowCondition {
+    /// ***IF EXISTS***
+    Exists,
+    /// ***IF full_name = ? AND age = ?***
+    Unchanged(ExpectedFullNameAndAge),
+    /// ***IF version = ?***, the If-Match header
+    VersionMatches(Uuid),
+}
+
+impl RowCondition {
+    /// IF clause with one bind marker per value, plus the values in the same order
+    pub fn if_clause(&self) -> (&'static str, Vec<Value>) {
+        match self {
+            RowCondition::Exists => ("IF EXISTS", Vec::new()),
+            RowCondition::Unchanged(expected) => (
+                "IF full_name = ? AND age = ?",
+                vec![Value::from(expected.full_name.to_string()), Value::from(expected.age)],
+            ),
+            RowCondition::VersionMatches(version) => ("IF version = ?", vec![Value::from(*version)]),
+        }
+    }
+
+    /// Same check as the IF clause against a row that exists, a null version never matches
+    pub fn matches(&self, row: &UserProfileRowStruct) -> bool {
+        match self {
+            RowCondition::Exists => true,
+            RowCondition::Unchanged(expected) => row.full_name == expected.full_name && row.age == expected.age,
+            RowCondition::VersionMatches(version) => row.version == Some(*version),
+        }
+    }
 }
 
+/// What every update of an existing row carries besides the columns it sets
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExistingRowWrite {
+    pub condition: RowCondition,
+    /// Stored in ***version***, the next ETag
+    pub new_version: Uuid,
+    /// ***USING TTL*** for the written cells, the row's remaining TTL (0 for none) so they expire together with the rest of the row.
+    /// Read before the write, see ***YcqlUserProfileRepository::remaining_ttl***
+    pub ttl_secs: i32,
+}
 
 pub async fn select_user_profile_query(
-  ycql_session: &mut CurrentYcqlDbSession,
+  ycql_session: &CurrentYcqlDbSession,
    user_id: Uuid,
    email_address: String
   ) ->YugabyteDBResult<Vec<UserProfileRowStruct>>{
 
-    static SELECT_USER_PROFILE: &'static str = r#"
-    SELECT * FROM demo_ycql_service_keyspace.user_profile
-      WHERE user_id = ? AND  email_address = ?;
-    "#;
    let values = query_values!(user_id, email_address);
 
-   let res = ycql_session.query_with_values(SELECT_USER_PROFILE, values).await?;
+   let res = ycql_session.execute_statement(UserProfileStatement::SelectUserProfile, values).await?;
   
    let body = res.get_body()?;
    
@@ -109,39 +532,630 @@ pub async fn select_user_profile_query(
    Ok(user_profile)
   }
 
+  /// Look a profile up by email alone, email addresses are unique so there is at most one row
+  pub async fn find_user_profile_by_email_query(
+    ycql_session: &CurrentYcqlDbSession,
+    email_address: String
+   ) ->YugabyteDBResult<Option<UserProfileRowStruct>>{
+
+    let values = query_values!(email_address);
+
+    let res = ycql_session.execute_statement(UserProfileStatement::FindUserProfileByEmail, values).await?;
+
+    let body = res.get_body()?;
+
+    let new_rows= body.into_rows().expect("Could not get Row Body");
+
+    match new_rows.into_iter().next() {
+     Some(row) => Ok(Some(UserProfileRowStruct::try_from_row(row)?)),
+     None => Ok(None),
+    }
+   }
+
+  /// Every profile row in the ***user_id*** partition, ordered by time_uuid_order
+  pub async fn select_user_profiles_by_user_id_query(
+    ycql_session: &CurrentYcqlDbSession,
+    user_id: Uuid
+   ) ->YugabyteDBResult<Vec<UserProfileRowStruct>>{
+
+    let values = query_values!(user_id);
+
+    let res = ycql_session.execute_statement(UserProfileStatement::SelectUserProfilesByUserId, values).await?;
+
+    let new_rows= res.get_body()?.into_rows().expect("Could not get Row Body");
+
+    let mut user_profiles: Vec<UserProfileRowStruct> = Vec::with_capacity(new_rows.len());
+
+    for row in new_rows {
+     user_profiles.push(UserProfileRowStruct::try_from_row(row)?);
+    }
+
+    Ok(user_profiles)
+   }
+
+  /// One row by its full primary key
+  pub async fn select_user_profile_by_key_query(
+    ycql_session: &CurrentYcqlDbSession,
+    user_id: Uuid,
+    time_uuid_order: Uuid
+   ) ->YugabyteDBResult<Option<UserProfileRowStruct>>{
+
+    let values = query_values!(user_id, time_uuid_order);
+
+    let res = ycql_session.execute_statement(UserProfileStatement::SelectUserProfileByKey, values).await?;
+
+    let new_rows= res.get_body()?.into_rows().expect("Could not get Row Body");
+
+    match new_rows.into_iter().next() {
+     Some(row) => Ok(Some(UserProfileRowStruct::try_from_row(row)?)),
+     None => Ok(None),
+    }
+   }
+
+  /// Only updates a row that passes ***write.condition***
   pub async fn update_full_name_and_age_query(
-     ycql_session: &mut CurrentYcqlDbSession,
+     ycql_session: &CurrentYcqlDbSession,
      user_id: Uuid,
      time_uuid_order: Uuid,
      age: i8,
-     full_name: String
-    ) ->YugabyteDBResult<()>{
-    
-    static UPDATE_USER_PROFILE: &'static str = r#"
-    UPDATE demo_ycql_service_keyspace.user_profile SET age = ? , full_name = ? WHERE user_id = ? AND time_uuid_order = ?;
-    "#;
+     full_name: String,
+     write: ExistingRowWrite
+    ) ->YugabyteDBResult<ConditionalWriteOutcome>{
 
-     let values = query_values!(age, full_name, user_id, time_uuid_order);
+     let update = UserProfileWrite::UpdateFullNameAndAge { user_id, time_uuid_order, age, full_name, write };
+     user_profile_batch_query(ycql_session, UserProfileBatchType::Logged, vec![update]).await
+    }
+
+    /// Columns a partial update may touch, None leaves the column as it is.
+    /// ***comment***, ***address*** and ***preferences*** are nullable so ***Some(None)*** stores null, full_name and age always hold a value.
+    /// The frozen address and the preferences document are replaced as a whole.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct UserProfilePatch {
+      pub full_name: Option<String>,
+      pub age: Option<i8>,
+      pub comment: Option<Option<String>>,
+      pub address: Option<Option<Address>>,
+      pub preferences: Option<Option<Preferences>>,
+    }
 
-     ycql_session.query_with_values(UPDATE_USER_PROFILE, values).await?;
+    impl UserProfilePatch {
+      pub fn is_empty(&self) -> bool {
+        self.full_name.is_none() && self.age.is_none() && self.comment.is_none() && self.address.is_none()
+          && self.preferences.is_none()
+      }
 
-     Ok(())
+      /// SET clause with one bind marker per supplied column, plus the values in the same order
+      pub fn set_clause(&self) -> (String, Vec<Value>) {
+        let mut assignments = Vec::new();
+        let mut values = Vec::new();
+        if let Some(full_name) = &self.full_name {
+          assignments.push("full_name = ?");
+          values.push(Value::from(full_name.to_string()));
+        }
+        if let Some(age) = self.age {
+          assignments.push("age = ?");
+          values.push(Value::from(age));
+        }
+        if let Some(comment) = &self.comment {
+          assignments.push("comment = ?");
+          values.push(Value::from(comment.clone()));
+        }
+        if let Some(address) = &self.address {
+          assignments.push("address = ?");
+          values.push(Value::from(address.clone()));
+        }
+        if let Some(preferences) = &self.preferences {
+          assignments.push("preferences = ?");
+          values.push(Value::from(preferences.clone()));
+        }
+        (assignments.join(", "), values)
+      }
     }
 
+    /// One element level change to a collection column, the rest of the collection and of the row stay as they are
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum CollectionChange {
+      /// ***tags = tags + ?***
+      AddTags(Vec<String>),
+      /// ***tags = tags - ?***
+      RemoveTags(Vec<String>),
+      /// ***phone_numbers = phone_numbers + ?***, appended in order
+      AppendPhoneNumbers(Vec<String>),
+      /// ***phone_numbers = phone_numbers - ?***, removes every occurrence
+      RemovePhoneNumbers(Vec<String>),
+      /// ***attributes = attributes + ?***, existing keys are overwritten
+      PutAttributes(HashMap<String, String>),
+      /// ***attributes = attributes - ?***, by key
+      RemoveAttributes(Vec<String>),
+    }
+
+    impl CollectionChange {
+      /// SET assignment with its single bind value
+      pub fn set_clause(&self) -> (&'static str, Value) {
+        match self {
+          CollectionChange::AddTags(tags) => ("tags = tags + ?", Value::from(tags.clone())),
+          CollectionChange::RemoveTags(tags) => ("tags = tags - ?", Value::from(tags.clone())),
+          CollectionChange::AppendPhoneNumbers(numbers) => ("phone_numbers = phone_numbers + ?", Value::from(numbers.clone())),
+          CollectionChange::RemovePhoneNumbers(numbers) => ("phone_numbers = phone_numbers - ?", Value::from(numbers.clone())),
+          CollectionChange::PutAttributes(attributes) => ("attributes = attributes + ?", Value::from(attributes.clone())),
+          CollectionChange::RemoveAttributes(keys) => ("attributes = attributes - ?", Value::from(keys.clone())),
+        }
+      }
+
+      /// Same change on a row held in memory, with YCQL's set semantics for tags
+      pub fn apply_to(&self, row: &mut UserProfileRowStruct) {
+        match self {
+          CollectionChange::AddTags(tags) => {
+            row.tags.extend(tags.iter().cloned());
+            row.tags.sort();
+            row.tags.dedup();
+          }
+          CollectionChange::RemoveTags(tags) => row.tags.retain(|tag| !tags.contains(tag)),
+          CollectionChange::AppendPhoneNumbers(numbers) => row.phone_numbers.extend(numbers.iter().cloned()),
+          CollectionChange::RemovePhoneNumbers(numbers) => row.phone_numbers.retain(|number| !numbers.contains(number)),
+          CollectionChange::PutAttributes(attributes) => row.attributes.extend(attributes.clone()),
+          CollectionChange::RemoveAttributes(keys) => row.attributes.retain(|key, _| !keys.contains(key)),
+        }
+      }
+    }
+
+    /// Update only the columns present in ***patch***, on an existing row
+    pub async fn patch_user_profile_query(
+      ycql_session: &CurrentYcqlDbSession,
+      user_id: Uuid,
+      time_uuid_order: Uuid,
+      patch: UserProfilePatch,
+      write: ExistingRowWrite
+     ) ->YugabyteDBResult<ConditionalWriteOutcome>{
+
+      let (set_clause, set_values) = patch.set_clause();
+      update_existing_row(ycql_session, user_id, time_uuid_order, &set_clause, set_values, write).await
+     }
+
+    /// Add or remove collection elements in place, the collection is never read back and rewritten
+    pub async fn update_collection_query(
+      ycql_session: &CurrentYcqlDbSession,
+      user_id: Uuid,
+      time_uuid_order: Uuid,
+      change: CollectionChange,
+      write: ExistingRowWrite
+     ) ->YugabyteDBResult<ConditionalWriteOutcome>{
+
+      let (set_clause, value) = change.set_clause();
+      update_existing_row(ycql_session, user_id, time_uuid_order, set_clause, vec![value], write).await
+     }
+
+    /// ***preferences->'a'->>'b'*** of one row, None when the row does not exist, Some(None) when the path does not
+    pub async fn select_preference_query(
+      ycql_session: &CurrentYcqlDbSession,
+      user_id: Uuid,
+      time_uuid_order: Uuid,
+      path: &PreferencePath
+     ) ->YugabyteDBResult<Option<Option<String>>>{
+
+      let select_preference = format!(
+        "SELECT {} AS value FROM {}.user_profile WHERE user_id = ? AND time_uuid_order = ?;",
+        path.selector(), ycql_session.keyspace()
+      );
+      let values = query_values!(user_id, time_uuid_order);
+
+      let res = ycql_session.query_with_values(select_preference, values).await?;
+
+      let new_rows= res.get_body()?.into_rows().expect("Could not get Row Body");
+
+      match new_rows.into_iter().next() {
+       Some(row) => Ok(Some(row.get_by_name("value")?)),
+       None => Ok(None),
+      }
+     }
+
+    /// ***UPDATE ... SET preferences->'a'->>'b' = ?***, the rest of the document stays as it is.
+    /// The object holding the last segment must already exist
+    pub async fn set_preference_query(
+      ycql_session: &CurrentYcqlDbSession,
+      user_id: Uuid,
+      time_uuid_order: Uuid,
+      path: &PreferencePath,
+      value: JsonValue,
+      write: ExistingRowWrite
+     ) ->YugabyteDBResult<ConditionalWriteOutcome>{
+
+      let set_clause = format!("{} = ?", path.selector());
+      update_existing_row(ycql_session, user_id, time_uuid_order, &set_clause, vec![Value::from(value.to_string())], write).await
+     }
+
+    /// ***UPDATE ... SET {set_clause}, version = ?*** guarded by ***write.condition***, in one logged batch with its audit row.
+    /// The statement text depends on the caller, so it is sent as text while the audit insert goes by prepared id.
+    async fn update_existing_row(
+      ycql_session: &CurrentYcqlDbSession,
+      user_id: Uuid,
+      time_uuid_order: Uuid,
+      set_clause: &str,
+      set_values: Vec<Value>,
+      write: ExistingRowWrite
+     ) ->YugabyteDBResult<ConditionalWriteOutcome>{
+
+      let (if_clause, condition_values) = write.condition.if_clause();
+      let update_user_profile = format!(
+        "UPDATE {}.user_profile USING TTL ? SET {}, version = ? WHERE user_id = ? AND time_uuid_order = ? {} ELSE ERROR;",
+        ycql_session.keyspace(), set_clause, if_clause
+      );
+      let mut values = vec![Value::from(write.ttl_secs)];
+      values.extend(set_values);
+      values.push(Value::from(write.new_version));
+      values.push(Value::from(user_id));
+      values.push(Value::from(time_uuid_order));
+      values.extend(condition_values);
+
+      let audit = audit_values(user_id, time_uuid_order, UserProfileAuditAction::Updated, Some(write.new_version));
+      let statements = vec![
+        (BatchStatement::Unprepared(update_user_profile), values),
+        (BatchStatement::Prepared(UserProfileStatement::InsertUserProfileAudit), audit),
+      ];
+      conditional_batch(ycql_session, BatchType::Logged, statements).await
+     }
+
+    /// Deletes the row and records it in user_profile_audit in one logged batch
     pub async fn delete_user_profile_query(
-      ycql_session: &mut CurrentYcqlDbSession,
+      ycql_session: &CurrentYcqlDbSession,
+      user_id: Uuid,
+      time_uuid: Uuid,
+      condition: RowCondition
+     ) ->YugabyteDBResult<ConditionalWriteOutcome>{
+
+      let delete = UserProfileWrite::Delete { user_id, time_uuid_order: time_uuid, condition };
+      user_profile_batch_query(ycql_session, UserProfileBatchType::Logged, vec![delete]).await
+     }
+
+    /// Audit trail of one profile, oldest first. It outlives the profile, deletes are recorded too
+    pub async fn select_user_profile_audit_query(
+      ycql_session: &CurrentYcqlDbSession,
+      user_id: Uuid,
+      time_uuid_order: Uuid
+     ) ->YugabyteDBResult<Vec<UserProfileAuditRowStruct>>{
+
+      let values = query_values!(user_id, time_uuid_order);
+
+      let res = ycql_session.execute_statement(UserProfileStatement::SelectUserProfileAudit, values).await?;
+
+      let new_rows= res.get_body()?.into_rows().expect("Could not get Row Body");
+
+      let mut audit_rows: Vec<UserProfileAuditRowStruct> = Vec::with_capacity(new_rows.len());
+
+      for row in new_rows {
+       audit_rows.push(UserProfileAuditRowStruct::try_from_row(row)?);
+      }
+
+      Ok(audit_rows)
+     }
+
+    /// One page of user profiles across all partitions
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct UserProfilePage {
+      pub user_profiles: Vec<UserProfileRowStruct>,
+      /// Driver paging state to continue from, None on the last page
+      pub paging_state: Option<Vec<u8>>,
+    }
+
+    /// Every user profile across all partitions, ***page_size*** rows at a time using CQL paging state
+    pub async fn list_user_profiles_query(
+      ycql_session: &CurrentYcqlDbSession,
+      page_size: i32,
+      paging_state: Option<Vec<u8>>
+     ) ->YugabyteDBResult<UserProfilePage>{
+
+      let mut query_params = QueryParamsBuilder::new().page_size(page_size);
+      if let Some(paging_state) = paging_state {
+        query_params = query_params.paging_state(CBytes::new(paging_state));
+      }
+
+      let res = ycql_session.execute_statement_with_params(UserProfileStatement::ListUserProfiles, query_params.finalize()).await?;
+
+      let body = res.get_body()?;
+
+      let next_paging_state = body.as_rows_metadata().and_then(|metadata| {
+        if RowsMetadataFlag::has_has_more_pages(metadata.flags) {
+          metadata.paging_state.and_then(|paging_state| paging_state.into_plain())
+        } else {
+          None
+        }
+      });
+
+      let new_rows= body.into_rows().expect("Could not get Row Body");
+
+      let mut user_profiles: Vec<UserProfileRowStruct> = Vec::with_capacity(new_rows.len());
+
+      for row in new_rows {
+       user_profiles.push(UserProfileRowStruct::try_from_row(row)?);
+      }
+
+      Ok(UserProfilePage { user_profiles, paging_state: next_paging_state })
+     }
+
+    /// ***roles*** row, a role name and the permissions it grants as text, eg ***profile:read***
+    #[derive(Clone, Serialize, Debug, PartialEq)]
+    pub struct RoleRowStruct {
+      pub role: String,
+      pub permissions: Vec<String>,
+    }
+
+    impl TryFromRow for RoleRowStruct {
+      fn try_from_row(row: Row) -> YugabyteDBResult<RoleRowStruct> {
+        Ok(RoleRowStruct {
+          role: row.get_r_by_name("role")?,
+          permissions: text_collection(&row, "permissions")?,
+        })
+      }
+    }
+
+    /// Every role with its permissions, the table only holds a handful of rows
+    pub async fn select_roles_query(ycql_session: &CurrentYcqlDbSession) ->YugabyteDBResult<Vec<RoleRowStruct>>{
+
+      let res = ycql_session.execute_statement_with_params(UserProfileStatement::SelectRoles, QueryParamsBuilder::new().finalize()).await?;
+
+      let new_rows= res.get_body()?.into_rows().expect("Could not get Row Body");
+
+      let mut roles: Vec<RoleRowStruct> = Vec::with_capacity(new_rows.len());
+
+      for row in new_rows {
+       roles.push(RoleRowStruct::try_from_row(row)?);
+      }
+
+      Ok(roles)
+     }
+
+    /// Role names assigned to a token subject in ***user_roles***
+    pub async fn select_user_roles_query(ycql_session: &CurrentYcqlDbSession, subject: &str) ->YugabyteDBResult<Vec<String>>{
+
+      let res = ycql_session.execute_statement(UserProfileStatement::SelectUserRoles, query_values!(subject)).await?;
+
+      let new_rows= res.get_body()?.into_rows().expect("Could not get Row Body");
+
+      let mut roles: Vec<String> = Vec::with_capacity(new_rows.len());
+
+      for row in new_rows {
+       roles.push(row.get_r_by_name("role")?);
+      }
+
+      Ok(roles)
+     }
+
+    /// Assigning a role twice writes the same row again
+    pub async fn insert_user_role_query(ycql_session: &CurrentYcqlDbSession, subject: &str, role: &str) ->YugabyteDBResult<()>{
+
+      ycql_session.execute_statement(UserProfileStatement::InsertUserRole, query_values!(subject, role)).await?;
+
+      Ok(())
+     }
+
+    /// Revoking a role the subject does not have is not an error
+    pub async fn delete_user_role_query(ycql_session: &CurrentYcqlDbSession, subject: &str, role: &str) ->YugabyteDBResult<()>{
+
+      ycql_session.execute_statement(UserProfileStatement::DeleteUserRole, query_values!(subject, role)).await?;
+
+      Ok(())
+     }
+
+    /// ***api_keys*** row, the secret itself is never stored
+    #[derive(Clone, Serialize, Debug, PartialEq)]
+    pub struct ApiKeyRowStruct {
+      pub key_id: Uuid,
+      /// Hex SHA-256 of the secret part of the key
+      #[serde(skip_serializing)]
+      pub key_hash: String,
+      pub name: String,
+      /// Permissions the key grants, as text like ***roles.permissions***
+      pub scopes: Vec<String>,
+      /// Token subject that issued the key
+      pub created_by: String,
+      /// Not a column: ***TTL(key_hash)***, None for keys that never expire
+      pub ttl_secs: Option<i32>,
+    }
+
+    impl TryFromRow for ApiKeyRowStruct {
+      fn try_from_row(row: Row) -> YugabyteDBResult<ApiKeyRowStruct> {
+        Ok(ApiKeyRowStruct {
+          key_id: row.get_r_by_name("key_id")?,
+          key_hash: row.get_r_by_name("key_hash")?,
+          name: row.get_r_by_name("name")?,
+          scopes: text_collection(&row, "scopes")?,
+          created_by: row.get_r_by_name("created_by")?,
+          ttl_secs: row.get_by_name("ttl_secs")?,
+        })
+      }
+    }
+
+    pub const API_KEY_COLUMNS: &str = "key_id, key_hash, name, scopes, created_by, TTL(key_hash) AS ttl_secs";
+
+    /// ***ttl_secs*** of the row is written as ***USING TTL***, 0 or None keeps the key until it is revoked
+    pub async fn insert_api_key_query(ycql_session: &CurrentYcqlDbSession, api_key_row: ApiKeyRowStruct) ->YugabyteDBResult<()>{
+
+      let values = query_values!(
+        api_key_row.key_id,
+        api_key_row.key_hash,
+        api_key_row.name,
+        api_key_row.scopes,
+        api_key_row.created_by,
+        api_key_row.ttl_secs.unwrap_or(0)
+      );
+
+      ycql_session.execute_statement(UserProfileStatement::InsertApiKey, values).await?;
+
+      Ok(())
+     }
+
+    pub async fn select_api_key_query(ycql_session: &CurrentYcqlDbSession, key_id: Uuid) ->YugabyteDBResult<Option<ApiKeyRowStruct>>{
+
+      let res = ycql_session.execute_statement(UserProfileStatement::SelectApiKey, query_values!(key_id)).await?;
+
+      let new_rows= res.get_body()?.into_rows().expect("Could not get Row Body");
+
+      new_rows.into_iter().next().map(ApiKeyRowStruct::try_from_row).transpose()
+     }
+
+    /// Every live key, the table holds one row per issued key so it stays small
+    pub async fn select_api_keys_query(ycql_session: &CurrentYcqlDbSession) ->YugabyteDBResult<Vec<ApiKeyRowStruct>>{
+
+      let res = ycql_session.execute_statement_with_params(UserProfileStatement::SelectApiKeys, QueryParamsBuilder::new().finalize()).await?;
+
+      let new_rows= res.get_body()?.into_rows().expect("Could not get Row Body");
+
+      let mut api_keys: Vec<ApiKeyRowStruct> = Vec::with_capacity(new_rows.len());
+
+      for row in new_rows {
+       api_keys.push(ApiKeyRowStruct::try_from_row(row)?);
+      }
+
+      Ok(api_keys)
+     }
+
+    pub async fn delete_api_key_query(ycql_session: &CurrentYcqlDbSession, key_id: Uuid) ->YugabyteDBResult<()>{
+
+      ycql_session.execute_statement(UserProfileStatement::DeleteApiKey, query_values!(key_id)).await?;
+
+      Ok(())
+     }
+
+    /// ***user_credentials*** row, keyed by the email address a user logs in with
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct UserCredentialsRowStruct {
+      pub email_address: String,
+      pub user_id: Uuid,
+      /// argon2 PHC string, salt and parameters included
+      pub password_hash: String,
+    }
+
+    impl TryFromRow for UserCredentialsRowStruct {
+      fn try_from_row(row: Row) -> YugabyteDBResult<UserCredentialsRowStruct> {
+        Ok(UserCredentialsRowStruct {
+          email_address: row.get_r_by_name("email_address")?,
+          user_id: row.get_r_by_name("user_id")?,
+          password_hash: row.get_r_by_name("password_hash")?,
+        })
+      }
+    }
+
+    /// Setting a password again replaces the hash
+    pub async fn upsert_user_credentials_query(ycql_session: &CurrentYcqlDbSession, credentials_row: UserCredentialsRowStruct) ->YugabyteDBResult<()>{
+
+      let values = query_values!(credentials_row.email_address, credentials_row.user_id, credentials_row.password_hash);
+
+      ycql_session.execute_statement(UserProfileStatement::UpsertUserCredentials, values).await?;
+
+      Ok(())
+     }
+
+    pub async fn select_user_credentials_query(ycql_session: &CurrentYcqlDbSession, email_address: &str) ->YugabyteDBResult<Option<UserCredentialsRowStruct>>{
+
+      let res = ycql_session.execute_statement(UserProfileStatement::SelectUserCredentials, query_values!(email_address)).await?;
+
+      let new_rows= res.get_body()?.into_rows().expect("Could not get Row Body");
+
+      new_rows.into_iter().next().map(UserCredentialsRowStruct::try_from_row).transpose()
+     }
+
+    /// One login session, the row lives as long as the refresh token that names it
+    pub async fn insert_refresh_token_query(
+      ycql_session: &CurrentYcqlDbSession,
       user_id: Uuid,
-      time_uuid: Uuid
+      token_id: Uuid,
+      email_address: &str,
+      ttl_secs: i32
      ) ->YugabyteDBResult<()>{
- 
-      //  let  my_uuid = Uuid::parse_str("607ad0f4-0000-1000-8000-010203040506").unwrap();
-       
-       static DELETE_USER_PROFILE: &'static str = r#"
-       DELETE FROM demo_ycql_service_keyspace.user_profile  WHERE user_id = ? AND time_uuid_order = ?;
-       "#;
-
-      let values = query_values!(user_id, time_uuid);
-   
-      ycql_session.query_with_values(DELETE_USER_PROFILE, values).await?;
+
+      let values = query_values!(user_id, token_id, email_address, ttl_secs);
+
+      ycql_session.execute_statement(UserProfileStatement::InsertRefreshToken, values).await?;
+
       Ok(())
-     }
\ No newline at end of file
+     }
+
+    /// ***IF EXISTS ELSE ERROR*** makes the delete the single use of the token: false when the row was already
+    /// deleted by an earlier rotation, a logout or its TTL
+    pub async fn delete_refresh_token_query(ycql_session: &CurrentYcqlDbSession, user_id: Uuid, token_id: Uuid) ->YugabyteDBResult<bool>{
+
+      match ycql_session.execute_statement(UserProfileStatement::DeleteRefreshToken, query_values!(user_id, token_id)).await {
+        Ok(_) => Ok(true),
+        Err(error) if is_condition_not_satisfied(&error) => Ok(false),
+        Err(error) => Err(error),
+      }
+     }
+
+    /// Every session of the user in one partition delete
+    pub async fn delete_refresh_tokens_query(ycql_session: &CurrentYcqlDbSession, user_id: Uuid) ->YugabyteDBResult<()>{
+
+      ycql_session.execute_statement(UserProfileStatement::DeleteRefreshTokens, query_values!(user_id)).await?;
+
+      Ok(())
+     }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_clause_only_lists_supplied_columns() {
+        let patch = UserProfilePatch {
+            full_name: None,
+            age: Some(30),
+            comment: Some(None),
+            address: None,
+            preferences: None,
+        };
+        let (set_clause, values) = patch.set_clause();
+        assert_eq!(set_clause, "age = ?, comment = ?");
+        assert_eq!(values.len(), 2);
+        assert!(UserProfilePatch::default().is_empty());
+    }
+
+    #[test]
+    fn preference_paths_render_selectors_and_reject_quotes() {
+        let path = PreferencePath::parse("theme.color").unwrap();
+        assert_eq!(path.selector(), "preferences->'theme'->>'color'");
+        assert_eq!(PreferencePath::parse("language").unwrap().selector(), "preferences->>'language'");
+        for bad in &["", "theme.", "theme..color", "x'; DROP TABLE user_profile; --", "a.b.c.d.e.f.g.h.i"] {
+            assert!(PreferencePath::parse(bad).is_err(), "{}", bad);
+        }
+
+        let mut preferences = Preferences(serde_json::json!({"theme": {"color": "dark", "size": 12}}));
+        assert_eq!(preferences.text_at(&path).as_deref(), Some("dark"));
+        assert_eq!(preferences.text_at(&PreferencePath::parse("theme.size").unwrap()).as_deref(), Some("12"));
+        assert!(!preferences.has_parent_of(&PreferencePath::parse("layout.density").unwrap()));
+        preferences.set_at(&path, serde_json::json!("light"));
+        assert_eq!(preferences.0, serde_json::json!({"theme": {"color": "light", "size": 12}}));
+    }
+
+    #[test]
+    fn only_server_errors_report_a_failed_condition() {
+        use cdrs_tokio::frame::frame_error::{AdditionalErrorInfo, CDRSError, SimpleError};
+        use cdrs_tokio::types::CString;
+        let server_error = |error_code, message: &str, additional_info| {
+            cdrs_tokio::error::Error::Server(CDRSError {
+                error_code,
+                message: CString::new(message.to_string()),
+                additional_info,
+            })
+        };
+        let failed_condition = "Execution Error. Condition on table user_profile was not satisfied.";
+        assert!(is_condition_not_satisfied(&server_error(0x2200, failed_condition, AdditionalErrorInfo::Invalid(SimpleError {}))));
+        assert!(!is_condition_not_satisfied(&server_error(0x2200, "Invalid Arguments. unknown column", AdditionalErrorInfo::Invalid(SimpleError {}))));
+        assert!(!is_condition_not_satisfied(&cdrs_tokio::error::Error::General(failed_condition.to_string())));
+    }
+
+    #[test]
+    fn every_write_goes_by_prepared_id_with_its_audit_row() {
+        let user_id = Uuid::new_v4();
+        let version = Uuid::new_v4();
+        let statements = UserProfileWrite::Delete { user_id, time_uuid_order: user_id, condition: RowCondition::VersionMatches(version) }.statements();
+        let kinds: Vec<&BatchStatement> = statements.iter().map(|(statement, _)| statement).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                &BatchStatement::Prepared(UserProfileStatement::DeleteUserProfileIfVersion),
+                &BatchStatement::Prepared(UserProfileStatement::InsertUserProfileAudit),
+            ]
+        );
+        assert_eq!(statements[0].1, vec![Value::from(user_id), Value::from(user_id), Value::from(version)]);
+        assert_eq!(statements[1].1, audit_values(user_id, user_id, UserProfileAuditAction::Deleted, None));
+    }
+}
@@ -0,0 +1,339 @@
+use crate::ycql_db_schema_and_queries::{API_KEY_COLUMNS, USER_PROFILE_COLUMNS};
+use crate::yugabyte_db_ycql_config::YcqlDbClusterSession;
+use cdrs_tokio::error::Error as YcqlError;
+use cdrs_tokio::frame::frame_error::AdditionalErrorInfo;
+use cdrs_tokio::frame::frame_batch::BatchType;
+use cdrs_tokio::frame::Frame;
+use cdrs_tokio::query::*;
+use cdrs_tokio::Result as YugabyteDBResult;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+/// Every static statement the service runs against user_profile and the tables next to it, each is prepared once per session.
+/// user_profile writes run by prepared id in a logged batch together with their audit row, so their conditions use ***ELSE ERROR***
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UserProfileStatement {
+    CreateUserProfile,
+    CreateUserProfileWithTtl,
+    SelectUserProfile,
+    UpdateFullNameAndAge,
+    UpdateFullNameAndAgeIfUnchanged,
+    UpdateFullNameAndAgeIfVersion,
+    DeleteUserProfile,
+    DeleteUserProfileIfUnchanged,
+    DeleteUserProfileIfVersion,
+    ListUserProfiles,
+    FindUserProfileByEmail,
+    SelectUserProfilesByUserId,
+    SelectUserProfileByKey,
+    InsertUserProfileAudit,
+    SelectUserProfileAudit,
+    SelectRoles,
+    SelectUserRoles,
+    InsertUserRole,
+    DeleteUserRole,
+    InsertApiKey,
+    SelectApiKey,
+    SelectApiKeys,
+    DeleteApiKey,
+    UpsertUserCredentials,
+    SelectUserCredentials,
+    InsertRefreshToken,
+    DeleteRefreshToken,
+    DeleteRefreshTokens,
+}
+
+impl UserProfileStatement {
+    pub const ALL: [UserProfileStatement; 28] = [
+        UserProfileStatement::CreateUserProfile,
+        UserProfileStatement::CreateUserProfileWithTtl,
+        UserProfileStatement::SelectUserProfile,
+        UserProfileStatement::UpdateFullNameAndAge,
+        UserProfileStatement::UpdateFullNameAndAgeIfUnchanged,
+        UserProfileStatement::UpdateFullNameAndAgeIfVersion,
+        UserProfileStatement::DeleteUserProfile,
+        UserProfileStatement::DeleteUserProfileIfUnchanged,
+        UserProfileStatement::DeleteUserProfileIfVersion,
+        UserProfileStatement::ListUserProfiles,
+        UserProfileStatement::FindUserProfileByEmail,
+        UserProfileStatement::SelectUserProfilesByUserId,
+        UserProfileStatement::SelectUserProfileByKey,
+        UserProfileStatement::InsertUserProfileAudit,
+        UserProfileStatement::SelectUserProfileAudit,
+        UserProfileStatement::SelectRoles,
+        UserProfileStatement::SelectUserRoles,
+        UserProfileStatement::InsertUserRole,
+        UserProfileStatement::DeleteUserRole,
+        UserProfileStatement::InsertApiKey,
+        UserProfileStatement::SelectApiKey,
+        UserProfileStatement::SelectApiKeys,
+        UserProfileStatement::DeleteApiKey,
+        UserProfileStatement::UpsertUserCredentials,
+        UserProfileStatement::SelectUserCredentials,
+        UserProfileStatement::InsertRefreshToken,
+        UserProfileStatement::DeleteRefreshToken,
+        UserProfileStatement::DeleteRefreshTokens,
+    ];
+
+    /// CQL text qualified with the configured keyspace
+    pub fn cql(self, keyspace: &str) -> String {
+        match self {
+            UserProfileStatement::CreateUserProfile => format!(r#"
+    INSERT INTO {}.user_profile (
+            user_id,
+            time_uuid_order,
+            email_address,
+            full_name,
+            age,
+            comment,
+            version,
+            address,
+            tags,
+            phone_numbers,
+            attributes,
+            preferences
+    )
+      VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) IF NOT EXISTS ELSE ERROR;
+    "#, keyspace),
+            UserProfileStatement::CreateUserProfileWithTtl => format!(r#"
+    INSERT INTO {}.user_profile (
+            user_id,
+            time_uuid_order,
+            email_address,
+            full_name,
+            age,
+            comment,
+            version,
+            address,
+            tags,
+            phone_numbers,
+            attributes,
+            preferences
+    )
+      VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) IF NOT EXISTS ELSE ERROR USING TTL ?;
+    "#, keyspace),
+            UserProfileStatement::SelectUserProfile => format!(r#"
+    SELECT {} FROM {}.user_profile
+      WHERE user_id = ? AND  email_address = ?;
+    "#, USER_PROFILE_COLUMNS, keyspace),
+            UserProfileStatement::UpdateFullNameAndAge => format!(r#"
+    UPDATE {}.user_profile USING TTL ? SET age = ? , full_name = ? , version = ? WHERE user_id = ? AND time_uuid_order = ? IF EXISTS ELSE ERROR;
+    "#, keyspace),
+            UserProfileStatement::UpdateFullNameAndAgeIfUnchanged => format!(r#"
+    UPDATE {}.user_profile USING TTL ? SET age = ? , full_name = ? , version = ? WHERE user_id = ? AND time_uuid_order = ?
+      IF full_name = ? AND age = ? ELSE ERROR;
+    "#, keyspace),
+            UserProfileStatement::UpdateFullNameAndAgeIfVersion => format!(r#"
+    UPDATE {}.user_profile USING TTL ? SET age = ? , full_name = ? , version = ? WHERE user_id = ? AND time_uuid_order = ?
+      IF version = ? ELSE ERROR;
+    "#, keyspace),
+            UserProfileStatement::DeleteUserProfile => format!(r#"
+       DELETE FROM {}.user_profile  WHERE user_id = ? AND time_uuid_order = ? IF EXISTS ELSE ERROR;
+       "#, keyspace),
+            UserProfileStatement::DeleteUserProfileIfUnchanged => format!(r#"
+       DELETE FROM {}.user_profile  WHERE user_id = ? AND time_uuid_order = ? IF full_name = ? AND age = ? ELSE ERROR;
+       "#, keyspace),
+            UserProfileStatement::DeleteUserProfileIfVersion => format!(r#"
+       DELETE FROM {}.user_profile  WHERE user_id = ? AND time_uuid_order = ? IF version = ? ELSE ERROR;
+       "#, keyspace),
+            UserProfileStatement::ListUserProfiles => format!(r#"
+    SELECT {} FROM {}.user_profile;
+    "#, USER_PROFILE_COLUMNS, keyspace),
+            // served by the user_profile_by_email unique index
+            UserProfileStatement::FindUserProfileByEmail => format!(r#"
+    SELECT {} FROM {}.user_profile WHERE email_address = ?;
+    "#, USER_PROFILE_COLUMNS, keyspace),
+            UserProfileStatement::SelectUserProfilesByUserId => format!(r#"
+    SELECT {} FROM {}.user_profile WHERE user_id = ?;
+    "#, USER_PROFILE_COLUMNS, keyspace),
+            UserProfileStatement::SelectUserProfileByKey => format!(r#"
+    SELECT {} FROM {}.user_profile WHERE user_id = ? AND time_uuid_order = ?;
+    "#, USER_PROFILE_COLUMNS, keyspace),
+            UserProfileStatement::InsertUserProfileAudit => format!(r#"
+    INSERT INTO {}.user_profile_audit (user_id, time_uuid_order, audit_id, action, version) VALUES (?, ?, now(), ?, ?);
+    "#, keyspace),
+            UserProfileStatement::SelectUserProfileAudit => format!(r#"
+    SELECT user_id, time_uuid_order, audit_id, action, version FROM {}.user_profile_audit
+      WHERE user_id = ? AND time_uuid_order = ?;
+    "#, keyspace),
+            UserProfileStatement::SelectRoles => format!(r#"
+    SELECT role, permissions FROM {}.roles;
+    "#, keyspace),
+            UserProfileStatement::SelectUserRoles => format!(r#"
+    SELECT role FROM {}.user_roles WHERE subject = ?;
+    "#, keyspace),
+            UserProfileStatement::InsertUserRole => format!(r#"
+    INSERT INTO {}.user_roles (subject, role) VALUES (?, ?);
+    "#, keyspace),
+            UserProfileStatement::DeleteUserRole => format!(r#"
+    DELETE FROM {}.user_roles WHERE subject = ? AND role = ?;
+    "#, keyspace),
+            // TTL 0 keeps the key until it is revoked
+            UserProfileStatement::InsertApiKey => format!(r#"
+    INSERT INTO {}.api_keys (key_id, key_hash, name, scopes, created_by) VALUES (?, ?, ?, ?, ?) USING TTL ?;
+    "#, keyspace),
+            UserProfileStatement::SelectApiKey => format!(r#"
+    SELECT {} FROM {}.api_keys WHERE key_id = ?;
+    "#, API_KEY_COLUMNS, keyspace),
+            UserProfileStatement::SelectApiKeys => format!(r#"
+    SELECT {} FROM {}.api_keys;
+    "#, API_KEY_COLUMNS, keyspace),
+            UserProfileStatement::DeleteApiKey => format!(r#"
+    DELETE FROM {}.api_keys WHERE key_id = ?;
+    "#, keyspace),
+            UserProfileStatement::UpsertUserCredentials => format!(r#"
+    INSERT INTO {}.user_credentials (email_address, user_id, password_hash) VALUES (?, ?, ?);
+    "#, keyspace),
+            UserProfileStatement::SelectUserCredentials => format!(r#"
+    SELECT email_address, user_id, password_hash FROM {}.user_credentials WHERE email_address = ?;
+    "#, keyspace),
+            UserProfileStatement::InsertRefreshToken => format!(r#"
+    INSERT INTO {}.refresh_tokens (user_id, token_id, email_address) VALUES (?, ?, ?) USING TTL ?;
+    "#, keyspace),
+            UserProfileStatement::DeleteRefreshToken => format!(r#"
+    DELETE FROM {}.refresh_tokens WHERE user_id = ? AND token_id = ? IF EXISTS ELSE ERROR;
+    "#, keyspace),
+            UserProfileStatement::DeleteRefreshTokens => format!(r#"
+    DELETE FROM {}.refresh_tokens WHERE user_id = ?;
+    "#, keyspace),
+        }
+    }
+}
+
+/// One entry of a batch. Registry statements go by prepared id, statements whose text depends on the request
+/// (patches, collection and preference changes) are sent as text
+#[derive(Clone, Debug, PartialEq)]
+pub enum BatchStatement {
+    Prepared(UserProfileStatement),
+    Unprepared(String),
+}
+
+/// Prepared statement cache shared by every clone of the session handle.
+/// The lock only guards the map and is never held across an await point.
+pub struct YcqlStatementRegistry {
+    keyspace: Arc<str>,
+    prepared: RwLock<HashMap<UserProfileStatement, Arc<PreparedQuery>>>,
+}
+
+impl YcqlStatementRegistry {
+    pub fn new(keyspace: Arc<str>) -> YcqlStatementRegistry {
+        YcqlStatementRegistry {
+            keyspace,
+            prepared: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Prepare every statement up front so a broken schema fails at startup instead of on the first request
+    pub async fn prepare_all(&self, session: &YcqlDbClusterSession) -> YugabyteDBResult<()> {
+        for statement in UserProfileStatement::ALL.iter() {
+            self.prepare(session, *statement).await?;
+        }
+        Ok(())
+    }
+
+    /// Execute a statement by prepared id, preparing it on first use.
+    /// When the server no longer knows the id (node restart, cache eviction) it is prepared again and retried once.
+    pub async fn execute(
+        &self,
+        session: &YcqlDbClusterSession,
+        statement: UserProfileStatement,
+        values: QueryValues,
+    ) -> YugabyteDBResult<Frame> {
+        let query_params = QueryParamsBuilder::new().values(values).finalize();
+        self.execute_with_params(session, statement, query_params).await
+    }
+
+    /// Same as execute, with full control over paging and consistency
+    pub async fn execute_with_params(
+        &self,
+        session: &YcqlDbClusterSession,
+        statement: UserProfileStatement,
+        query_params: QueryParams,
+    ) -> YugabyteDBResult<Frame> {
+        let prepared = match self.cached(statement) {
+            Some(prepared) => prepared,
+            None => self.prepare(session, statement).await?,
+        };
+        match session.exec_with_params(&prepared, query_params.clone()).await {
+            Err(ref error) if is_unprepared_error(error) => {
+                let prepared = self.prepare(session, statement).await?;
+                session.exec_with_params(&prepared, query_params).await
+            }
+            result => result,
+        }
+    }
+
+    /// Run several statements as one CQL batch, values in the same order as ***statements***.
+    /// Unknown ids are prepared again and the whole batch retried once, like execute
+    pub async fn execute_batch(
+        &self,
+        session: &YcqlDbClusterSession,
+        batch_type: BatchType,
+        statements: Vec<(BatchStatement, QueryValues)>,
+    ) -> YugabyteDBResult<Frame> {
+        let batch = self.prepared_batch(session, batch_type.clone(), &statements).await?;
+        match session.batch_with_params(batch).await {
+            Err(ref error) if is_unprepared_error(error) => {
+                let distinct: HashSet<UserProfileStatement> = statements
+                    .iter()
+                    .filter_map(|(statement, _)| match statement {
+                        BatchStatement::Prepared(statement) => Some(*statement),
+                        BatchStatement::Unprepared(_) => None,
+                    })
+                    .collect();
+                for statement in distinct {
+                    self.prepare(session, statement).await?;
+                }
+                let batch = self.prepared_batch(session, batch_type, &statements).await?;
+                session.batch_with_params(batch).await
+            }
+            result => result,
+        }
+    }
+
+    async fn prepared_batch(
+        &self,
+        session: &YcqlDbClusterSession,
+        batch_type: BatchType,
+        statements: &[(BatchStatement, QueryValues)],
+    ) -> YugabyteDBResult<QueryBatch> {
+        let mut batch = BatchQueryBuilder::new().batch_type(batch_type);
+        for (statement, values) in statements {
+            batch = match statement {
+                BatchStatement::Prepared(statement) => {
+                    let prepared = match self.cached(*statement) {
+                        Some(prepared) => prepared,
+                        None => self.prepare(session, *statement).await?,
+                    };
+                    batch.add_query_prepared(prepared.as_ref().clone(), values.clone())
+                }
+                BatchStatement::Unprepared(cql) => batch.add_query(cql.as_str(), values.clone()),
+            };
+        }
+        batch.finalize()
+    }
+
+    fn cached(&self, statement: UserProfileStatement) -> Option<Arc<PreparedQuery>> {
+        self.prepared
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(&statement)
+            .cloned()
+    }
+
+    async fn prepare(&self, session: &YcqlDbClusterSession, statement: UserProfileStatement) -> YugabyteDBResult<Arc<PreparedQuery>> {
+        let prepared = Arc::new(session.prepare(statement.cql(&self.keyspace)).await?);
+        self.prepared
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(statement, prepared.clone());
+        Ok(prepared)
+    }
+}
+
+fn is_unprepared_error(error: &YcqlError) -> bool {
+    match error {
+        YcqlError::Server(server_error) => matches!(server_error.additional_info, AdditionalErrorInfo::Unprepared(_)),
+        _ => false,
+    }
+}
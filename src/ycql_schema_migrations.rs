@@ -0,0 +1,455 @@
+use crate::yugabyte_db_ycql_config::CurrentYcqlDbSession;
+use cdrs_tokio::query::*;
+use cdrs_tokio::query_values;
+use cdrs_tokio::types::prelude::*;
+use cdrs_tokio::types::from_cdrs::FromCDRSByName;
+use cdrs_tokio::types::IntoRustByName;
+use cdrs_tokio::Result as YugabyteDBResult;
+use cdrs_tokio_helpers_derive::*;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One ordered CQL migration file from ***migrations/***, ***{keyspace}*** inside the file is replaced with the configured keyspace
+pub struct SchemaMigration {
+    pub version: i32,
+    pub name: &'static str,
+    pub cql: &'static str,
+}
+
+/// Every migration the service knows about, versions must be strictly increasing.
+/// Never edit a file once it has been applied anywhere, add a new one instead.
+pub const SCHEMA_MIGRATIONS: &[SchemaMigration] = &[
+    SchemaMigration {
+        version: 1,
+        name: "create_user_profile_table",
+        cql: include_str!("../migrations/0001_create_user_profile_table.cql"),
+    },
+    SchemaMigration {
+        version: 2,
+        name: "create_user_profile_email_index",
+        cql: include_str!("../migrations/0002_create_user_profile_email_index.cql"),
+    },
+    SchemaMigration {
+        version: 3,
+        name: "add_user_profile_version",
+        cql: include_str!("../migrations/0003_add_user_profile_version.cql"),
+    },
+    SchemaMigration {
+        version: 4,
+        name: "add_user_profile_complex_types",
+        cql: include_str!("../migrations/0004_add_user_profile_complex_types.cql"),
+    },
+    SchemaMigration {
+        version: 5,
+        name: "add_user_profile_preferences",
+        cql: include_str!("../migrations/0005_add_user_profile_preferences.cql"),
+    },
+    SchemaMigration {
+        version: 6,
+        name: "create_user_profile_audit_table",
+        cql: include_str!("../migrations/0006_create_user_profile_audit_table.cql"),
+    },
+    SchemaMigration {
+        version: 7,
+        name: "create_roles_and_user_roles_tables",
+        cql: include_str!("../migrations/0007_create_roles_and_user_roles_tables.cql"),
+    },
+    SchemaMigration {
+        version: 8,
+        name: "create_api_keys_table",
+        cql: include_str!("../migrations/0008_create_api_keys_table.cql"),
+    },
+    SchemaMigration {
+        version: 9,
+        name: "create_user_credentials_and_refresh_tokens_tables",
+        cql: include_str!("../migrations/0009_create_user_credentials_and_refresh_tokens_tables.cql"),
+    },
+    SchemaMigration {
+        version: 10,
+        name: "grant_credentials_manage_to_admin",
+        cql: include_str!("../migrations/0010_grant_credentials_manage_to_admin.cql"),
+    },
+];
+
+const MIGRATION_APPLIED: &str = "applied";
+const MIGRATION_FAILED: &str = "failed";
+
+#[derive(Clone, Debug, TryFromRow)]
+struct SchemaMigrationRow {
+    version: i32,
+    name: String,
+    checksum: String,
+    status: String,
+    error: Option<String>,
+}
+
+/// State of one known migration
+#[derive(Debug, PartialEq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    Failed { error: String },
+    /// The file changed after it was applied
+    ChecksumMismatch { applied: String, current: String },
+}
+
+#[derive(Debug)]
+pub struct MigrationStatus {
+    pub version: i32,
+    pub name: &'static str,
+    pub state: MigrationState,
+}
+
+/// Result of ***migrate status***
+#[derive(Debug)]
+pub struct MigrationReport {
+    /// False when the keyspace has no ***schema_migrations*** table yet, every migration is then pending
+    pub initialised: bool,
+    pub migrations: Vec<MigrationStatus>,
+}
+
+impl MigrationReport {
+    /// Compares SCHEMA_MIGRATIONS with the rows of ***schema_migrations***
+    fn from_recorded(initialised: bool, recorded: &HashMap<i32, SchemaMigrationRow>) -> MigrationReport {
+        let migrations = SCHEMA_MIGRATIONS
+            .iter()
+            .map(|migration| {
+                let current = migration_checksum(migration.cql);
+                let state = match recorded.get(&migration.version) {
+                    None => MigrationState::Pending,
+                    Some(row) if row.status == MIGRATION_FAILED => MigrationState::Failed {
+                        error: row.error.clone().unwrap_or_default(),
+                    },
+                    Some(row) if row.checksum != current => MigrationState::ChecksumMismatch {
+                        applied: row.checksum.clone(),
+                        current,
+                    },
+                    Some(_) => MigrationState::Applied,
+                };
+                MigrationStatus {
+                    version: migration.version,
+                    name: migration.name,
+                    state,
+                }
+            })
+            .collect();
+        MigrationReport { initialised, migrations }
+    }
+
+    /// True when every migration is applied and unchanged, the server refuses to start otherwise
+    pub fn is_up_to_date(&self) -> bool {
+        self.initialised && self.migrations.iter().all(|migration| migration.state == MigrationState::Applied)
+    }
+}
+
+impl Display for MigrationReport {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        if !self.initialised {
+            writeln!(f, "not initialised: the keyspace has no schema_migrations table")?;
+        }
+        for migration in &self.migrations {
+            let state = match &migration.state {
+                MigrationState::Applied => "applied".to_string(),
+                MigrationState::Pending => "pending".to_string(),
+                MigrationState::Failed { error } => format!("FAILED: {}", error),
+                MigrationState::ChecksumMismatch { applied, current } => {
+                    format!("CHANGED after apply (applied checksum {}, file checksum {})", applied, current)
+                }
+            };
+            writeln!(f, "{:04} {:<40} {}", migration.version, migration.name, state)?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies SCHEMA_MIGRATIONS in order and records each one in the ***schema_migrations*** table
+pub struct YcqlSchemaMigrator<'a> {
+    ycql_session: &'a CurrentYcqlDbSession,
+    replication_factor: u16,
+    default_ttl_secs: i32,
+}
+
+impl<'a> YcqlSchemaMigrator<'a> {
+    /// ***default_ttl_secs*** is the configured user_profile table TTL, 0 for none
+    pub fn new(ycql_session: &'a CurrentYcqlDbSession, replication_factor: u16, default_ttl_secs: i32) -> YcqlSchemaMigrator<'a> {
+        YcqlSchemaMigrator {
+            ycql_session,
+            replication_factor,
+            default_ttl_secs,
+        }
+    }
+
+    /// Reads ***system_schema*** only, a missing keyspace is reported the same as a missing table
+    async fn tracking_table_exists(&self) -> YugabyteDBResult<bool> {
+        let select_tracking_table =
+            "SELECT table_name FROM system_schema.tables WHERE keyspace_name = ? AND table_name = 'schema_migrations';".to_string();
+        let rows = self
+            .ycql_session
+            .query_with_values(select_tracking_table, query_values!(self.ycql_session.keyspace().to_string()))
+            .await?
+            .get_body()?
+            .into_rows()
+            .unwrap_or_default();
+        Ok(!rows.is_empty())
+    }
+
+    /// Create the configured keyspace or ***DATABASE*** if you are coming from a NoSQL world, plus the tracking table
+    async fn ensure_tracking_table(&self) -> YugabyteDBResult<()> {
+        let keyspace = self.ycql_session.keyspace();
+        let create_keyspace = format!(
+            "CREATE KEYSPACE IF NOT EXISTS {} WITH REPLICATION = {{ 'class' : 'SimpleStrategy', 'replication_factor' : {} }};",
+            keyspace, self.replication_factor
+        );
+        self.ycql_session.query(create_keyspace).await?;
+
+        let create_schema_migrations = format!(r#"
+        CREATE TABLE IF NOT EXISTS {}.schema_migrations(
+            version INT,
+            name TEXT,
+            checksum TEXT,
+            status TEXT,
+            error TEXT,
+            applied_at TIMESTAMP,
+            PRIMARY KEY (version));
+            "#, keyspace);
+        self.ycql_session.query(create_schema_migrations).await?;
+        Ok(())
+    }
+
+    async fn recorded_migrations(&self) -> YugabyteDBResult<HashMap<i32, SchemaMigrationRow>> {
+        let select_schema_migrations = format!(
+            "SELECT version, name, checksum, status, error FROM {}.schema_migrations;",
+            self.ycql_session.keyspace()
+        );
+        let rows = self.ycql_session.query(select_schema_migrations).await?
+            .get_body()?
+            .into_rows()
+            .unwrap_or_default();
+        let mut recorded = HashMap::with_capacity(rows.len());
+        for row in rows {
+            let migration = SchemaMigrationRow::try_from_row(row)?;
+            recorded.insert(migration.version, migration);
+        }
+        Ok(recorded)
+    }
+
+    /// Read only, used by ***migrate status*** and the startup check. Nothing is created,
+    /// a keyspace without the tracking table is reported as not initialised
+    pub async fn status(&self) -> YugabyteDBResult<MigrationReport> {
+        if !self.tracking_table_exists().await? {
+            return Ok(MigrationReport::from_recorded(false, &HashMap::new()));
+        }
+        let recorded = self.recorded_migrations().await?;
+        Ok(MigrationReport::from_recorded(true, &recorded))
+    }
+
+    /// Apply every pending or previously failed migration in version order, stopping at the first failure.
+    /// A failed migration is retried from its first statement: CREATE statements carry IF NOT EXISTS, seed rows
+    /// are upserts, and an ALTER TABLE ADD whose column an earlier run added counts as applied
+    pub async fn migrate_up(&self) -> YugabyteDBResult<MigrationReport> {
+        self.ensure_tracking_table().await?;
+        let report = self.status().await?;
+        for (migration, status) in SCHEMA_MIGRATIONS.iter().zip(report.migrations.iter()) {
+            match &status.state {
+                MigrationState::Applied => continue,
+                MigrationState::ChecksumMismatch { .. } => {
+                    return Err(cdrs_tokio::error::Error::General(format!(
+                        "migration {:04} {} was changed after it was applied, restore the original file and add a new migration instead",
+                        migration.version, migration.name
+                    )))
+                }
+                MigrationState::Pending | MigrationState::Failed { .. } => {}
+            }
+
+            let cql = migration.cql.replace("{keyspace}", self.ycql_session.keyspace());
+            for statement in split_cql_statements(&cql) {
+                match self.ycql_session.query(statement.clone()).await {
+                    Ok(_) => {}
+                    Err(e) if is_column_already_added(&statement, &e.to_string()) => {
+                        log::info!("migration {:04}: skipped `{}`, the column was added by an earlier run", migration.version, statement);
+                    }
+                    Err(e) => {
+                        self.record(migration, MIGRATION_FAILED, Some(e.to_string())).await?;
+                        return Err(e);
+                    }
+                }
+            }
+            self.record(migration, MIGRATION_APPLIED, None).await?;
+        }
+        self.apply_default_ttl().await?;
+        self.status().await
+    }
+
+    /// Table TTL comes from the service configuration rather than a migration file,
+    /// it is set again on every ***migrate up*** so a changed value only needs a re-run
+    async fn apply_default_ttl(&self) -> YugabyteDBResult<()> {
+        let alter_user_profile = format!(
+            "ALTER TABLE {}.user_profile WITH default_time_to_live = {};",
+            self.ycql_session.keyspace(),
+            self.default_ttl_secs
+        );
+        self.ycql_session.query(alter_user_profile).await?;
+        Ok(())
+    }
+
+    /// Read only like ***status***. Describes how the user_profile ***default_time_to_live*** differs from
+    /// ***retention.default_ttl_secs***, None when they agree or the table does not exist yet
+    pub async fn default_ttl_mismatch(&self) -> YugabyteDBResult<Option<String>> {
+        let select_default_ttl =
+            "SELECT default_time_to_live FROM system_schema.tables WHERE keyspace_name = ? AND table_name = 'user_profile';".to_string();
+        let rows = self
+            .ycql_session
+            .query_with_values(select_default_ttl, query_values!(self.ycql_session.keyspace().to_string()))
+            .await?
+            .get_body()?
+            .into_rows()
+            .unwrap_or_default();
+        let table_ttl_secs: Option<i32> = match rows.into_iter().next() {
+            Some(row) => row.get_by_name("default_time_to_live")?,
+            None => return Ok(None),
+        };
+        let table_ttl_secs = table_ttl_secs.unwrap_or(0);
+        if table_ttl_secs == self.default_ttl_secs {
+            return Ok(None);
+        }
+        Ok(Some(format!(
+            "user_profile default_time_to_live is {} but retention.default_ttl_secs is {}",
+            table_ttl_secs, self.default_ttl_secs
+        )))
+    }
+
+    async fn record(&self, migration: &SchemaMigration, status: &str, error: Option<String>) -> YugabyteDBResult<()> {
+        let insert_schema_migration = format!(
+            "INSERT INTO {}.schema_migrations (version, name, checksum, status, error, applied_at) VALUES (?, ?, ?, ?, ?, ?);",
+            self.ycql_session.keyspace()
+        );
+        let applied_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as i64)
+            .unwrap_or_default();
+        let values = query_values!(
+            migration.version,
+            migration.name.to_string(),
+            migration_checksum(migration.cql),
+            status.to_string(),
+            error,
+            applied_at
+        );
+        self.ycql_session.query_with_values(insert_schema_migration, values).await?;
+        Ok(())
+    }
+}
+
+/// FNV-1a, stable across Rust releases unlike DefaultHasher
+fn migration_checksum(cql: &str) -> String {
+    let hash = cql.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// YugabyteDB error texts, lower-cased, when ***ALTER TABLE ... ADD*** names a column the table already has
+const COLUMN_ALREADY_ADDED_ERRORS: &[&str] = &["duplicate column", "already exists"];
+
+/// ALTER TABLE ADD has no IF NOT EXISTS, so a retried migration meets the columns its failed run did add.
+/// Only column adds are forgiven, every other statement re-runs safely on its own:
+/// CREATE ... IF NOT EXISTS, or INSERT and UPDATE which overwrite the same cells
+fn is_column_already_added(statement: &str, error: &str) -> bool {
+    let statement = statement.split_whitespace().collect::<Vec<_>>().join(" ").to_ascii_uppercase();
+    let error = error.to_ascii_lowercase();
+    statement.starts_with("ALTER TABLE ")
+        && statement.contains(" ADD ")
+        && COLUMN_ALREADY_ADDED_ERRORS.iter().any(|text| error.contains(text))
+}
+
+/// Split a migration file into statements, dropping ***--*** comment lines
+fn split_cql_statements(cql: &str) -> Vec<String> {
+    let without_comments: String = cql
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("--"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    without_comments
+        .split(';')
+        .map(str::trim)
+        .filter(|statement| !statement.is_empty())
+        .map(|statement| format!("{};", statement))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migration_versions_are_strictly_increasing() {
+        for pair in SCHEMA_MIGRATIONS.windows(2) {
+            assert!(pair[0].version < pair[1].version, "{} must come before {}", pair[0].name, pair[1].name);
+        }
+    }
+
+    #[test]
+    fn split_cql_statements_drops_comments_and_empty_statements() {
+        let statements = split_cql_statements("-- header\nCREATE TABLE a(x INT PRIMARY KEY);\n\n  CREATE INDEX b ON a (x);\n;");
+        assert_eq!(statements, vec!["CREATE TABLE a(x INT PRIMARY KEY);", "CREATE INDEX b ON a (x);"]);
+    }
+
+    #[test]
+    fn report_compares_recorded_rows_with_the_migration_files() {
+        let not_initialised = MigrationReport::from_recorded(false, &HashMap::new());
+        assert!(!not_initialised.is_up_to_date());
+        assert!(not_initialised.to_string().starts_with("not initialised"));
+        assert!(not_initialised.migrations.iter().all(|migration| migration.state == MigrationState::Pending));
+
+        let mut recorded: HashMap<i32, SchemaMigrationRow> = SCHEMA_MIGRATIONS
+            .iter()
+            .map(|migration| {
+                let row = SchemaMigrationRow {
+                    version: migration.version,
+                    name: migration.name.to_string(),
+                    checksum: migration_checksum(migration.cql),
+                    status: MIGRATION_APPLIED.to_string(),
+                    error: None,
+                };
+                (migration.version, row)
+            })
+            .collect();
+        assert!(MigrationReport::from_recorded(true, &recorded).is_up_to_date());
+
+        recorded.get_mut(&1).unwrap().checksum = "0000000000000000".to_string();
+        let failed = recorded.get_mut(&2).unwrap();
+        failed.status = MIGRATION_FAILED.to_string();
+        failed.error = Some("timed out".to_string());
+        recorded.remove(&3);
+        let report = MigrationReport::from_recorded(true, &recorded);
+        assert!(!report.is_up_to_date());
+        assert!(matches!(report.migrations[0].state, MigrationState::ChecksumMismatch { .. }));
+        assert_eq!(report.migrations[1].state, MigrationState::Failed { error: "timed out".to_string() });
+        assert_eq!(report.migrations[2].state, MigrationState::Pending);
+    }
+
+    #[test]
+    fn only_a_repeated_column_add_counts_as_applied() {
+        let add = "ALTER TABLE ks.user_profile ADD version UUID;";
+        assert!(is_column_already_added(add, "Invalid Table Definition. Duplicate column name: version"));
+        assert!(is_column_already_added("alter table ks.user_profile\n  add tags SET<TEXT>;", "Column tags already exists"));
+        assert!(!is_column_already_added(add, "Server error: timed out"));
+        assert!(!is_column_already_added("CREATE TABLE ks.roles(role TEXT PRIMARY KEY);", "Table already exists"));
+    }
+
+    /// A failed migration is retried from its first statement, so each one must survive running twice
+    #[test]
+    fn every_migration_statement_can_be_re_run() {
+        for migration in SCHEMA_MIGRATIONS {
+            for statement in split_cql_statements(migration.cql) {
+                let normalized = statement.split_whitespace().collect::<Vec<_>>().join(" ").to_ascii_uppercase();
+                let re_runnable = normalized.contains(" IF NOT EXISTS ")
+                    || (normalized.starts_with("ALTER TABLE ") && normalized.contains(" ADD "))
+                    || normalized.starts_with("INSERT INTO ")
+                    || (normalized.starts_with("UPDATE ") && normalized.contains("PERMISSIONS = PERMISSIONS +"));
+                assert!(re_runnable, "migration {:04} {}: `{}` cannot be re-run", migration.version, migration.name, statement);
+            }
+        }
+    }
+}
@@ -0,0 +1,798 @@
+use crate::time_uuid_generator::parse_node_id;
+use crate::ycql_db_schema_and_queries::MAX_TTL_SECS;
+use derive_more::Display;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Environment variable pointing at the TOML configuration file
+pub const CONFIG_PATH_ENV: &str = "YCQL_SERVICE_CONFIG";
+/// Configuration file used when ***YCQL_SERVICE_CONFIG*** is not set, it is optional
+pub const DEFAULT_CONFIG_PATH: &str = "config/ycql_service.toml";
+
+/// Startup configuration errors, every message is meant to be read by whoever deploys the service
+#[derive(Debug, Display)]
+pub enum ConfigError {
+    #[display(fmt = "could not read configuration file {}: {}", path, reason)]
+    Unreadable { path: String, reason: String },
+    #[display(fmt = "could not parse configuration file {}: {}", path, reason)]
+    Malformed { path: String, reason: String },
+    #[display(fmt = "environment variable {} has an invalid value: {}", variable, reason)]
+    InvalidEnvironment { variable: String, reason: String },
+    #[display(fmt = "invalid configuration:\n  - {}", "_0.join(\"\n  - \")")]
+    Invalid(Vec<String>),
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Whole service configuration
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServiceConfig {
+    pub server: ServerConfig,
+    pub yugabyte: YugabyteDbConfig,
+    pub pagination: PaginationConfig,
+    pub time_uuid: TimeUuidConfig,
+    pub retention: RetentionConfig,
+    pub batch: BatchConfig,
+    pub jwt: JwtConfig,
+    pub access_control: AccessControlConfig,
+    pub login: LoginConfig,
+}
+
+/// HTTP server configuration
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: String,
+}
+
+/// Page sizes for list endpoints
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PaginationConfig {
+    /// Used when the request does not ask for a page size
+    pub default_page_size: i32,
+    /// Larger requested page sizes are clamped to this
+    pub max_page_size: i32,
+}
+
+/// TimeUUID generation
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeUuidConfig {
+    /// 12 hex digits, eg ***02:42:ac:11:00:02***. Leave unset for a random node id per process,
+    /// set it when several instances must be told apart in the generated ids.
+    pub node_id: Option<String>,
+}
+
+/// How long user profiles are kept
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// ***default_time_to_live*** of the user_profile table in seconds, 0 keeps rows forever.
+    /// Applied by ***migrate up***, a create request can still send its own ***ttl_secs***
+    pub default_ttl_secs: i32,
+}
+
+/// Limits of the batch write endpoint
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatchConfig {
+    /// Largest accepted batch body in bytes, separate from the 4096 bytes allowed on every other route
+    pub max_payload_bytes: usize,
+    /// Most operations one batch request may carry
+    pub max_operations: usize,
+    /// How many user_id partitions are written at the same time
+    pub max_concurrent_groups: usize,
+}
+
+/// Bearer token verification for every ***/api_v1*** route
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtConfig {
+    /// Every token must carry this ***iss***
+    pub issuer: String,
+    /// Every token must list this in ***aud***
+    pub audience: String,
+    /// Clock skew in seconds tolerated on ***exp***
+    pub leeway_secs: u64,
+    pub key: JwtKeyConfig,
+}
+
+/// Role based access control on top of the verified token
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessControlConfig {
+    /// How long the ***user_roles*** assignments and ***roles*** permissions read for a subject are reused.
+    /// Assignments changed through this instance take effect at once, changes made elsewhere within this many seconds.
+    /// 0 reads them on every request
+    pub role_cache_ttl_secs: u64,
+}
+
+/// Tokens issued by the ***/auth*** login routes
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoginConfig {
+    /// ***exp*** of access tokens, they stay valid until then even after a logout
+    pub access_token_ttl_secs: u64,
+    /// ***exp*** of refresh tokens and the TTL of their ***refresh_tokens*** row
+    pub refresh_token_ttl_secs: i32,
+}
+
+/// Signing algorithm and verification key, selected with ***algorithm***
+#[derive(Clone, Deserialize)]
+#[serde(tag = "algorithm")]
+pub enum JwtKeyConfig {
+    /// Shared secret of at least 32 bytes
+    #[serde(rename = "HS256")]
+    Hs256 { secret: String },
+    /// PEM public key of the token issuer, read at startup.
+    /// The login routes sign with ***private_key_path***, without it they are not mounted
+    #[serde(rename = "RS256")]
+    Rs256 {
+        public_key_path: PathBuf,
+        #[serde(default)]
+        private_key_path: Option<PathBuf>,
+    },
+}
+
+// never print the secret
+impl std::fmt::Debug for JwtKeyConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            JwtKeyConfig::Hs256 { .. } => f.debug_struct("Hs256").field("secret", &"***").finish(),
+            JwtKeyConfig::Rs256 { public_key_path, private_key_path } => f
+                .debug_struct("Rs256")
+                .field("public_key_path", public_key_path)
+                .field("private_key_path", private_key_path)
+                .finish(),
+        }
+    }
+}
+
+/// YCQL cluster configuration
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct YugabyteDbConfig {
+    /// Every YB-TServer the driver should open a pool against, formatted as ***host:port***
+    pub contact_points: Vec<String>,
+    pub keyspace: String,
+    pub replication_factor: u16,
+    pub pool: YcqlPoolConfig,
+    pub timeouts: YcqlTimeoutConfig,
+    pub auth: YcqlAuthConfig,
+}
+
+/// Per node connection pool sizes
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct YcqlPoolConfig {
+    pub max_size: u32,
+    pub min_idle: Option<u32>,
+}
+
+/// Per node connection pool timeouts, all values are in milliseconds
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct YcqlTimeoutConfig {
+    pub connection_timeout_ms: u64,
+    pub idle_timeout_ms: Option<u64>,
+    pub max_lifetime_ms: Option<u64>,
+}
+
+/// How the driver authenticates against the cluster, selected with ***mode***
+#[derive(Clone, Default, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum YcqlAuthConfig {
+    /// Cluster started without ***--use_cassandra_authentication***
+    #[default]
+    None,
+    /// StaticPasswordAuthenticator with the credentials inline
+    Password { username: String, password: String },
+    /// StaticPasswordAuthenticator with the credentials read at startup from a TOML file holding ***username*** and ***password***
+    SecretsFile { path: PathBuf },
+    /// Authenticator registered under ***provider*** in the YcqlAuthenticatorRegistry, ***options*** is handed to it untouched
+    Custom {
+        provider: String,
+        #[serde(default)]
+        options: toml::value::Table,
+    },
+}
+
+// never print the password
+impl std::fmt::Debug for YcqlAuthConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            YcqlAuthConfig::None => f.write_str("None"),
+            YcqlAuthConfig::Password { username, .. } => f
+                .debug_struct("Password")
+                .field("username", username)
+                .field("password", &"***")
+                .finish(),
+            YcqlAuthConfig::SecretsFile { path } => f.debug_struct("SecretsFile").field("path", path).finish(),
+            YcqlAuthConfig::Custom { provider, .. } => f
+                .debug_struct("Custom")
+                .field("provider", provider)
+                .field("options", &"***")
+                .finish(),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: "127.0.0.1:4055".to_string(),
+        }
+    }
+}
+
+impl Default for PaginationConfig {
+    fn default() -> Self {
+        PaginationConfig {
+            default_page_size: 25,
+            max_page_size: 500,
+        }
+    }
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            max_payload_bytes: 1_048_576,
+            max_operations: 500,
+            max_concurrent_groups: 16,
+        }
+    }
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        JwtConfig {
+            issuer: "demo_ycql_service".to_string(),
+            audience: "demo_ycql_service_api".to_string(),
+            leeway_secs: 30,
+            // no usable default secret, validate() asks for one
+            key: JwtKeyConfig::Hs256 { secret: String::new() },
+        }
+    }
+}
+
+impl Default for AccessControlConfig {
+    fn default() -> Self {
+        AccessControlConfig { role_cache_ttl_secs: 60 }
+    }
+}
+
+impl Default for LoginConfig {
+    fn default() -> Self {
+        LoginConfig {
+            access_token_ttl_secs: 900,
+            refresh_token_ttl_secs: 1_209_600,
+        }
+    }
+}
+
+impl Default for YugabyteDbConfig {
+    fn default() -> Self {
+        YugabyteDbConfig {
+            contact_points: vec!["localhost:9042".to_string()],
+            keyspace: "demo_ycql_service_keyspace".to_string(),
+            replication_factor: 1,
+            pool: YcqlPoolConfig::default(),
+            timeouts: YcqlTimeoutConfig::default(),
+            auth: YcqlAuthConfig::None,
+        }
+    }
+}
+
+impl Default for YcqlPoolConfig {
+    fn default() -> Self {
+        YcqlPoolConfig {
+            max_size: 10,
+            min_idle: None,
+        }
+    }
+}
+
+impl Default for YcqlTimeoutConfig {
+    fn default() -> Self {
+        YcqlTimeoutConfig {
+            connection_timeout_ms: 30_000,
+            idle_timeout_ms: None,
+            max_lifetime_ms: None,
+        }
+    }
+}
+
+impl YcqlTimeoutConfig {
+    pub fn connection_timeout(&self) -> Duration {
+        Duration::from_millis(self.connection_timeout_ms)
+    }
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_ms.map(Duration::from_millis)
+    }
+    pub fn max_lifetime(&self) -> Option<Duration> {
+        self.max_lifetime_ms.map(Duration::from_millis)
+    }
+}
+
+impl AccessControlConfig {
+    pub fn role_cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.role_cache_ttl_secs)
+    }
+}
+
+impl ServiceConfig {
+    /// Load configuration in this order, each step overriding the previous one:
+    /// built-in defaults, the TOML file, `.env`, then the process environment.
+    pub fn load() -> Result<ServiceConfig, ConfigError> {
+        // .env only fills variables that are not already set in the process environment
+        dotenv::dotenv().ok();
+
+        let mut config = match env::var(CONFIG_PATH_ENV) {
+            Ok(path) => ServiceConfig::from_file(Path::new(&path))?,
+            Err(_) => {
+                let default_path = PathBuf::from(DEFAULT_CONFIG_PATH);
+                if default_path.exists() {
+                    ServiceConfig::from_file(&default_path)?
+                } else {
+                    ServiceConfig::default()
+                }
+            }
+        };
+        config.apply_overrides(|variable| env::var(variable).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<ServiceConfig, ConfigError> {
+        let raw = fs::read_to_string(path).map_err(|e| ConfigError::Unreadable {
+            path: path.display().to_string(),
+            reason: e.to_string(),
+        })?;
+        toml::from_str(&raw).map_err(|e| ConfigError::Malformed {
+            path: path.display().to_string(),
+            reason: e.to_string(),
+        })
+    }
+
+    /// Environment overrides, ***lookup*** is a parameter so the precedence rules can be exercised without touching the process environment
+    pub fn apply_overrides<F>(&mut self, lookup: F) -> Result<(), ConfigError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        if let Some(bind_address) = lookup("SERVICE_BIND_ADDRESS") {
+            self.server.bind_address = bind_address;
+        }
+        if let Some(value) = lookup("LIST_DEFAULT_PAGE_SIZE") {
+            self.pagination.default_page_size = parse_env("LIST_DEFAULT_PAGE_SIZE", &value)?;
+        }
+        if let Some(value) = lookup("LIST_MAX_PAGE_SIZE") {
+            self.pagination.max_page_size = parse_env("LIST_MAX_PAGE_SIZE", &value)?;
+        }
+        if let Some(node_id) = lookup("TIMEUUID_NODE_ID") {
+            self.time_uuid.node_id = Some(node_id);
+        }
+        if let Some(value) = lookup("USER_PROFILE_DEFAULT_TTL_SECS") {
+            self.retention.default_ttl_secs = parse_env("USER_PROFILE_DEFAULT_TTL_SECS", &value)?;
+        }
+        if let Some(value) = lookup("BATCH_MAX_PAYLOAD_BYTES") {
+            self.batch.max_payload_bytes = parse_env("BATCH_MAX_PAYLOAD_BYTES", &value)?;
+        }
+        if let Some(value) = lookup("BATCH_MAX_OPERATIONS") {
+            self.batch.max_operations = parse_env("BATCH_MAX_OPERATIONS", &value)?;
+        }
+        if let Some(value) = lookup("BATCH_MAX_CONCURRENT_GROUPS") {
+            self.batch.max_concurrent_groups = parse_env("BATCH_MAX_CONCURRENT_GROUPS", &value)?;
+        }
+
+        if let Some(issuer) = lookup("JWT_ISSUER") {
+            self.jwt.issuer = issuer;
+        }
+        if let Some(audience) = lookup("JWT_AUDIENCE") {
+            self.jwt.audience = audience;
+        }
+        if let Some(value) = lookup("JWT_LEEWAY_SECS") {
+            self.jwt.leeway_secs = parse_env("JWT_LEEWAY_SECS", &value)?;
+        }
+        if let Some(algorithm) = lookup("JWT_ALGORITHM") {
+            self.jwt.key = match algorithm.trim() {
+                "HS256" => JwtKeyConfig::Hs256 { secret: String::new() },
+                "RS256" => JwtKeyConfig::Rs256 { public_key_path: PathBuf::new(), private_key_path: None },
+                other => {
+                    return Err(ConfigError::InvalidEnvironment {
+                        variable: "JWT_ALGORITHM".to_string(),
+                        reason: format!("`{}` is not one of HS256, RS256", other),
+                    })
+                }
+            };
+        }
+        if let Some(value) = lookup("JWT_SECRET") {
+            match &mut self.jwt.key {
+                JwtKeyConfig::Hs256 { secret } => *secret = value,
+                _ => {
+                    return Err(ConfigError::InvalidEnvironment {
+                        variable: "JWT_SECRET".to_string(),
+                        reason: "only applies to the HS256 algorithm".to_string(),
+                    })
+                }
+            }
+        }
+        if let Some(value) = lookup("ROLE_CACHE_TTL_SECS") {
+            self.access_control.role_cache_ttl_secs = parse_env("ROLE_CACHE_TTL_SECS", &value)?;
+        }
+        if let Some(value) = lookup("JWT_PUBLIC_KEY_PATH") {
+            match &mut self.jwt.key {
+                JwtKeyConfig::Rs256 { public_key_path, .. } => *public_key_path = PathBuf::from(value),
+                _ => {
+                    return Err(ConfigError::InvalidEnvironment {
+                        variable: "JWT_PUBLIC_KEY_PATH".to_string(),
+                        reason: "only applies to the RS256 algorithm, set JWT_ALGORITHM=RS256".to_string(),
+                    })
+                }
+            }
+        }
+        if let Some(value) = lookup("JWT_PRIVATE_KEY_PATH") {
+            match &mut self.jwt.key {
+                JwtKeyConfig::Rs256 { private_key_path, .. } => *private_key_path = Some(PathBuf::from(value)),
+                _ => {
+                    return Err(ConfigError::InvalidEnvironment {
+                        variable: "JWT_PRIVATE_KEY_PATH".to_string(),
+                        reason: "only applies to the RS256 algorithm, HS256 signs with JWT_SECRET".to_string(),
+                    })
+                }
+            }
+        }
+        if let Some(value) = lookup("LOGIN_ACCESS_TOKEN_TTL_SECS") {
+            self.login.access_token_ttl_secs = parse_env("LOGIN_ACCESS_TOKEN_TTL_SECS", &value)?;
+        }
+        if let Some(value) = lookup("LOGIN_REFRESH_TOKEN_TTL_SECS") {
+            self.login.refresh_token_ttl_secs = parse_env("LOGIN_REFRESH_TOKEN_TTL_SECS", &value)?;
+        }
+
+        let yugabyte = &mut self.yugabyte;
+        if let Some(contact_points) = lookup("YCQL_CONTACT_POINTS") {
+            yugabyte.contact_points = contact_points
+                .split(',')
+                .map(|point| point.trim().to_string())
+                .filter(|point| !point.is_empty())
+                .collect();
+        }
+        if let Some(keyspace) = lookup("YCQL_KEYSPACE") {
+            yugabyte.keyspace = keyspace;
+        }
+        if let Some(value) = lookup("YCQL_REPLICATION_FACTOR") {
+            yugabyte.replication_factor = parse_env("YCQL_REPLICATION_FACTOR", &value)?;
+        }
+        if let Some(value) = lookup("YCQL_POOL_MAX_SIZE") {
+            yugabyte.pool.max_size = parse_env("YCQL_POOL_MAX_SIZE", &value)?;
+        }
+        if let Some(value) = lookup("YCQL_POOL_MIN_IDLE") {
+            yugabyte.pool.min_idle = Some(parse_env("YCQL_POOL_MIN_IDLE", &value)?);
+        }
+        if let Some(value) = lookup("YCQL_CONNECTION_TIMEOUT_MS") {
+            yugabyte.timeouts.connection_timeout_ms = parse_env("YCQL_CONNECTION_TIMEOUT_MS", &value)?;
+        }
+        if let Some(value) = lookup("YCQL_IDLE_TIMEOUT_MS") {
+            yugabyte.timeouts.idle_timeout_ms = Some(parse_env("YCQL_IDLE_TIMEOUT_MS", &value)?);
+        }
+        if let Some(value) = lookup("YCQL_MAX_LIFETIME_MS") {
+            yugabyte.timeouts.max_lifetime_ms = Some(parse_env("YCQL_MAX_LIFETIME_MS", &value)?);
+        }
+
+        if let Some(mode) = lookup("YCQL_AUTH_MODE") {
+            yugabyte.auth = match mode.trim() {
+                "none" => YcqlAuthConfig::None,
+                "password" => YcqlAuthConfig::Password { username: String::new(), password: String::new() },
+                "secrets_file" => YcqlAuthConfig::SecretsFile { path: PathBuf::new() },
+                "custom" => {
+                    return Err(ConfigError::InvalidEnvironment {
+                        variable: "YCQL_AUTH_MODE".to_string(),
+                        reason: "custom authenticators need provider options, configure them in the TOML file".to_string(),
+                    })
+                }
+                other => {
+                    return Err(ConfigError::InvalidEnvironment {
+                        variable: "YCQL_AUTH_MODE".to_string(),
+                        reason: format!("`{}` is not one of none, password, secrets_file", other),
+                    })
+                }
+            };
+        }
+
+        // credentials from the environment imply password mode unless a mode was chosen explicitly
+        let username_override = lookup("YCQL_USERNAME");
+        let password_override = lookup("YCQL_PASSWORD");
+        if username_override.is_some() || password_override.is_some() {
+            if let YcqlAuthConfig::None = yugabyte.auth {
+                yugabyte.auth = YcqlAuthConfig::Password { username: String::new(), password: String::new() };
+            }
+            match &mut yugabyte.auth {
+                YcqlAuthConfig::Password { username, password } => {
+                    if let Some(value) = username_override {
+                        *username = value;
+                    }
+                    if let Some(value) = password_override {
+                        *password = value;
+                    }
+                }
+                _ => {
+                    return Err(ConfigError::InvalidEnvironment {
+                        variable: "YCQL_USERNAME".to_string(),
+                        reason: "YCQL_USERNAME and YCQL_PASSWORD only apply to the password auth mode".to_string(),
+                    })
+                }
+            }
+        }
+        if let Some(path) = lookup("YCQL_SECRETS_FILE") {
+            match &mut yugabyte.auth {
+                YcqlAuthConfig::SecretsFile { path: secrets_path } => *secrets_path = PathBuf::from(path),
+                YcqlAuthConfig::None => yugabyte.auth = YcqlAuthConfig::SecretsFile { path: PathBuf::from(path) },
+                _ => {
+                    return Err(ConfigError::InvalidEnvironment {
+                        variable: "YCQL_SECRETS_FILE".to_string(),
+                        reason: "only applies to the secrets_file auth mode".to_string(),
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Collects every problem instead of stopping at the first one
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        // HttpServer::bind resolves host names, so localhost:4055 is as good as 127.0.0.1:4055
+        if !is_valid_host_port(&self.server.bind_address) {
+            problems.push(format!("server.bind_address `{}` is not a valid host:port address", self.server.bind_address));
+        }
+
+        if self.pagination.default_page_size < 1 {
+            problems.push("pagination.default_page_size must be at least 1".to_string());
+        }
+        if self.pagination.max_page_size < self.pagination.default_page_size {
+            problems.push(format!(
+                "pagination.max_page_size ({}) cannot be smaller than pagination.default_page_size ({})",
+                self.pagination.max_page_size, self.pagination.default_page_size
+            ));
+        }
+
+        if let Some(node_id) = &self.time_uuid.node_id {
+            if parse_node_id(node_id).is_none() {
+                problems.push(format!("time_uuid.node_id `{}` must be 12 hex digits, eg 02:42:ac:11:00:02", node_id));
+            }
+        }
+
+        if !(0..=MAX_TTL_SECS).contains(&self.retention.default_ttl_secs) {
+            problems.push(format!(
+                "retention.default_ttl_secs ({}) must be between 0 (never expire) and {}",
+                self.retention.default_ttl_secs, MAX_TTL_SECS
+            ));
+        }
+
+        if self.batch.max_payload_bytes == 0 {
+            problems.push("batch.max_payload_bytes must be at least 1".to_string());
+        }
+        if self.batch.max_operations == 0 {
+            problems.push("batch.max_operations must be at least 1".to_string());
+        }
+        if self.batch.max_concurrent_groups == 0 {
+            problems.push("batch.max_concurrent_groups must be at least 1".to_string());
+        }
+
+        if self.jwt.issuer.trim().is_empty() {
+            problems.push("jwt.issuer cannot be empty".to_string());
+        }
+        if self.jwt.audience.trim().is_empty() {
+            problems.push("jwt.audience cannot be empty".to_string());
+        }
+        match &self.jwt.key {
+            JwtKeyConfig::Hs256 { secret } => {
+                if secret.len() < 32 {
+                    problems.push("jwt.key.secret must be at least 32 bytes for HS256, set it in the TOML file or JWT_SECRET".to_string());
+                }
+            }
+            JwtKeyConfig::Rs256 { public_key_path, private_key_path } => {
+                if public_key_path.as_os_str().is_empty() {
+                    problems.push("jwt.key.public_key_path must point at the issuer's PEM public key for RS256".to_string());
+                }
+                if private_key_path.as_ref().is_some_and(|path| path.as_os_str().is_empty()) {
+                    problems.push("jwt.key.private_key_path cannot be empty when set, leave it out to disable the login routes".to_string());
+                }
+            }
+        }
+        if self.login.access_token_ttl_secs == 0 {
+            problems.push("login.access_token_ttl_secs must be at least 1".to_string());
+        }
+        if !(1..=MAX_TTL_SECS).contains(&self.login.refresh_token_ttl_secs) {
+            problems.push(format!(
+                "login.refresh_token_ttl_secs ({}) must be between 1 and {}",
+                self.login.refresh_token_ttl_secs, MAX_TTL_SECS
+            ));
+        }
+
+        let yugabyte = &self.yugabyte;
+        if yugabyte.contact_points.is_empty() {
+            problems.push("yugabyte.contact_points must list at least one host:port".to_string());
+        }
+        for point in &yugabyte.contact_points {
+            if !is_valid_host_port(point) {
+                problems.push(format!("yugabyte.contact_points entry `{}` is not a valid host:port", point));
+            }
+        }
+        if !is_valid_keyspace_name(&yugabyte.keyspace) {
+            problems.push(format!(
+                "yugabyte.keyspace `{}` must start with a letter and contain only letters, digits or underscores (max 48 characters)",
+                yugabyte.keyspace
+            ));
+        }
+        if yugabyte.replication_factor == 0 {
+            problems.push("yugabyte.replication_factor must be at least 1".to_string());
+        }
+        if yugabyte.pool.max_size == 0 {
+            problems.push("yugabyte.pool.max_size must be at least 1".to_string());
+        }
+        if let Some(min_idle) = yugabyte.pool.min_idle {
+            if min_idle > yugabyte.pool.max_size {
+                problems.push(format!(
+                    "yugabyte.pool.min_idle ({}) cannot be greater than yugabyte.pool.max_size ({})",
+                    min_idle, yugabyte.pool.max_size
+                ));
+            }
+        }
+        if yugabyte.timeouts.connection_timeout_ms == 0 {
+            problems.push("yugabyte.timeouts.connection_timeout_ms must be greater than 0".to_string());
+        }
+        if yugabyte.timeouts.idle_timeout_ms == Some(0) {
+            problems.push("yugabyte.timeouts.idle_timeout_ms must be greater than 0 when set".to_string());
+        }
+        if yugabyte.timeouts.max_lifetime_ms == Some(0) {
+            problems.push("yugabyte.timeouts.max_lifetime_ms must be greater than 0 when set".to_string());
+        }
+        match &yugabyte.auth {
+            YcqlAuthConfig::None => {}
+            YcqlAuthConfig::Password { username, password } => {
+                if username.is_empty() {
+                    problems.push("yugabyte.auth.username cannot be empty in password mode".to_string());
+                }
+                if password.is_empty() {
+                    problems.push("yugabyte.auth.password cannot be empty in password mode".to_string());
+                }
+            }
+            YcqlAuthConfig::SecretsFile { path } => {
+                if path.as_os_str().is_empty() {
+                    problems.push("yugabyte.auth.path must point at the secrets file in secrets_file mode".to_string());
+                }
+            }
+            YcqlAuthConfig::Custom { provider, .. } => {
+                if provider.is_empty() {
+                    problems.push("yugabyte.auth.provider cannot be empty in custom mode".to_string());
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+}
+
+fn parse_env<T>(variable: &str, value: &str) -> Result<T, ConfigError>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    value.trim().parse::<T>().map_err(|e| ConfigError::InvalidEnvironment {
+        variable: variable.to_string(),
+        reason: format!("`{}`: {}", value, e),
+    })
+}
+
+/// ***host:port*** with a host name or an address, ***[::1]*** style for IPv6, and a port other than 0
+fn is_valid_host_port(address: &str) -> bool {
+    match address.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().map(|port| port != 0).unwrap_or(false),
+        None => false,
+    }
+}
+
+/// YCQL keyspace names are used unquoted in statements, so keep them to plain identifiers
+fn is_valid_keyspace_name(keyspace: &str) -> bool {
+    let mut chars = keyspace.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() => {}
+        _ => return false,
+    }
+    keyspace.len() <= 48 && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn tempfile_with(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("{}-{}", uuid::Uuid::new_v4(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn variables(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn each_layer_overrides_the_previous_one() {
+        let toml_path = tempfile_with(
+            "ycql_service.toml",
+            "[server]\nbind_address = \"0.0.0.0:8080\"\n[pagination]\ndefault_page_size = 10\nmax_page_size = 100\n",
+        );
+        let mut config = ServiceConfig::from_file(&toml_path).unwrap();
+        let dotenv = variables(&[("LIST_DEFAULT_PAGE_SIZE", "20"), ("YCQL_KEYSPACE", "from_dotenv")]);
+        let environment = variables(&[("LIST_DEFAULT_PAGE_SIZE", "30")]);
+        // dotenv never replaces a variable the process already has, so the environment is looked up first
+        config
+            .apply_overrides(|variable| environment.get(variable).or_else(|| dotenv.get(variable)).cloned())
+            .unwrap();
+        fs::remove_file(&toml_path).ok();
+
+        assert_eq!(config.batch.max_operations, BatchConfig::default().max_operations, "default");
+        assert_eq!(config.server.bind_address, "0.0.0.0:8080", "TOML over the default");
+        assert_eq!(config.pagination.max_page_size, 100, "TOML over the default");
+        assert_eq!(config.yugabyte.keyspace, "from_dotenv", ".env over the default");
+        assert_eq!(config.pagination.default_page_size, 30, "environment over .env over TOML");
+    }
+
+    #[test]
+    fn unparsable_overrides_name_their_variable() {
+        let mut config = ServiceConfig::default();
+        match config.apply_overrides(|variable| (variable == "BATCH_MAX_OPERATIONS").then(|| "many".to_string())) {
+            Err(ConfigError::InvalidEnvironment { variable, .. }) => assert_eq!(variable, "BATCH_MAX_OPERATIONS"),
+            other => panic!("expected an InvalidEnvironment error, got {:?}", other),
+        }
+        assert!(matches!(ServiceConfig::from_file(Path::new("/nonexistent/ycql_service.toml")), Err(ConfigError::Unreadable { .. })));
+    }
+
+    #[test]
+    fn validate_reports_every_problem_at_once() {
+        let mut config = ServiceConfig::default();
+        config.jwt.key = JwtKeyConfig::Hs256 { secret: "0123456789abcdef0123456789abcdef".to_string() };
+        assert!(config.validate().is_ok());
+
+        config.server.bind_address = "localhost".to_string();
+        config.pagination.max_page_size = 5;
+        config.yugabyte.contact_points = vec!["localhost:0".to_string()];
+        config.yugabyte.keyspace = "1keyspace".to_string();
+        match config.validate() {
+            Err(ConfigError::Invalid(problems)) => {
+                assert_eq!(problems.len(), 4, "{:?}", problems);
+                assert!(problems[0].starts_with("server.bind_address"));
+                assert!(problems[1].starts_with("pagination.max_page_size"));
+                assert!(problems[2].starts_with("yugabyte.contact_points"));
+                assert!(problems[3].starts_with("yugabyte.keyspace"));
+            }
+            other => panic!("expected ConfigError::Invalid, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bind_address_takes_host_names_and_addresses() {
+        let mut config = ServiceConfig::default();
+        config.jwt.key = JwtKeyConfig::Hs256 { secret: "0123456789abcdef0123456789abcdef".to_string() };
+        for bind_address in ["localhost:4055", "0.0.0.0:8080", "[::1]:4055"] {
+            config.server.bind_address = bind_address.to_string();
+            assert!(config.validate().is_ok(), "{}", bind_address);
+        }
+        for bind_address in ["localhost", ":4055", "localhost:http", "localhost:0"] {
+            config.server.bind_address = bind_address.to_string();
+            assert!(config.validate().is_err(), "{}", bind_address);
+        }
+    }
+}
@@ -1,4 +1,3 @@
-use cdrs_tokio::authenticators::NoneAuthenticator;
 use cdrs_tokio::cluster::session::{new as new_session, Session};
 use cdrs_tokio::cluster::{ClusterTcpConfig, NodeTcpConfigBuilder, TcpConnectionPool};
 use cdrs_tokio::load_balancing::RoundRobin;
@@ -6,45 +5,80 @@ use cdrs_tokio::query::*;
 use cdrs_tokio::types::prelude::*;
 use cdrs_tokio_helpers_derive::*;
 use cdrs_tokio::Result as YugabyteDBResult;
+use crate::ycql_authenticators::SharedYcqlAuthenticator;
+use crate::ycql_prepared_statements::{BatchStatement, UserProfileStatement, YcqlStatementRegistry};
+use cdrs_tokio::frame::Frame;
+use cdrs_tokio::frame::frame_batch::BatchType;
+use crate::ycql_service_config::YugabyteDbConfig;
+use std::ops::Deref;
 use std::sync::Arc;
 use std::result::Result;
 use std::error;
-use std::sync::Mutex;
 
-pub type CurrentYcqlDbSession = Session<RoundRobin<TcpConnectionPool>>;
+pub type YcqlDbClusterSession = Session<RoundRobin<TcpConnectionPool>>;
 
-/// Create DB session NOTE: ***in production consider using ***StaticPasswordAuthenticator**** instead of NoneAuthenticator
-pub async fn configure_yugabyte_db_session() -> YugabyteDBResult<CurrentYcqlDbSession>{
-    let node = NodeTcpConfigBuilder::new("localhost:9042", Arc::new(NoneAuthenticator {})).build();
-    let cluster_config = ClusterTcpConfig(vec![node]);
-    let newsession = new_session(&cluster_config, RoundRobin::new()).await?;
-    Ok(newsession)
-}
-/// Create demo_ycql_service_keyspace or ***DATABASE*** if you are coming from a NoSQL world
-pub async fn create_demo_ycql_service_keyspace(yugabyte_ycql_session: &mut CurrentYcqlDbSession) ->YugabyteDBResult<()> {
-    let demo_ycql_service_keyspace: &'static str = "CREATE KEYSPACE IF NOT EXISTS demo_ycql_service_keyspace WITH REPLICATION = { \
-        'class' : 'SimpleStrategy', 'replication_factor' : 1 };";
-        yugabyte_ycql_session.query(demo_ycql_service_keyspace).await?; // ***Propagate error to the calling function
-    Ok(())    
-}
-/// Create demo_ycql_user_profile_table
-pub async fn create_demo_ycql_user_profile_table(yugabyte_ycql_session: &mut CurrentYcqlDbSession) ->YugabyteDBResult<()> {
-        let create_user_profile: &'static str = r#"
-        CREATE TABLE IF NOT EXISTS demo_ycql_service_keyspace.user_profile(
-            user_id UUID,
-            time_uuid_order TIMEUUID,
-            email_address TEXT,
-            full_name TEXT,
-            age TINYINT,
-            comment TEXT,
-
-            PRIMARY KEY ((user_id), time_uuid_order))
-            WITH transactions = { 'enabled' : true };
-            "#;
-            yugabyte_ycql_session.query(create_user_profile).await?; //***Propagate error to the calling function
-    Ok(())  
+/// Cloneable, lock-free handle to the DB session together with the keyspace every query should be qualified with.
+/// The driver session multiplexes requests over its per node connection pools, so handlers share it without a Mutex.
+#[derive(Clone)]
+pub struct CurrentYcqlDbSession {
+    session: Arc<YcqlDbClusterSession>,
+    keyspace: Arc<str>,
+    statements: Arc<YcqlStatementRegistry>,
 }
 
+impl CurrentYcqlDbSession {
+    /// Keyspace from configuration, already validated as a plain identifier so it is safe to format into CQL
+    pub fn keyspace(&self) -> &str {
+        &self.keyspace
+    }
 
+    /// Prepare every registry statement, call it once the migrations are up to date so every table exists
+    pub async fn prepare_user_profile_statements(&self) -> YugabyteDBResult<()> {
+        self.statements.prepare_all(&self.session).await
+    }
 
+    /// Run a registry statement by prepared id
+    pub async fn execute_statement(&self, statement: UserProfileStatement, values: QueryValues) -> YugabyteDBResult<Frame> {
+        self.statements.execute(&self.session, statement, values).await
+    }
+
+    /// Run user_profile statements as one logged or unlogged batch, registry statements by prepared id
+    pub async fn execute_statement_batch(&self, batch_type: BatchType, statements: Vec<(BatchStatement, QueryValues)>) -> YugabyteDBResult<Frame> {
+        self.statements.execute_batch(&self.session, batch_type, statements).await
+    }
+
+    /// Run a registry statement by prepared id with explicit query params, eg page size and paging state
+    pub async fn execute_statement_with_params(&self, statement: UserProfileStatement, query_params: QueryParams) -> YugabyteDBResult<Frame> {
+        self.statements.execute_with_params(&self.session, statement, query_params).await
+    }
+}
 
+impl Deref for CurrentYcqlDbSession {
+    type Target = YcqlDbClusterSession;
+
+    fn deref(&self) -> &Self::Target {
+        &self.session
+    }
+}
+
+/// Create DB session with one connection pool per configured contact point,
+/// ***authenticator*** comes from YcqlAuthenticatorRegistry::build_authenticator so the same binary works against dev and secured clusters
+pub async fn configure_yugabyte_db_session(yugabyte_config: &YugabyteDbConfig, authenticator: SharedYcqlAuthenticator) -> YugabyteDBResult<CurrentYcqlDbSession>{
+    let nodes = yugabyte_config.contact_points.iter().map(|contact_point| {
+        NodeTcpConfigBuilder::new(contact_point, authenticator.clone())
+            .max_size(yugabyte_config.pool.max_size)
+            .min_idle(yugabyte_config.pool.min_idle)
+            .connection_timeout(yugabyte_config.timeouts.connection_timeout())
+            .idle_timeout(yugabyte_config.timeouts.idle_timeout())
+            .max_lifetime(yugabyte_config.timeouts.max_lifetime())
+            .build()
+    }).collect();
+    let cluster_config = ClusterTcpConfig(nodes);
+    let newsession = new_session(&cluster_config, RoundRobin::new()).await?;
+    let keyspace: Arc<str> = Arc::from(yugabyte_config.keyspace.as_str());
+    Ok(CurrentYcqlDbSession {
+        session: Arc::new(newsession),
+        statements: Arc::new(YcqlStatementRegistry::new(keyspace.clone())),
+        keyspace,
+    })
+}